@@ -0,0 +1,573 @@
+//! Admin UI for events of the `local` event source.
+//!
+//! Users log in with a password, whose Argon2 hash is configured in `admin.users`. Sessions are
+//! kept in memory and identified by a random token in a cookie, so a restart logs out everybody.
+//! Every form carries the CSRF token of its session, which is checked on submission.
+//!
+//! Login attempts are limited per client IP and per user name, and only a few passwords are
+//! verified at the same time, as every verification takes a while and needs 19 MiB of memory.
+
+use crate::calendar::PublishState;
+use crate::calendar::local::LocalEvent;
+use crate::contact::RateLimiter;
+use crate::{AdminConfig, Error, Result};
+use argon2::password_hash::{PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Argon2, PasswordHash};
+use jiff::civil::{DateTime, Time};
+use jiff::tz::TimeZone;
+use jiff::{SignedDuration, Timestamp};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{LazyLock, Mutex};
+use tokio::sync::Semaphore;
+
+/// Path of the admin UI.
+pub const PATH: &str = "/admin";
+
+/// Name of the cookie holding the session token.
+pub const SESSION_COOKIE: &str = "wz_admin_session";
+
+/// Format of `datetime-local` inputs.
+const DATETIME_FORMAT: &str = "%Y-%m-%dT%H:%M";
+
+const MAX_TITLE_LEN: usize = 200;
+const MAX_FIELD_LEN: usize = 500;
+const MAX_DESCRIPTION_LEN: usize = 20_000;
+
+/// Maximum number of login attempts per client IP and of failed logins per user name within
+/// `LOGIN_WINDOW`.
+const MAX_LOGIN_ATTEMPTS: u32 = 10;
+const LOGIN_WINDOW: SignedDuration = SignedDuration::from_mins(15);
+
+/// Maximum number of passwords verified at the same time.
+const MAX_CONCURRENT_VERIFICATIONS: usize = 2;
+
+/// Hash of a random password, which is verified for unknown users, so that the response time
+/// doesn't reveal which users exist.
+static DUMMY_HASH: LazyLock<String> = LazyLock::new(|| {
+    let mut password = [0; 32];
+    getrandom::fill(&mut password).expect("failed to generate dummy password");
+    hash_password(&hex::encode(password)).expect("failed to hash dummy password")
+});
+
+/// Hashes a password with Argon2 for `admin.users`.
+pub fn hash_password(password: &str) -> Result<String> {
+    let mut salt = [0; 16];
+    getrandom::fill(&mut salt)
+        .map_err(|err| Error::Admin(format!("failed to generate salt: {err}")))?;
+    let salt = SaltString::encode_b64(&salt)
+        .map_err(|err| Error::Admin(format!("failed to encode salt: {err}")))?;
+
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|err| Error::Admin(format!("failed to hash password: {err}")))
+}
+
+/// Generates a random token for sessions, CSRF protection and event IDs.
+fn random_token(len: usize) -> Result<String> {
+    let mut bytes = vec![0; len];
+    getrandom::fill(&mut bytes)
+        .map_err(|err| Error::Admin(format!("failed to generate token: {err}")))?;
+    Ok(hex::encode(bytes))
+}
+
+/// Generates the ID of a new event.
+pub fn new_event_id() -> Result<String> {
+    random_token(8)
+}
+
+/// A login session.
+#[derive(Debug, Clone)]
+pub struct Session {
+    /// The name of the logged in user.
+    pub user: String,
+    /// Token which has to be submitted with every form.
+    pub csrf_token: String,
+    expires_at: Timestamp,
+}
+
+/// Authentication and sessions of the admin UI.
+pub struct Admin {
+    users: HashMap<String, String>,
+    session_ttl: SignedDuration,
+    sessions: Mutex<HashMap<String, Session>>,
+    login_attempts: Mutex<RateLimiter>,
+    verifications: Semaphore,
+}
+
+impl Admin {
+    /// Creates a new `Admin` from configuration.
+    pub fn new(config: &AdminConfig) -> Admin {
+        Admin {
+            users: config.users.clone(),
+            session_ttl: SignedDuration::from_hours(config.session_ttl_hours as i64),
+            sessions: Mutex::new(HashMap::new()),
+            login_attempts: Mutex::new(RateLimiter::default()),
+            verifications: Semaphore::new(MAX_CONCURRENT_VERIFICATIONS),
+        }
+    }
+
+    /// Records a login attempt of `user` from the client with the IP address `ip`. Returns
+    /// whether it is within the limit of the client and `user` didn't fail to log in too often.
+    ///
+    /// Only failed logins count against the user, so that attempts which were rejected anyway
+    /// can't lock out a user.
+    pub fn allow_login(&self, ip: &str, user: &str, now: Timestamp) -> bool {
+        let mut attempts = self
+            .login_attempts
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+
+        attempts.allow(&format!("ip:{ip}"), MAX_LOGIN_ATTEMPTS, LOGIN_WINDOW, now)
+            && !attempts.is_limited(
+                &format!("user:{user}"),
+                MAX_LOGIN_ATTEMPTS,
+                LOGIN_WINDOW,
+                now,
+            )
+    }
+
+    /// Records a failed login of `user`.
+    fn record_failed_login(&self, user: &str, now: Timestamp) {
+        self.login_attempts
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .allow(
+                &format!("user:{user}"),
+                MAX_LOGIN_ATTEMPTS,
+                LOGIN_WINDOW,
+                now,
+            );
+    }
+
+    /// Checks the password of a user. Returns the token of a new session if it is correct.
+    pub async fn login(
+        &self,
+        user: &str,
+        password: &str,
+        now: Timestamp,
+    ) -> Result<Option<String>> {
+        let known = self.users.contains_key(user);
+        let hash = match self.users.get(user) {
+            Some(hash) => hash.clone(),
+            None => DUMMY_HASH.clone(),
+        };
+        let password = password.to_string();
+
+        let _permit = self
+            .verifications
+            .acquire()
+            .await
+            .map_err(|err| Error::Admin(format!("password verification failed: {err}")))?;
+
+        // Argon2 is slow on purpose, so keep it off the async workers.
+        let valid = tokio::task::spawn_blocking(move || {
+            PasswordHash::new(&hash).is_ok_and(|hash| {
+                Argon2::default()
+                    .verify_password(password.as_bytes(), &hash)
+                    .is_ok()
+            })
+        })
+        .await
+        .map_err(|err| Error::Admin(format!("password verification failed: {err}")))?;
+
+        if !(known && valid) {
+            self.record_failed_login(user, now);
+            return Ok(None);
+        }
+
+        let token = random_token(32)?;
+        let session = Session {
+            user: user.into(),
+            csrf_token: random_token(32)?,
+            expires_at: now + self.session_ttl,
+        };
+
+        let mut sessions = self.sessions.lock().unwrap_or_else(|err| err.into_inner());
+        sessions.retain(|_, session| session.expires_at > now);
+        sessions.insert(token.clone(), session);
+
+        Ok(Some(token))
+    }
+
+    /// Returns the session with the given token, unless it expired.
+    pub fn session(&self, token: &str, now: Timestamp) -> Option<Session> {
+        let sessions = self.sessions.lock().unwrap_or_else(|err| err.into_inner());
+        sessions
+            .get(token)
+            .filter(|session| session.expires_at > now)
+            .cloned()
+    }
+
+    /// Ends the session with the given token.
+    pub fn logout(&self, token: &str) {
+        let mut sessions = self.sessions.lock().unwrap_or_else(|err| err.into_inner());
+        sessions.remove(token);
+    }
+
+    /// Returns the lifetime of sessions.
+    pub fn session_ttl(&self) -> SignedDuration {
+        self.session_ttl
+    }
+}
+
+/// The submitted login form.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct LoginForm {
+    pub user: String,
+    pub password: String,
+}
+
+/// The submitted event form. All fields are kept as entered, so that the form can be shown again
+/// if it is invalid.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct EventForm {
+    pub title: String,
+    /// Start of the event, as entered into a `datetime-local` input.
+    pub start: String,
+    /// End of the event, as entered into a `datetime-local` input.
+    pub end: String,
+    /// Time when doors open, as entered into a time input (`HH:MM`).
+    pub doors: String,
+    pub price: String,
+    pub ticket_url: String,
+    /// Comma-separated tags.
+    pub tags: String,
+    pub image: String,
+    /// Description as markdown.
+    pub description: String,
+    /// `draft`, or `published` (the default).
+    pub publish_state: String,
+    /// Time of the official announcement, as entered into a `datetime-local` input.
+    pub publish_at: String,
+    #[serde(skip_serializing)]
+    pub csrf_token: String,
+    /// The submit button which was used, `preview` to only show a preview.
+    #[serde(skip_serializing)]
+    pub action: String,
+}
+
+/// Problems with a submitted form, keyed by field name.
+pub type ValidationErrors = BTreeMap<&'static str, &'static str>;
+
+/// Trims a single line input. Returns `None` if it contains control characters or is too long.
+fn single_line(value: &str, max_len: usize) -> Option<&str> {
+    let value = value.trim();
+    (!value.chars().any(char::is_control) && value.chars().count() <= max_len).then_some(value)
+}
+
+/// Returns whether `value` is an absolute http(s) URL.
+fn is_http_url(value: &str) -> bool {
+    url::Url::parse(value).is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+}
+
+impl EventForm {
+    /// Creates a form with the values of an existing event.
+    pub fn from_event(event: &LocalEvent) -> EventForm {
+        let datetime = |date: Timestamp| {
+            date.to_zoned(TimeZone::system())
+                .strftime(DATETIME_FORMAT)
+                .to_string()
+        };
+
+        EventForm {
+            title: event.title.clone(),
+            start: datetime(event.start_date),
+            end: event.end_date.map(datetime).unwrap_or_default(),
+            doors: event
+                .doors
+                .map(|doors| doors.strftime("%H:%M").to_string())
+                .unwrap_or_default(),
+            price: event.price.clone().unwrap_or_default(),
+            ticket_url: event.ticket_url.clone().unwrap_or_default(),
+            tags: event.tags.join(", "),
+            image: event.image.clone().unwrap_or_default(),
+            description: event.description.clone(),
+            publish_state: match event.publish_state {
+                PublishState::Published => "published".into(),
+                PublishState::Draft => "draft".into(),
+            },
+            publish_at: event.publish_at.map(datetime).unwrap_or_default(),
+            csrf_token: String::new(),
+            action: String::new(),
+        }
+    }
+
+    /// Returns whether only a preview was requested.
+    pub fn is_preview(&self) -> bool {
+        self.action == "preview"
+    }
+
+    /// Validates the form. Dates are in the system time zone. The event gets the given `id`, and
+    /// `user` and `now` are recorded as the last change.
+    pub fn validate(
+        &self,
+        id: &str,
+        user: &str,
+        now: Timestamp,
+    ) -> Result<LocalEvent, ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+
+        let title = single_line(&self.title, MAX_TITLE_LEN).unwrap_or_default();
+        if title.is_empty() {
+            errors.insert("title", "Bitte gib einen Titel an.");
+        }
+
+        let timestamp = |value: &str| {
+            DateTime::strptime(DATETIME_FORMAT, value.trim())
+                .ok()
+                .and_then(|datetime| datetime.to_zoned(TimeZone::system()).ok())
+                .map(|zoned| zoned.timestamp())
+        };
+
+        let start_date = timestamp(&self.start);
+        if start_date.is_none() {
+            errors.insert("start", "Bitte gib Datum und Uhrzeit des Beginns an.");
+        }
+
+        let end_date = match self.end.trim() {
+            "" => None,
+            end => match (timestamp(end), start_date) {
+                (Some(end), Some(start)) if end <= start => {
+                    errors.insert("end", "Das Ende muss nach dem Beginn liegen.");
+                    None
+                }
+                (Some(end), _) => Some(end),
+                (None, _) => {
+                    errors.insert("end", "Bitte gib ein gültiges Datum an.");
+                    None
+                }
+            },
+        };
+
+        let doors = match self.doors.trim() {
+            "" => None,
+            doors => match Time::strptime("%H:%M", doors) {
+                Ok(doors) => Some(doors),
+                Err(_) => {
+                    errors.insert("doors", "Bitte gib eine Uhrzeit wie 19:30 an.");
+                    None
+                }
+            },
+        };
+
+        let price = single_line(&self.price, MAX_FIELD_LEN);
+        if price.is_none() {
+            errors.insert("price", "Bitte gib den Eintritt in einer Zeile an.");
+        }
+
+        let ticket_url = single_line(&self.ticket_url, MAX_FIELD_LEN);
+        if ticket_url.is_none_or(|url| !url.is_empty() && !is_http_url(url)) {
+            errors.insert("ticket_url", "Bitte gib eine Adresse mit https:// an.");
+        }
+
+        let image = single_line(&self.image, MAX_FIELD_LEN);
+        if image
+            .is_none_or(|image| !image.is_empty() && !image.starts_with('/') && !is_http_url(image))
+        {
+            errors.insert(
+                "image",
+                "Bitte gib eine Adresse mit https:// oder einen Pfad wie /static/… an.",
+            );
+        }
+
+        let mut tags: Vec<String> = Vec::new();
+        for tag in self.tags.split(',').map(str::trim) {
+            if !tag.is_empty() && !tags.iter().any(|other| other == tag) {
+                tags.push(tag.into());
+            }
+        }
+        if single_line(&self.tags, MAX_FIELD_LEN).is_none() {
+            errors.insert("tags", "Bitte gib die Tags durch Kommas getrennt an.");
+        }
+
+        let description = self.description.trim();
+        if description.chars().count() > MAX_DESCRIPTION_LEN {
+            errors.insert(
+                "description",
+                "Die Beschreibung darf höchstens 20000 Zeichen lang sein.",
+            );
+        }
+
+        let publish_state = match self.publish_state.as_str() {
+            "" | "published" => PublishState::Published,
+            "draft" => PublishState::Draft,
+            _ => {
+                errors.insert(
+                    "publish_state",
+                    "Bitte wähle aus, ob der Termin sichtbar ist.",
+                );
+                PublishState::Draft
+            }
+        };
+
+        let publish_at = match self.publish_at.trim() {
+            "" => None,
+            publish_at => {
+                let publish_at = timestamp(publish_at);
+                if publish_at.is_none() {
+                    errors.insert("publish_at", "Bitte gib ein gültiges Datum an.");
+                }
+                publish_at
+            }
+        };
+
+        let optional =
+            |value: Option<&str>| value.filter(|value| !value.is_empty()).map(String::from);
+
+        match start_date {
+            Some(start_date) if errors.is_empty() => Ok(LocalEvent {
+                id: id.into(),
+                start_date,
+                end_date,
+                title: title.into(),
+                description: description.into(),
+                price: optional(price),
+                doors,
+                ticket_url: optional(ticket_url),
+                tags,
+                image: optional(image),
+                updated_at: now,
+                updated_by: user.into(),
+                publish_state,
+                publish_at,
+            }),
+            _ => Err(errors),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn form() -> EventForm {
+        EventForm {
+            title: " Jazz im Saal ".into(),
+            start: "2025-03-07T20:00".into(),
+            doors: "19:30".into(),
+            price: "10 €".into(),
+            ticket_url: "https://tickets.example.com/jazz".into(),
+            tags: "Jazz, Konzert, ,Jazz".into(),
+            description: "**Live**\n".into(),
+            ..EventForm::default()
+        }
+    }
+
+    #[test]
+    fn validate() {
+        let now: Timestamp = "2025-03-01T12:00:00Z".parse().unwrap();
+        let event = form().validate("abc", "anna", now).unwrap();
+
+        assert_eq!(event.id, "abc");
+        assert_eq!(event.title, "Jazz im Saal");
+        assert_eq!(
+            event.start_date,
+            DateTime::strptime(DATETIME_FORMAT, "2025-03-07T20:00")
+                .unwrap()
+                .to_zoned(TimeZone::system())
+                .unwrap()
+                .timestamp()
+        );
+        assert_eq!(event.end_date, None);
+        assert_eq!(event.doors, Some("19:30".parse().unwrap()));
+        assert_eq!(event.tags, ["Jazz", "Konzert"]);
+        assert_eq!(event.image, None);
+        assert_eq!(event.description, "**Live**");
+        assert_eq!(event.updated_by, "anna");
+        assert_eq!(event.publish_state, PublishState::Published);
+        assert_eq!(event.publish_at, None);
+
+        // Editing an event shows the stored values again.
+        let form = EventForm::from_event(&event);
+        assert_eq!(form.start, "2025-03-07T20:00");
+        assert_eq!(form.doors, "19:30");
+        assert_eq!(form.tags, "Jazz, Konzert");
+        assert_eq!(form.validate("abc", "anna", now).unwrap(), event);
+
+        let draft = EventForm {
+            publish_state: "draft".into(),
+            publish_at: "2025-03-03T10:00".into(),
+            ..form
+        };
+        let event = draft.validate("abc", "anna", now).unwrap();
+        assert_eq!(event.publish_state, PublishState::Draft);
+        assert_eq!(EventForm::from_event(&event).publish_at, "2025-03-03T10:00");
+
+        let invalid = EventForm {
+            title: "Quiz\nNacht".into(),
+            start: "2025-03-07T20:00".into(),
+            end: "2025-03-07T19:00".into(),
+            doors: "7 Uhr".into(),
+            ticket_url: "javascript:alert(1)".into(),
+            image: "poster.jpg".into(),
+            publish_state: "secret".into(),
+            publish_at: "morgen".into(),
+            ..EventForm::default()
+        };
+        assert_eq!(
+            invalid
+                .validate("abc", "anna", now)
+                .unwrap_err()
+                .into_keys()
+                .collect::<Vec<_>>(),
+            [
+                "doors",
+                "end",
+                "image",
+                "publish_at",
+                "publish_state",
+                "ticket_url",
+                "title"
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn login() {
+        let config = AdminConfig {
+            users: HashMap::from([("anna".into(), hash_password("geheim").unwrap())]),
+            ..AdminConfig::default()
+        };
+        let admin = Admin::new(&config);
+        let now = Timestamp::now();
+
+        assert_eq!(admin.login("anna", "falsch", now).await.unwrap(), None);
+        assert_eq!(admin.login("bernd", "geheim", now).await.unwrap(), None);
+
+        let token = admin.login("anna", "geheim", now).await.unwrap().unwrap();
+        let session = admin.session(&token, now).unwrap();
+        assert_eq!(session.user, "anna");
+        assert_eq!(session.csrf_token.len(), 64);
+        assert!(admin.session("other", now).is_none());
+        assert!(admin.session(&token, now + admin.session_ttl()).is_none());
+
+        admin.logout(&token);
+        assert!(admin.session(&token, now).is_none());
+    }
+
+    #[test]
+    fn login_attempts() {
+        let admin = Admin::new(&AdminConfig::default());
+        let now = Timestamp::now();
+
+        for _ in 0..MAX_LOGIN_ATTEMPTS {
+            assert!(admin.allow_login("192.0.2.1", "anna", now));
+        }
+        // Limited per IP. Attempts alone don't count against the user.
+        assert!(!admin.allow_login("192.0.2.1", "bernd", now));
+        assert!(admin.allow_login("192.0.2.2", "anna", now));
+
+        // Limited per user after failed logins.
+        for _ in 0..MAX_LOGIN_ATTEMPTS {
+            admin.record_failed_login("anna", now);
+        }
+        assert!(!admin.allow_login("192.0.2.3", "anna", now));
+        assert!(admin.allow_login("192.0.2.3", "bernd", now));
+
+        assert!(admin.allow_login("192.0.2.1", "anna", now + LOGIN_WINDOW));
+    }
+}
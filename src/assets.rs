@@ -0,0 +1,429 @@
+//! Content-hashed URLs and precompressed variants of static files.
+//!
+//! All files below the static directory are hashed once at startup. Templates reference them via
+//! the `asset()` function, which appends the hash as query parameter, so that URLs only change if
+//! the file content changes and responses can be cached forever. References to other static files
+//! in `url()`s of stylesheets are rewritten the same way, so fonts and images can be cached
+//! forever as well.
+//!
+//! Text based files can be precompressed with brotli and gzip ahead of time via `precompress`, so
+//! the server doesn't need to compress them on every request.
+
+use crate::Result;
+use flate2::{Compression, write::GzEncoder};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+
+/// URL path under which static files are served.
+pub const URL_PREFIX: &str = "/static";
+
+/// Name of the query parameter carrying the content hash.
+const HASH_PARAM: &str = "v";
+
+/// Extensions of files which are worth compressing. Other files like images and fonts are
+/// already compressed.
+const COMPRESSIBLE_EXTENSIONS: &[&str] = &[
+    "css",
+    "html",
+    "ico",
+    "js",
+    "json",
+    "svg",
+    "txt",
+    "webmanifest",
+    "xml",
+];
+
+/// Returns `true` if the static file `path` is worth compressing, see `COMPRESSIBLE_EXTENSIONS`.
+pub fn is_compressible(path: impl AsRef<Path>) -> bool {
+    path.as_ref()
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| COMPRESSIBLE_EXTENSIONS.contains(&ext))
+}
+
+/// Content encodings of precompressed files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Precompressed {
+    Brotli,
+    Gzip,
+}
+
+impl Precompressed {
+    /// Returns the file extension of the precompressed variant.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Precompressed::Brotli => "br",
+            Precompressed::Gzip => "gz",
+        }
+    }
+
+    fn compress(self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Precompressed::Brotli => {
+                let mut out = Vec::new();
+                let params = brotli::enc::BrotliEncoderParams {
+                    quality: 11,
+                    ..Default::default()
+                };
+                brotli::BrotliCompress(&mut &data[..], &mut out, &params)?;
+                Ok(out)
+            }
+            Precompressed::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?)
+            }
+        }
+    }
+}
+
+/// Maps paths of static files relative to the static directory to their content hashes.
+#[derive(Debug, Default, Clone)]
+pub struct AssetManifest {
+    hashes: HashMap<String, String>,
+    /// Content of stylesheets whose references to other static files were rewritten, by path.
+    stylesheets: HashMap<String, String>,
+}
+
+impl AssetManifest {
+    /// Hashes all files below `dir`.
+    pub fn load(dir: impl AsRef<Path>) -> Result<AssetManifest> {
+        let mut manifest = AssetManifest::default();
+        let mut stylesheets = Vec::new();
+
+        walk(dir.as_ref(), "", &mut |file, path| {
+            // Stylesheets are hashed after the files they reference.
+            if path.ends_with(".css") {
+                stylesheets.push((path, file.to_owned()));
+            } else {
+                manifest.insert(path, &std::fs::read(file)?);
+            }
+            Ok(())
+        })?;
+
+        for (path, file) in stylesheets {
+            let css = std::fs::read_to_string(file)?;
+            let rewritten = manifest.rewrite_stylesheet(&path, &css);
+            manifest.insert(path.clone(), rewritten.as_bytes());
+
+            if rewritten != css {
+                manifest.stylesheets.insert(path, rewritten);
+            }
+        }
+
+        log::debug!("hashed {} static files", manifest.hashes.len());
+        Ok(manifest)
+    }
+
+    fn insert(&mut self, path: String, content: &[u8]) {
+        let hash = Sha256::digest(content);
+        self.hashes.insert(path, hex::encode(&hash[..6]));
+    }
+
+    /// Returns the content of a stylesheet given by its path relative to the static directory, if
+    /// it references other static files. These references include content hashes, so the
+    /// stylesheet needs to be served with this content instead of the file content.
+    pub fn stylesheet(&self, path: &str) -> Option<&str> {
+        self.stylesheets.get(path).map(String::as_str)
+    }
+
+    /// Rewrites the `url()`s of a stylesheet at `path` which reference static files to URLs
+    /// including content hashes.
+    fn rewrite_stylesheet(&self, path: &str, css: &str) -> String {
+        let dir = path.rsplit_once('/').map_or("", |(dir, _)| dir);
+        let mut rewritten = String::with_capacity(css.len());
+        let mut rest = css;
+
+        while let Some(start) = rest.find("url(") {
+            let (before, after) = rest.split_at(start + "url(".len());
+            rewritten.push_str(before);
+
+            let Some(end) = after.find(')') else {
+                rest = after;
+                break;
+            };
+
+            let arg = &after[..end];
+            let trimmed = arg.trim();
+            let (quote, url) = match trimmed.chars().next() {
+                Some(quote @ ('"' | '\'')) if trimmed.len() >= 2 && trimmed.ends_with(quote) => {
+                    (Some(quote), &trimmed[1..trimmed.len() - 1])
+                }
+                _ => (None, trimmed),
+            };
+
+            match self.resolve(dir, url) {
+                Some(path) => {
+                    let quote = quote.map(String::from).unwrap_or_default();
+                    rewritten.push_str(&format!("{quote}{}{quote}", self.url(&path)));
+                }
+                None => rewritten.push_str(arg),
+            }
+
+            rest = &after[end..];
+        }
+
+        rewritten.push_str(rest);
+        rewritten
+    }
+
+    /// Resolves a URL referenced from a stylesheet in `dir` to the path of a known static file.
+    /// URLs of other sites, data URLs and URLs which already have a query are ignored.
+    fn resolve(&self, dir: &str, url: &str) -> Option<String> {
+        if url.contains([':', '?', '#']) || url.starts_with("//") {
+            return None;
+        }
+
+        let path = match url.strip_prefix(URL_PREFIX) {
+            Some(path) => path.strip_prefix('/')?.to_string(),
+            None if url.starts_with('/') => return None,
+            None => {
+                let mut segments: Vec<_> = dir.split('/').filter(|s| !s.is_empty()).collect();
+
+                for segment in url.split('/') {
+                    match segment {
+                        "" | "." => {}
+                        ".." => {
+                            segments.pop()?;
+                        }
+                        segment => segments.push(segment),
+                    }
+                }
+
+                segments.join("/")
+            }
+        };
+
+        self.hashes.contains_key(&path).then_some(path)
+    }
+
+    /// Returns the content hash of a static file given by its path relative to the static
+    /// directory.
+    pub fn hash(&self, path: &str) -> Option<&str> {
+        self.hashes.get(path).map(String::as_str)
+    }
+
+    /// Returns the URL of a static file given by its path relative to the static directory. Files
+    /// which are unknown are referenced without hash.
+    pub fn url(&self, path: &str) -> String {
+        let path = path.trim_start_matches('/');
+
+        match self.hash(path) {
+            Some(hash) => format!("{URL_PREFIX}/{path}?{HASH_PARAM}={hash}"),
+            None => {
+                log::warn!("unknown static file {path:?}");
+                format!("{URL_PREFIX}/{path}")
+            }
+        }
+    }
+
+    /// Returns `true` if a precompressed variant of the static file `path` exists.
+    pub fn has_precompressed(&self, path: &str, encoding: Precompressed) -> bool {
+        self.hashes
+            .contains_key(&format!("{path}.{}", encoding.extension()))
+    }
+
+    /// Returns `true` if a request for the static file `path` (relative to the static directory)
+    /// with query string `query` references the current content, i.e. the response can be cached
+    /// forever.
+    pub fn is_current(&self, path: &str, query: &str) -> bool {
+        self.hash(path).is_some_and(|hash| {
+            query
+                .strip_prefix(HASH_PARAM)
+                .and_then(|query| query.strip_prefix('='))
+                == Some(hash)
+        })
+    }
+}
+
+/// Calls `f` with the file system path and the relative path of every file below `dir`.
+fn walk(dir: &Path, prefix: &str, f: &mut impl FnMut(&Path, String) -> Result<()>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        let path = format!("{prefix}{name}");
+
+        if entry.file_type()?.is_dir() {
+            walk(&entry.path(), &format!("{path}/"), f)?;
+        } else {
+            f(&entry.path(), path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes brotli and gzip compressed variants next to all compressible files below `dir`.
+/// Variants which are up to date or wouldn't be smaller than the original are skipped. Returns
+/// the number of written files.
+///
+/// Stylesheets are compressed with rewritten references, see `AssetManifest::stylesheet`.
+pub fn precompress(dir: impl AsRef<Path>) -> Result<usize> {
+    let manifest = AssetManifest::load(dir.as_ref())?;
+    let mut written = 0;
+
+    walk(dir.as_ref(), "", &mut |file, path| {
+        if !is_compressible(file) {
+            return Ok(());
+        }
+
+        let modified = file.metadata()?.modified()?;
+        let stylesheet = manifest.stylesheet(&path);
+        let mut data = stylesheet.map(|css| css.as_bytes().to_vec());
+
+        for encoding in [Precompressed::Brotli, Precompressed::Gzip] {
+            let mut target = file.as_os_str().to_owned();
+            target.push(".");
+            target.push(encoding.extension());
+            let target = Path::new(&target);
+
+            // Rewritten stylesheets also change with the files they reference.
+            if stylesheet.is_none()
+                && target
+                    .metadata()
+                    .and_then(|meta| meta.modified())
+                    .is_ok_and(|target_modified| target_modified >= modified)
+            {
+                continue;
+            }
+
+            let data = match &mut data {
+                Some(data) => data,
+                None => data.insert(std::fs::read(file)?),
+            };
+            let compressed = encoding.compress(data)?;
+
+            if compressed.len() < data.len() {
+                std::fs::write(target, compressed)?;
+                written += 1;
+            } else if target.exists() {
+                std::fs::remove_file(target)?;
+            }
+        }
+
+        Ok(())
+    })?;
+
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifest() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir.join("css")).unwrap();
+        std::fs::write(dir.join("css/style.css"), "body {}").unwrap();
+        std::fs::write(dir.join("robots.txt"), "").unwrap();
+
+        let manifest = AssetManifest::load(dir).unwrap();
+        let hash = manifest.hash("css/style.css").unwrap().to_string();
+
+        assert_eq!(hash.len(), 12);
+        assert_eq!(
+            manifest.url("css/style.css"),
+            format!("/static/css/style.css?v={hash}")
+        );
+        assert_eq!(
+            manifest.url("/css/style.css"),
+            format!("/static/css/style.css?v={hash}")
+        );
+        assert_eq!(manifest.url("missing.css"), "/static/missing.css");
+
+        assert!(manifest.is_current("css/style.css", &format!("v={hash}")));
+        assert!(!manifest.is_current("css/style.css", "v=000000000000"));
+        assert!(!manifest.is_current("css/style.css", ""));
+        assert!(!manifest.is_current("missing.css", "v="));
+
+        // Different content results in a different hash.
+        std::fs::write(dir.join("css/style.css"), "body { color: red; }").unwrap();
+        let manifest = AssetManifest::load(dir).unwrap();
+        assert_ne!(manifest.hash("css/style.css"), Some(hash.as_str()));
+    }
+
+    #[test]
+    fn stylesheets() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir.join("css")).unwrap();
+        std::fs::create_dir_all(dir.join("fonts")).unwrap();
+        std::fs::write(dir.join("fonts/lato.woff2"), "font").unwrap();
+        std::fs::write(dir.join("logo.png"), "logo").unwrap();
+        std::fs::write(dir.join("css/plain.css"), "body {}").unwrap();
+        std::fs::write(
+            dir.join("css/style.css"),
+            "@font-face { src: url(\"/static/fonts/lato.woff2\") format(\"woff2\"); }\n\
+             .logo { background: url( '../logo.png' ); }\n\
+             .a { background: url(data:image/png;base64,AA==), url(https://example.com/a.png); }\n\
+             .b { background: url(/static/missing.png), url(/static/logo.png?v=1); }",
+        )
+        .unwrap();
+
+        let manifest = AssetManifest::load(dir).unwrap();
+        let font = manifest.url("fonts/lato.woff2");
+        let logo = manifest.url("logo.png");
+
+        assert_eq!(
+            manifest.stylesheet("css/style.css").unwrap(),
+            format!(
+                "@font-face {{ src: url(\"{font}\") format(\"woff2\"); }}\n\
+                 .logo {{ background: url('{logo}'); }}\n\
+                 .a {{ background: url(data:image/png;base64,AA==), url(https://example.com/a.png); }}\n\
+                 .b {{ background: url(/static/missing.png), url(/static/logo.png?v=1); }}"
+            )
+        );
+        assert_eq!(manifest.stylesheet("css/plain.css"), None);
+
+        // The hash of a stylesheet changes with the files it references.
+        let hash = manifest.hash("css/style.css").unwrap().to_string();
+        std::fs::write(dir.join("fonts/lato.woff2"), "changed font").unwrap();
+        let manifest = AssetManifest::load(dir).unwrap();
+        assert_ne!(manifest.hash("css/style.css"), Some(hash.as_str()));
+    }
+
+    #[test]
+    fn precompressed() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir.join("css")).unwrap();
+        std::fs::write(
+            dir.join("css/style.css"),
+            "body { color: red; }\n".repeat(100),
+        )
+        .unwrap();
+        std::fs::write(dir.join("tiny.js"), "1").unwrap();
+        std::fs::write(dir.join("image.png"), "not really a png".repeat(100)).unwrap();
+
+        assert_eq!(precompress(dir).unwrap(), 2);
+
+        let manifest = AssetManifest::load(dir).unwrap();
+        assert!(manifest.has_precompressed("css/style.css", Precompressed::Brotli));
+        assert!(manifest.has_precompressed("css/style.css", Precompressed::Gzip));
+        // Compression wouldn't make tiny files smaller.
+        assert!(!manifest.has_precompressed("tiny.js", Precompressed::Brotli));
+        // Images are not compressed.
+        assert!(!manifest.has_precompressed("image.png", Precompressed::Gzip));
+
+        let mut decompressed = Vec::new();
+        brotli::BrotliDecompress(
+            &mut std::fs::File::open(dir.join("css/style.css.br")).unwrap(),
+            &mut decompressed,
+        )
+        .unwrap();
+        assert_eq!(
+            decompressed,
+            std::fs::read(dir.join("css/style.css")).unwrap()
+        );
+
+        // Up to date variants are not written again.
+        assert_eq!(precompress(dir).unwrap(), 0);
+    }
+}
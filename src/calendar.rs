@@ -1,28 +1,51 @@
+pub mod diff;
 pub mod google;
+pub mod local;
 pub mod templating;
+pub mod watch;
 
-use super::Result;
-use crate::CalendarConfig;
+use super::{Error, Result};
+use crate::images::{ImagePipeline, Poster};
 use crate::markdown;
 use crate::metrics::{CalendarMetrics, CalendarSyncStatus, EventDetail};
+use crate::{CalendarConfig, SanitizeConfig};
 use async_trait::async_trait;
+use diff::{ChangeKind, EventDiff};
+use futures_util::{StreamExt, stream};
 use google::GoogleCalendarClient;
 use indexmap::IndexMap;
-use jiff::{Timestamp, ToSpan, Zoned, tz::TimeZone};
+use jiff::{Timestamp, ToSpan, Zoned, civil::Time, tz::TimeZone};
+use local::{LocalEventSource, LocalEventStore};
 use prometheus::Registry;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::fmt;
 use std::io;
 use std::ops::Range;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use tokio::sync::oneshot::{self, Receiver, Sender};
+use tokio::sync::{Mutex, broadcast};
 use tokio::task::JoinHandle;
 use tokio::time::Duration;
 
+/// Number of event diffs buffered for slow subscribers before they start missing diffs.
+const CHANGES_CAPACITY: usize = 16;
+
+/// Number of poster images which are downloaded and resized at the same time.
+const POSTER_CONCURRENCY: usize = 4;
+
+/// Maximum duration of processing poster images in the background. Remaining images are
+/// processed after the next sync.
+const POSTER_PROCESSING_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
 /// Represents a single calendar event.
-#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Default)]
 pub struct Event {
+    /// A stable identifier of the event. If empty, an ID is derived from the start date and title
+    /// during sync.
+    #[serde(default)]
+    pub id: String,
     /// The start date of the event.
     pub start_date: Timestamp,
     /// The end date of the event, if any.
@@ -32,6 +55,69 @@ pub struct Event {
     /// The event description, if any.
     #[serde(default, deserialize_with = "markdown::deserialize_to_html")]
     pub description: Option<String>,
+    /// Plain text version of the description, if any. This is populated during sync.
+    #[serde(skip_deserializing)]
+    pub description_text: Option<String>,
+    /// A short plain text excerpt of the description, if any. This is populated during sync.
+    #[serde(skip_deserializing)]
+    pub excerpt: Option<String>,
+    /// The admission price, if any.
+    pub price: Option<String>,
+    /// The time when doors open, if any.
+    pub doors: Option<Time>,
+    /// URL of the ticket shop, if any.
+    pub ticket_url: Option<String>,
+    /// Tags of the event, e.g. genres.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Source of a poster image for the event, if any. See `ImagePipeline` for supported
+    /// sources.
+    pub image: Option<String>,
+    /// The processed poster image, if any. This is populated during sync.
+    #[serde(skip_deserializing)]
+    pub poster: Option<Poster>,
+    /// Whether the event is published or still a draft.
+    #[serde(default)]
+    pub publish_state: PublishState,
+    /// The time of the official announcement, if the event must not appear before.
+    #[serde(default)]
+    pub publish_at: Option<Timestamp>,
+}
+
+/// Publication state of an event. Only published events are synchronized into the calendar, so
+/// drafts appear neither on the site nor in any notifications.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum PublishState {
+    /// The event is shown, starting at its `publish_at` time if any.
+    #[default]
+    Published,
+    /// The event is not shown yet.
+    Draft,
+}
+
+impl Event {
+    /// Returns the path of the event's detail page.
+    pub fn url(&self) -> String {
+        format!("/events/{}", self.id)
+    }
+
+    /// Returns the path of the event's generated social preview image.
+    pub fn preview_image_url(&self) -> String {
+        format!("{}/og.png", self.url())
+    }
+
+    /// Returns whether the event is published at time `now`.
+    pub fn is_published(&self, now: Timestamp) -> bool {
+        self.publish_state == PublishState::Published
+            && self.publish_at.is_none_or(|publish_at| publish_at <= now)
+    }
+
+    /// Returns the time at which the event will be published, if it is scheduled after `now`.
+    pub fn scheduled_publication(&self, now: Timestamp) -> Option<Timestamp> {
+        self.publish_at
+            .filter(|publish_at| self.publish_state == PublishState::Published && *publish_at > now)
+    }
 }
 
 impl fmt::Display for Event {
@@ -51,6 +137,8 @@ pub enum EventSourceKind {
     Static,
     /// Load events from Google Calendar.
     GoogleCalendar,
+    /// Load events from the local database edited in the admin UI.
+    Local,
 }
 
 /// Trait that needs to be implemented by a source of calendar events.
@@ -58,6 +146,14 @@ pub enum EventSourceKind {
 pub trait EventSource: Send + Sync {
     /// Fetches events from the source.
     async fn fetch_events(&self) -> Result<Vec<Event>>;
+
+    /// Fetches the content of an event attachment by its file ID. Fails if the attachment is
+    /// larger than `max_bytes`.
+    async fn fetch_attachment(&self, file_id: &str, _max_bytes: usize) -> Result<Vec<u8>> {
+        Err(Error::ImagePipeline(format!(
+            "cannot fetch attachment {file_id}: event source does not support attachments"
+        )))
+    }
 }
 
 /// An `EventSource` that returns events from a static list.
@@ -85,6 +181,61 @@ impl EventSource for StaticEventSource {
     }
 }
 
+/// An `EventSource` that merges the events of multiple sources, e.g. the events edited in the
+/// admin UI with the events from Google Calendar. If sources return events with the same ID, the
+/// event of the first source is kept.
+pub struct CompositeEventSource {
+    sources: Vec<Box<dyn EventSource>>,
+}
+
+impl CompositeEventSource {
+    /// Creates a new `CompositeEventSource` from sources in order of precedence.
+    pub fn new(sources: Vec<Box<dyn EventSource>>) -> CompositeEventSource {
+        CompositeEventSource { sources }
+    }
+}
+
+#[async_trait]
+impl EventSource for CompositeEventSource {
+    async fn fetch_events(&self) -> Result<Vec<Event>> {
+        let mut events = Vec::new();
+        let mut ids = HashSet::new();
+
+        for source in &self.sources {
+            for event in source.fetch_events().await? {
+                // Events without an ID get one derived during the sync.
+                if event.id.is_empty() || ids.insert(event.id.clone()) {
+                    events.push(event);
+                } else {
+                    log::warn!("ignoring event \"{event}\" with duplicate ID {}", event.id);
+                }
+            }
+        }
+
+        Ok(events)
+    }
+
+    /// Fetches the attachment from the first source which provides it.
+    async fn fetch_attachment(&self, file_id: &str, max_bytes: usize) -> Result<Vec<u8>> {
+        let mut first_err = None;
+
+        for source in &self.sources {
+            match source.fetch_attachment(file_id, max_bytes).await {
+                Ok(data) => return Ok(data),
+                Err(err) => {
+                    first_err.get_or_insert(err);
+                }
+            }
+        }
+
+        Err(first_err.unwrap_or_else(|| {
+            Error::ImagePipeline(format!(
+                "cannot fetch attachment {file_id}: no event source"
+            ))
+        }))
+    }
+}
+
 #[derive(Debug)]
 pub struct GoogleCalendarEventSource {
     client: GoogleCalendarClient,
@@ -100,11 +251,51 @@ impl GoogleCalendarEventSource {
 
 impl From<google::models::Event> for Event {
     fn from(ev: google::models::Event) -> Self {
+        // Metadata is written as a header block at the top of the description.
+        let (metadata, description) = match &ev.description {
+            Some(description) => {
+                let (metadata, body) = markdown::extract_metadata(description);
+                let body = Some(body).filter(|body| !body.trim().is_empty());
+                (metadata, body.and_then(markdown::to_html))
+            }
+            None => Default::default(),
+        };
+        let properties = &ev.extended_properties;
+
         Self {
+            id: ev.id,
             start_date: ev.start.to_timestamp(),
             end_date: Some(ev.end.to_timestamp()),
             title: ev.summary,
-            description: ev.description.and_then(markdown::to_html),
+            description,
+            description_text: None,
+            excerpt: None,
+            price: metadata.price,
+            doors: metadata.doors,
+            ticket_url: metadata.ticket_url,
+            tags: metadata.tags,
+            // An image from the description takes precedence over attached images.
+            image: metadata.image.or_else(|| {
+                ev.attachments
+                    .iter()
+                    .flatten()
+                    .find(|attachment| attachment.mime_type.starts_with("image/"))
+                    .map(|attachment| format!("drive:{}", attachment.file_id))
+            }),
+            poster: None,
+            // Markers in the description take precedence over extended properties, which can
+            // only be set via the API.
+            publish_state: metadata.publish_state.unwrap_or_else(|| {
+                match properties.get("publishState") {
+                    Some("draft") => PublishState::Draft,
+                    _ => PublishState::Published,
+                }
+            }),
+            publish_at: metadata.publish_at.or_else(|| {
+                properties
+                    .get("publishAt")
+                    .and_then(|publish_at| publish_at.parse().ok())
+            }),
         }
     }
 }
@@ -140,6 +331,10 @@ impl EventSource for GoogleCalendarEventSource {
 
         Ok(events)
     }
+
+    async fn fetch_attachment(&self, file_id: &str, max_bytes: usize) -> Result<Vec<u8>> {
+        Ok(self.client.download_file(file_id, max_bytes).await?)
+    }
 }
 
 #[async_trait]
@@ -150,6 +345,10 @@ where
     async fn fetch_events(&self) -> Result<Vec<Event>> {
         (**self).fetch_events().await
     }
+
+    async fn fetch_attachment(&self, file_id: &str, max_bytes: usize) -> Result<Vec<u8>> {
+        (**self).fetch_attachment(file_id, max_bytes).await
+    }
 }
 
 #[async_trait]
@@ -160,6 +359,95 @@ where
     async fn fetch_events(&self) -> Result<Vec<Event>> {
         (**self).fetch_events().await
     }
+
+    async fn fetch_attachment(&self, file_id: &str, max_bytes: usize) -> Result<Vec<u8>> {
+        (**self).fetch_attachment(file_id, max_bytes).await
+    }
+}
+
+/// Identifies a state of the synchronized events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CalendarVersion {
+    /// A counter which is incremented whenever the set of events changes.
+    pub number: u64,
+    /// The time of the last change.
+    pub modified: Timestamp,
+}
+
+/// Health of the calendar synchronization.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SyncHealth {
+    /// The time of the last successful sync.
+    pub last_success: Option<Timestamp>,
+    /// The time of the last failed sync.
+    pub last_error_at: Option<Timestamp>,
+    /// The error message of the last failed sync.
+    pub last_error: Option<String>,
+    /// The number of synchronized events.
+    pub events: usize,
+}
+
+/// Summary of a successful sync.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SyncReport {
+    /// The duration of the sync in seconds.
+    pub duration_seconds: f64,
+    /// The number of synchronized events.
+    pub events: usize,
+    /// The number of events which were added.
+    pub added: usize,
+    /// The number of events which were removed.
+    pub removed: usize,
+    /// The number of events which were changed.
+    pub changed: usize,
+}
+
+impl SyncReport {
+    /// Summarizes a sync which started at `start` and resulted in `events` events.
+    fn new(start: Timestamp, diff: &EventDiff, events: usize) -> SyncReport {
+        SyncReport {
+            duration_seconds: Timestamp::now().duration_since(start).as_secs_f64(),
+            events,
+            added: diff.added.len(),
+            removed: diff.removed.len(),
+            changed: diff.updated.len(),
+        }
+    }
+}
+
+/// The most recent sync, used to coalesce concurrent syncs.
+#[derive(Default)]
+struct LastSync {
+    /// The sequence number of the sync.
+    number: u64,
+    /// The result of the sync. Errors are stored as message because they can't be cloned.
+    result: Option<Result<SyncReport, String>>,
+}
+
+/// Whether the calendar is ready to serve events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Readiness {
+    /// No sync succeeded yet.
+    NotReady,
+    /// The last sync succeeded recently.
+    Ready,
+    /// Events are served, but the last successful sync is older than expected.
+    Degraded,
+}
+
+impl SyncHealth {
+    /// Determines the readiness at time `now`, considering syncs older than `max_age` as
+    /// degraded.
+    pub fn readiness(&self, now: Timestamp, max_age: Duration) -> Readiness {
+        match self.last_success {
+            None => Readiness::NotReady,
+            Some(last_success) if now.duration_since(last_success).unsigned_abs() > max_age => {
+                Readiness::Degraded
+            }
+            Some(_) => Readiness::Ready,
+        }
+    }
 }
 
 /// The `Calendar` type wraps an event source with additional functionality.
@@ -167,7 +455,16 @@ where
 pub struct Calendar {
     event_source: Arc<dyn EventSource>,
     events: Arc<Mutex<Vec<Event>>>,
+    version: Arc<Mutex<CalendarVersion>>,
+    health: Arc<Mutex<SyncHealth>>,
+    last_sync: Arc<Mutex<LastSync>>,
+    syncs_started: Arc<AtomicU64>,
+    changes: broadcast::Sender<Arc<EventDiff>>,
     metrics: Arc<CalendarMetrics>,
+    sanitize_config: Arc<SanitizeConfig>,
+    images: Option<Arc<ImagePipeline>>,
+    processing_posters: Arc<AtomicBool>,
+    next_publication: Arc<Mutex<Option<Timestamp>>>,
 }
 
 impl Calendar {
@@ -179,18 +476,65 @@ impl Calendar {
         Ok(Calendar {
             event_source: Arc::new(event_source),
             events: Default::default(),
+            version: Arc::new(Mutex::new(CalendarVersion {
+                number: 0,
+                modified: Timestamp::now(),
+            })),
+            health: Default::default(),
+            last_sync: Default::default(),
+            syncs_started: Default::default(),
+            changes: broadcast::channel(CHANGES_CAPACITY).0,
             metrics: Arc::new(CalendarMetrics::new()?),
+            sanitize_config: Default::default(),
+            images: None,
+            processing_posters: Default::default(),
+            next_publication: Default::default(),
         })
     }
 
-    /// Creates a new `Calendar` from configuration.
-    pub async fn from_config(config: &CalendarConfig) -> Result<Calendar> {
-        let event_source: Box<dyn EventSource> = match config.event_source {
+    /// Sets the rules used to sanitize event descriptions after each sync.
+    pub fn with_sanitize_config(mut self, config: SanitizeConfig) -> Calendar {
+        self.sanitize_config = Arc::new(config);
+        self
+    }
+
+    /// Sets the pipeline used to process event posters after each sync.
+    pub fn with_image_pipeline(mut self, images: ImagePipeline) -> Calendar {
+        self.images = Some(Arc::new(images));
+        self
+    }
+
+    /// Creates a new `Calendar` from configuration. The events of `local`, i.e. the store edited
+    /// in the admin UI, are merged with the events of the configured source.
+    pub async fn from_config(
+        config: &CalendarConfig,
+        mut local: Option<LocalEventStore>,
+    ) -> Result<Calendar> {
+        let configured: Box<dyn EventSource> = match config.event_source {
             EventSourceKind::Static => Box::new(StaticEventSource::new(config.events.clone())),
             EventSourceKind::GoogleCalendar => Box::new(GoogleCalendarEventSource::new().await?),
+            EventSourceKind::Local => Box::new(LocalEventSource::new(match local.take() {
+                Some(store) => store,
+                None => LocalEventStore::open(&config.local.database)?,
+            })),
         };
 
-        Calendar::new(event_source)
+        let event_source: Box<dyn EventSource> = match local {
+            Some(store) => Box::new(CompositeEventSource::new(vec![
+                configured,
+                Box::new(LocalEventSource::new(store)),
+            ])),
+            None => configured,
+        };
+
+        let mut calendar =
+            Calendar::new(event_source)?.with_sanitize_config(config.sanitize.clone());
+
+        if config.images.enabled {
+            calendar = calendar.with_image_pipeline(ImagePipeline::new(config.images.clone())?);
+        }
+
+        Ok(calendar)
     }
 
     /// Registers the calendar metrics in a prometheus registry.
@@ -229,6 +573,147 @@ impl Calendar {
         self.metrics.syncs_total(status).inc();
     }
 
+    /// Logs and counts the changes of a sync. The changes of the initial sync are not counted,
+    /// since they just reflect a restart.
+    fn record_changes(&self, diff: &EventDiff) {
+        if diff.initial {
+            log::info!("loaded {} events", diff.added.len());
+            return;
+        }
+
+        for event in &diff.added {
+            log::info!(
+                "event \"{event}\" ({}) on {} was added",
+                event.id,
+                event.start_date
+            );
+        }
+
+        for event in &diff.removed {
+            log::info!(
+                "event \"{event}\" ({}) on {} was removed",
+                event.id,
+                event.start_date
+            );
+        }
+
+        for update in &diff.updated {
+            let (previous, event) = (&update.previous, &update.event);
+
+            for kind in &update.changes {
+                match kind {
+                    ChangeKind::Rescheduled => log::info!(
+                        "event \"{event}\" ({}) was rescheduled from {} to {}",
+                        event.id,
+                        previous.start_date,
+                        event.start_date
+                    ),
+                    ChangeKind::Retitled => log::info!(
+                        "event \"{previous}\" ({}) was retitled to \"{event}\"",
+                        event.id
+                    ),
+                    ChangeKind::DescriptionChanged => {
+                        log::info!("description of event \"{event}\" ({}) changed", event.id)
+                    }
+                    ChangeKind::DetailsChanged => {
+                        log::info!("details of event \"{event}\" ({}) changed", event.id)
+                    }
+                }
+
+                self.metrics.event_changes_total(kind.as_str()).inc();
+            }
+        }
+
+        self.metrics
+            .event_changes_total("added")
+            .inc_by(diff.added.len() as u64);
+        self.metrics
+            .event_changes_total("removed")
+            .inc_by(diff.removed.len() as u64);
+    }
+
+    /// Attaches cached posters to events. Returns the image sources which are not cached yet or
+    /// due to be refreshed, along with their cached poster.
+    async fn attach_posters(
+        &self,
+        images: &ImagePipeline,
+        events: &mut [Event],
+    ) -> Vec<(String, Option<Poster>)> {
+        let mut pending = Vec::new();
+        let mut seen = HashSet::new();
+
+        for event in events {
+            let Some(source) = &event.image else {
+                continue;
+            };
+
+            let cached = images.cached(source).await;
+            event.poster = cached.as_ref().map(|cached| cached.poster.clone());
+
+            let due = cached.as_ref().is_none_or(|cached| cached.stale);
+            if due && seen.insert(source.clone()) {
+                pending.push((source.clone(), cached.map(|cached| cached.poster)));
+            }
+        }
+
+        pending
+    }
+
+    /// Processes poster images in the background, so that slow downloads and resizing don't hold
+    /// up syncs. Once a poster changed, the calendar is synced again to attach it. Failures are
+    /// logged.
+    fn spawn_poster_processing(
+        &self,
+        images: Arc<ImagePipeline>,
+        pending: Vec<(String, Option<Poster>)>,
+    ) {
+        if pending.is_empty() || self.processing_posters.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let calendar = self.clone();
+
+        tokio::spawn(async move {
+            let deadline = tokio::time::Instant::now() + POSTER_PROCESSING_TIMEOUT;
+            let event_source = calendar.event_source.clone();
+            let mut results = stream::iter(pending)
+                .map(|(source, previous)| {
+                    let images = images.clone();
+                    let event_source = event_source.clone();
+                    async move {
+                        let result = images.process(&source, event_source.as_ref()).await;
+                        (source, previous, result)
+                    }
+                })
+                .buffer_unordered(POSTER_CONCURRENCY);
+
+            let mut changed = false;
+
+            loop {
+                match tokio::time::timeout_at(deadline, results.next()).await {
+                    Ok(Some((source, previous, Ok(poster)))) => {
+                        changed |= previous.as_ref() != Some(&poster);
+                        log::debug!("processed image {source}");
+                    }
+                    Ok(Some((source, _, Err(err)))) => {
+                        log::warn!("failed to process image {source}: {err}");
+                    }
+                    Ok(None) => break,
+                    Err(_) => {
+                        log::warn!("processing images timed out, continuing with the next sync");
+                        break;
+                    }
+                }
+            }
+
+            calendar.processing_posters.store(false, Ordering::SeqCst);
+
+            if changed && let Err(err) = calendar.sync_coalesced().await {
+                log::error!("failed to sync calendar events after processing images: {err}");
+            }
+        });
+    }
+
     /// Filters events between a start date (inclusive) and an end date (exclusive).
     pub async fn get_events(&self, range: Range<Timestamp>) -> Result<Vec<Event>> {
         let events = self.events.lock().await.clone();
@@ -241,6 +726,38 @@ impl Calendar {
         Ok(events)
     }
 
+    /// Returns the current version of the synchronized events.
+    pub async fn version(&self) -> CalendarVersion {
+        *self.version.lock().await
+    }
+
+    /// Subscribes to the changes of future syncs. Syncs without changes are not published.
+    /// Subscribers which fall behind by more than a few syncs miss the oldest diffs.
+    pub fn subscribe_changes(&self) -> broadcast::Receiver<Arc<EventDiff>> {
+        self.changes.subscribe()
+    }
+
+    /// Returns the earliest time at which a scheduled event will be published, as of the last
+    /// successful sync.
+    pub async fn next_publication(&self) -> Option<Timestamp> {
+        *self.next_publication.lock().await
+    }
+
+    /// Returns the health of the calendar synchronization.
+    pub async fn health(&self) -> SyncHealth {
+        self.health.lock().await.clone()
+    }
+
+    /// Returns the event with the given ID, if any.
+    pub async fn get_event(&self, id: &str) -> Option<Event> {
+        self.events
+            .lock()
+            .await
+            .iter()
+            .find(|event| event.id == id)
+            .cloned()
+    }
+
     /// Builds an index of event year to list of events. This is used to avoid having complicated
     /// logic for displaying events by year in HTML templates.
     pub async fn get_events_by_year(&self, range: Range<Timestamp>) -> Result<EventsByYear> {
@@ -259,22 +776,127 @@ impl Calendar {
     }
 
     /// Synchronize events from the source into the calendar once.
-    pub async fn sync_once(&self) -> Result<()> {
+    ///
+    /// Syncs never run concurrently. This is cancellation safe: the calendar is only updated after
+    /// all locks are acquired, so dropping the future either keeps the previous events or applies
+    /// the sync completely.
+    pub async fn sync_once(&self) -> Result<SyncReport> {
+        let mut last_sync = self.last_sync.lock().await;
+        self.sync_locked(&mut last_sync).await
+    }
+
+    /// Like `sync_once`, but requests arriving while another sync is pending share the result of
+    /// the next sync which starts after they arrived. This avoids hammering the event source if a
+    /// sync is triggered many times in a row.
+    pub async fn sync_coalesced(&self) -> Result<SyncReport> {
+        let requested = self.syncs_started.load(Ordering::SeqCst);
+        let mut last_sync = self.last_sync.lock().await;
+
+        if last_sync.number > requested
+            && let Some(result) = &last_sync.result
+        {
+            log::debug!("reusing result of concurrent calendar sync");
+            return result.clone().map_err(Error::Sync);
+        }
+
+        self.sync_locked(&mut last_sync).await
+    }
+
+    /// Runs a sync while holding the `last_sync` lock.
+    async fn sync_locked(&self, last_sync: &mut LastSync) -> Result<SyncReport> {
+        let number = self.syncs_started.fetch_add(1, Ordering::SeqCst) + 1;
+        let result = self.fetch_and_apply().await;
+
+        last_sync.number = number;
+        last_sync.result = Some(match &result {
+            Ok(report) => Ok(report.clone()),
+            Err(err) => Err(err.to_string()),
+        });
+
+        result
+    }
+
+    async fn fetch_and_apply(&self) -> Result<SyncReport> {
         log::debug!("synchronizing calendar events");
 
         let start = Timestamp::now();
 
         let (result, status) = match self.event_source.fetch_events().await {
             Ok(mut events) => {
+                // Unpublished events are left out entirely, so that scheduled events appear like
+                // new events with the first sync after their publication.
+                let now = Timestamp::now();
+                let next_publication = events
+                    .iter()
+                    .filter_map(|event| event.scheduled_publication(now))
+                    .min();
+                events.retain(|event| event.is_published(now));
+
                 self.record_event_metrics(&events);
 
+                // Descriptions may contain arbitrary HTML from anyone with write access to the
+                // event source and are rendered without escaping.
+                for event in &mut events {
+                    if let Some(description) = &event.description {
+                        let description =
+                            markdown::render_description(description, &self.sanitize_config);
+
+                        event.description = Some(description.html);
+                        event.description_text = Some(description.text);
+                        event.excerpt = Some(description.excerpt);
+                    }
+                }
+
+                if let Some(images) = &self.images {
+                    let pending = self.attach_posters(images, &mut events).await;
+                    self.spawn_poster_processing(images.clone(), pending);
+
+                    if let Err(err) = images.prune(&events).await {
+                        log::warn!("failed to prune image cache: {err}");
+                    }
+                }
+
                 // Ensure events are always sorted by date.
                 events.sort_by_key(|event| event.start_date);
-                *self.events.lock().await = events;
+                assign_ids(&mut events);
+
+                let mut current = self.events.lock().await;
+                let mut version = self.version.lock().await;
+                let mut health = self.health.lock().await;
+                *self.next_publication.lock().await = next_publication;
+
+                let diff = EventDiff {
+                    initial: health.last_success.is_none(),
+                    ..EventDiff::new(&current, &events, start)
+                };
+                let report = SyncReport::new(start, &diff, events.len());
+
+                health.last_success = Some(Timestamp::now());
+                health.events = events.len();
+
+                // Only bump the version if something changed, so that clients can keep using
+                // their cached pages.
+                if *current != events {
+                    *current = events;
+                    version.number += 1;
+                    version.modified = Timestamp::now();
+                }
 
-                (Ok(()), CalendarSyncStatus::Success)
+                if !diff.is_empty() {
+                    self.record_changes(&diff);
+                    // Sending only fails if there are no subscribers.
+                    let _ = self.changes.send(Arc::new(diff));
+                }
+
+                (Ok(report), CalendarSyncStatus::Success)
+            }
+            Err(err) => {
+                let mut health = self.health.lock().await;
+                health.last_error_at = Some(Timestamp::now());
+                health.last_error = Some(err.to_string());
+
+                (Err(err), CalendarSyncStatus::Error)
             }
-            Err(err) => (Err(err), CalendarSyncStatus::Error),
         };
 
         self.record_sync_metrics(start, status);
@@ -283,20 +905,42 @@ impl Calendar {
     }
 
     /// Starts to periodically sync the calendar every `interval` until a message is received via
-    /// `stop`.
+    /// `stop`. A sync which is still running when the message is received is cancelled.
     async fn start_sync(&self, period: Duration, mut stop: Receiver<()>) {
         log::info!("synchronizing calendar events every {:?}", period);
         let mut interval = tokio::time::interval(period);
 
         loop {
+            let next_publication = self.next_publication().await;
+
             tokio::select! {
-                _ = interval.tick() => {
-                    if let Err(err) = self.sync_once().await {
+                _ = interval.tick() => {}
+                // Sync right away when a scheduled event is due, instead of up to a sync period
+                // later.
+                _ = wait_until(next_publication) => {
+                    log::info!("publishing scheduled events");
+                }
+                _ = &mut stop => {
+                    log::info!("stopping calendar sync");
+                    return;
+                }
+            }
+
+            tokio::select! {
+                result = self.sync_once() => {
+                    if let Err(err) = result {
                         log::error!("failed to sync calendar events: {err}");
+
+                        // The publication time is only updated by successful syncs. Retry a due
+                        // publication with the next regular sync instead of right away.
+                        let mut next_publication = self.next_publication.lock().await;
+                        if next_publication.is_some_and(|time| time <= Timestamp::now()) {
+                            *next_publication = None;
+                        }
                     }
                 }
                 _ = &mut stop => {
-                    log::info!("stopping calendar sync");
+                    log::info!("cancelling running calendar sync");
                     return;
                 }
             }
@@ -320,6 +964,63 @@ impl Calendar {
     }
 }
 
+/// Waits until `time`, or forever if it is `None`.
+async fn wait_until(time: Option<Timestamp>) {
+    match time {
+        Some(time) => {
+            let duration = Timestamp::now().duration_until(time);
+            tokio::time::sleep(duration.try_into().unwrap_or_default()).await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Derives IDs for events without one from their start date and title, e.g.
+/// `2023-03-17-till-burgwaechter-lesung`. Events with the same date and title get a numeric
+/// suffix. Expects events to be sorted by start date to keep the suffixes stable.
+fn assign_ids(events: &mut [Event]) {
+    let mut seen = HashSet::new();
+
+    for event in events.iter_mut() {
+        if !event.id.is_empty() {
+            seen.insert(event.id.clone());
+            continue;
+        }
+
+        let date = event.start_date.to_zoned(TimeZone::system()).date();
+        let base = format!("{date}-{}", slugify(&event.title));
+        let mut id = base.clone();
+        let mut n = 1;
+
+        while seen.contains(&id) {
+            n += 1;
+            id = format!("{base}-{n}");
+        }
+
+        seen.insert(id.clone());
+        event.id = id;
+    }
+}
+
+/// Converts a title into a lowercase ASCII slug.
+fn slugify(title: &str) -> String {
+    let mut slug = String::with_capacity(title.len());
+
+    for c in title.to_lowercase().chars() {
+        match c {
+            'ä' => slug.push_str("ae"),
+            'ö' => slug.push_str("oe"),
+            'ü' => slug.push_str("ue"),
+            'ß' => slug.push_str("ss"),
+            c if c.is_ascii_alphanumeric() => slug.push(c),
+            _ if !slug.ends_with('-') => slug.push('-'),
+            _ => {}
+        }
+    }
+
+    slug.trim_matches('-').to_string()
+}
+
 /// A handle for stopping a calendar sync task.
 pub struct SyncTaskHandle {
     join_handle: JoinHandle<()>,
@@ -341,7 +1042,7 @@ impl SyncTaskHandle {
 mod tests {
     use super::*;
     use indexmap::indexmap;
-    use jiff::{civil::datetime, tz::TimeZone};
+    use jiff::{SignedDuration, civil::datetime, tz::TimeZone};
     use std::sync::atomic::{AtomicUsize, Ordering};
 
     macro_rules! date {
@@ -356,14 +1057,31 @@ mod tests {
     macro_rules! event {
         ($title:expr, $y:expr, $m:expr, $d:expr) => {
             Event {
+                // Matches the ID derived during sync.
+                id: format!("{:04}-{:02}-{:02}-{}", $y, $m, $d, $title),
                 title: $title.into(),
                 start_date: date!($y, $m, $d),
-                end_date: None,
-                description: None,
+                ..Default::default()
             }
         };
     }
 
+    /// A fake `EventSource` whose events can be replaced between syncs.
+    struct MutableSource(std::sync::Mutex<Vec<Event>>);
+
+    impl MutableSource {
+        fn new(events: Vec<Event>) -> Arc<MutableSource> {
+            Arc::new(MutableSource(std::sync::Mutex::new(events)))
+        }
+    }
+
+    #[async_trait]
+    impl EventSource for MutableSource {
+        async fn fetch_events(&self) -> Result<Vec<Event>> {
+            Ok(self.0.lock().unwrap().clone())
+        }
+    }
+
     #[actix_rt::test]
     async fn events_between() {
         let calendar = Calendar::new(StaticEventSource::new([
@@ -429,6 +1147,383 @@ mod tests {
         );
     }
 
+    #[test]
+    fn slugs() {
+        assert_eq!(
+            slugify("Till Burgwächter (Lesung)"),
+            "till-burgwaechter-lesung"
+        );
+        assert_eq!(
+            slugify("Smith & Smart / DJ Cutrock"),
+            "smith-smart-dj-cutrock"
+        );
+        assert_eq!(slugify("Große Party!"), "grosse-party");
+    }
+
+    #[actix_rt::test]
+    async fn event_ids() {
+        let without_id = |mut event: Event| {
+            event.id.clear();
+            event
+        };
+
+        let mut google = event!("c", 2023, 1, 1);
+        google.id = "abc123".into();
+
+        let calendar = Calendar::new(StaticEventSource::new([
+            without_id(event!("Barabend", 2023, 1, 2)),
+            without_id(event!("Barabend", 2023, 1, 1)),
+            without_id(event!("Barabend", 2023, 1, 1)),
+            google,
+        ]))
+        .unwrap();
+        calendar.sync_once().await.unwrap();
+
+        let ids: Vec<_> = calendar
+            .get_events(date!(2023, 1, 1)..date!(2023, 1, 3))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|event| event.id)
+            .collect();
+
+        assert_eq!(
+            ids,
+            vec![
+                "2023-01-01-barabend",
+                "2023-01-01-barabend-2",
+                "abc123",
+                "2023-01-02-barabend"
+            ]
+        );
+
+        assert_eq!(
+            calendar.get_event("abc123").await.map(|event| event.title),
+            Some("c".into())
+        );
+        assert_eq!(calendar.get_event("unknown").await, None);
+    }
+
+    #[actix_rt::test]
+    async fn publication() {
+        let now = Timestamp::now();
+        let hours = |hours| SignedDuration::from_hours(hours);
+
+        // Drafts stay hidden even if their publication time passed.
+        let mut draft = event!("draft", 2023, 1, 1);
+        draft.publish_state = PublishState::Draft;
+        draft.publish_at = Some(now - hours(1));
+        let mut announced = event!("announced", 2023, 1, 2);
+        announced.publish_at = Some(now - hours(1));
+        let mut scheduled = event!("scheduled", 2023, 1, 3);
+        scheduled.publish_at = Some(now + hours(2));
+        let mut soon = event!("soon", 2023, 1, 4);
+        soon.publish_at = Some(now + hours(1));
+
+        let calendar = Calendar::new(StaticEventSource::new([
+            event!("a", 2023, 1, 1),
+            draft,
+            announced,
+            scheduled,
+            soon,
+        ]))
+        .unwrap();
+        let report = calendar.sync_once().await.unwrap();
+        assert_eq!(report.events, 2);
+
+        let titles: Vec<_> = calendar
+            .get_events(date!(2023, 1, 1)..date!(2023, 2, 1))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|event| event.title)
+            .collect();
+        assert_eq!(titles, ["a", "announced"]);
+        assert_eq!(calendar.get_event("2023-01-04-soon").await, None);
+        assert_eq!(calendar.next_publication().await, Some(now + hours(1)));
+    }
+
+    #[actix_rt::test]
+    async fn sanitize_descriptions() {
+        let mut event = event!("a", 2023, 1, 1);
+        event.description = Some("<p onclick=\"alert(1)\">Hello<iframe></iframe></p>".into());
+
+        let calendar = Calendar::new(StaticEventSource::new([event])).unwrap();
+        calendar.sync_once().await.unwrap();
+
+        let events = calendar
+            .get_events(date!(2023, 1, 1)..date!(2023, 1, 2))
+            .await
+            .unwrap();
+
+        assert_eq!(events[0].description.as_deref(), Some("<p>Hello</p>"));
+        assert_eq!(events[0].description_text.as_deref(), Some("Hello"));
+        assert_eq!(events[0].excerpt.as_deref(), Some("Hello"));
+    }
+
+    #[actix_rt::test]
+    async fn composite_event_source() {
+        let mut duplicate = event!("b", 2023, 1, 2);
+        duplicate.id = "2023-01-01-a".into();
+
+        let source = CompositeEventSource::new(vec![
+            Box::new(StaticEventSource::new([event!("a", 2023, 1, 1)])),
+            Box::new(StaticEventSource::new([duplicate, event!("c", 2023, 1, 3)])),
+        ]);
+        let calendar = Calendar::new(source).unwrap();
+        calendar.sync_once().await.unwrap();
+
+        let titles: Vec<_> = calendar
+            .get_events(date!(2023, 1, 1)..date!(2023, 2, 1))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|event| event.title)
+            .collect();
+        assert_eq!(titles, ["a", "c"]);
+    }
+
+    #[actix_rt::test]
+    async fn version() {
+        let source = MutableSource::new(vec![event!("a", 2023, 1, 1)]);
+        let calendar = Calendar::new(source.clone()).unwrap();
+
+        assert_eq!(calendar.version().await.number, 0);
+
+        calendar.sync_once().await.unwrap();
+        let version = calendar.version().await;
+        assert_eq!(version.number, 1);
+
+        // Unchanged events keep the version.
+        calendar.sync_once().await.unwrap();
+        assert_eq!(calendar.version().await, version);
+
+        source.0.lock().unwrap().push(event!("b", 2023, 1, 2));
+        calendar.sync_once().await.unwrap();
+        assert_eq!(calendar.version().await.number, 2);
+    }
+
+    #[actix_rt::test]
+    async fn publish_changes() {
+        let source = MutableSource::new(vec![event!("a", 2023, 1, 1)]);
+        let calendar = Calendar::new(source.clone()).unwrap();
+        let mut changes = calendar.subscribe_changes();
+
+        calendar.sync_once().await.unwrap();
+        let diff = changes.try_recv().unwrap();
+        assert!(diff.initial);
+        assert_eq!(diff.added.len(), 1);
+
+        // Syncs without changes are not published.
+        calendar.sync_once().await.unwrap();
+        assert!(changes.try_recv().is_err());
+
+        source.0.lock().unwrap()[0].description = Some("Neu".into());
+        calendar.sync_once().await.unwrap();
+        let diff = changes.try_recv().unwrap();
+        assert!(!diff.initial);
+        assert_eq!(diff.updated[0].changes, [ChangeKind::DescriptionChanged]);
+    }
+
+    #[actix_rt::test]
+    async fn sync_report() {
+        let calendar = Calendar::new(StaticEventSource::new([
+            event!("a", 2023, 1, 1),
+            event!("b", 2023, 1, 2),
+        ]))
+        .unwrap();
+
+        let report = calendar.sync_once().await.unwrap();
+        assert_eq!(
+            (report.events, report.added, report.removed, report.changed),
+            (2, 2, 0, 0)
+        );
+
+        let mut changed = event!("b", 2023, 1, 2);
+        changed.price = Some("5 €".into());
+        let diff = EventDiff::new(
+            &[event!("a", 2023, 1, 1), event!("b", 2023, 1, 2)],
+            &[changed, event!("c", 2023, 1, 3)],
+            "2022-12-01T00:00:00Z".parse().unwrap(),
+        );
+        let report = SyncReport::new(Timestamp::now(), &diff, 2);
+        assert_eq!(
+            (report.events, report.added, report.removed, report.changed),
+            (2, 1, 1, 1)
+        );
+    }
+
+    #[actix_rt::test]
+    async fn coalesce_syncs() {
+        // A fake `EventSource` which counts invocations of `fetch_events` and takes a while.
+        struct Slow(AtomicUsize);
+
+        #[async_trait]
+        impl EventSource for Slow {
+            async fn fetch_events(&self) -> Result<Vec<Event>> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                Ok(vec![event!("a", 2023, 1, 1)])
+            }
+        }
+
+        let source = Arc::new(Slow(AtomicUsize::new(0)));
+        let calendar = Calendar::new(source.clone()).unwrap();
+
+        let (first, (second, third, fourth)) = tokio::join!(calendar.sync_once(), async {
+            // These arrive while the first sync is running, which may have fetched stale events,
+            // so they share one additional sync.
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            tokio::join!(
+                calendar.sync_coalesced(),
+                calendar.sync_coalesced(),
+                calendar.sync_coalesced()
+            )
+        });
+
+        assert_eq!(source.0.load(Ordering::SeqCst), 2);
+        assert_eq!(first.unwrap().added, 1);
+
+        let second = second.unwrap();
+        assert_eq!(second.added, 0);
+        assert_eq!(third.unwrap(), second);
+        assert_eq!(fourth.unwrap(), second);
+    }
+
+    #[actix_rt::test]
+    async fn posters_in_background() {
+        // A fake `EventSource` with a poster attachment which takes a while to download.
+        struct Source;
+
+        #[async_trait]
+        impl EventSource for Source {
+            async fn fetch_events(&self) -> Result<Vec<Event>> {
+                let mut event = event!("a", 2023, 1, 1);
+                event.image = Some("drive:poster".into());
+                Ok(vec![event])
+            }
+
+            async fn fetch_attachment(&self, _file_id: &str, _max_bytes: usize) -> Result<Vec<u8>> {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+
+                let mut png = Vec::new();
+                image::DynamicImage::ImageRgb8(image::RgbImage::new(200, 100))
+                    .write_to(&mut io::Cursor::new(&mut png), image::ImageFormat::Png)
+                    .unwrap();
+                Ok(png)
+            }
+        }
+
+        let tmp = tempfile::tempdir().unwrap();
+        let images = ImagePipeline::new(crate::ImageConfig {
+            cache_dir: tmp.path().to_path_buf(),
+            widths: vec![100],
+            ..Default::default()
+        })
+        .unwrap();
+        let calendar = Calendar::new(Source).unwrap().with_image_pipeline(images);
+
+        // The sync doesn't wait for the download.
+        let start = std::time::Instant::now();
+        calendar.sync_once().await.unwrap();
+        assert!(start.elapsed() < Duration::from_millis(200));
+        assert_eq!(
+            calendar.get_event("2023-01-01-a").await.unwrap().poster,
+            None
+        );
+
+        // The poster is attached by another sync once it is processed.
+        for _ in 0..100 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            if calendar.version().await.number > 1 {
+                break;
+            }
+        }
+
+        let poster = calendar.get_event("2023-01-01-a").await.unwrap().poster;
+        assert_eq!(poster.unwrap().variants.len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn failed_sync_at_publication() {
+        // A fake `EventSource` which returns a scheduled event once and fails afterwards.
+        struct Source(AtomicUsize);
+
+        #[async_trait]
+        impl EventSource for Source {
+            async fn fetch_events(&self) -> Result<Vec<Event>> {
+                if self.0.fetch_add(1, Ordering::SeqCst) > 0 {
+                    return Err(Error::Io(io::Error::other("unavailable")));
+                }
+
+                let mut scheduled = event!("scheduled", 2023, 1, 1);
+                scheduled.publish_at = Some(Timestamp::now() + SignedDuration::from_millis(50));
+                Ok(vec![scheduled])
+            }
+        }
+
+        let source = Arc::new(Source(AtomicUsize::new(0)));
+        let calendar = Calendar::new(source.clone()).unwrap();
+
+        // The initial sync and the one at the publication time, which fails. The failure doesn't
+        // make the task sync again right away.
+        let handle = calendar.spawn_sync_task(Duration::from_secs(3600)).await;
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        handle.stop().await.unwrap();
+
+        assert_eq!(source.0.load(Ordering::SeqCst), 2);
+        assert_eq!(calendar.next_publication().await, None);
+    }
+
+    #[actix_rt::test]
+    async fn health() {
+        // A fake `EventSource` which fails until it is given events.
+        struct Source(std::sync::Mutex<Option<Vec<Event>>>);
+
+        #[async_trait]
+        impl EventSource for Source {
+            async fn fetch_events(&self) -> Result<Vec<Event>> {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .clone()
+                    .ok_or_else(|| Error::Io(io::Error::other("unavailable")))
+            }
+        }
+
+        let source = Arc::new(Source(std::sync::Mutex::new(None)));
+        let calendar = Calendar::new(source.clone()).unwrap();
+        let max_age = Duration::from_secs(180);
+
+        let health = calendar.health().await;
+        assert_eq!(health, SyncHealth::default());
+        assert_eq!(
+            health.readiness(Timestamp::now(), max_age),
+            Readiness::NotReady
+        );
+
+        assert!(calendar.sync_once().await.is_err());
+        let health = calendar.health().await;
+        assert_eq!(health.last_error.as_deref(), Some("IO error: unavailable"));
+        assert!(health.last_error_at.is_some());
+        assert_eq!(
+            health.readiness(Timestamp::now(), max_age),
+            Readiness::NotReady
+        );
+
+        *source.0.lock().unwrap() = Some(vec![event!("a", 2023, 1, 1)]);
+        calendar.sync_once().await.unwrap();
+        let health = calendar.health().await;
+        assert_eq!(health.events, 1);
+
+        let last_success = health.last_success.unwrap();
+        assert_eq!(health.readiness(last_success, max_age), Readiness::Ready);
+        assert_eq!(
+            health.readiness(last_success + 181.seconds(), max_age),
+            Readiness::Degraded
+        );
+    }
+
     #[actix_rt::test]
     async fn calendar_sync() {
         use CalendarSyncStatus::*;
@@ -445,8 +1540,7 @@ mod tests {
                 Ok(vec![Event {
                     title: "event".into(),
                     start_date: date!(2023, 1, 1),
-                    end_date: None,
-                    description: None,
+                    ..Default::default()
                 }])
             }
         }
@@ -503,4 +1597,31 @@ mod tests {
         // Since sync is stopped, counter should not increase.
         assert_eq!(counter.0.load(Ordering::Relaxed), 3);
     }
+
+    #[actix_rt::test]
+    async fn cancel_sync() {
+        // A fake `EventSource` which never finishes fetching events.
+        struct Hanging;
+
+        #[async_trait]
+        impl EventSource for Hanging {
+            async fn fetch_events(&self) -> Result<Vec<Event>> {
+                std::future::pending().await
+            }
+        }
+
+        let calendar = Calendar::new(Hanging).unwrap();
+        let sync_task_handle = calendar.spawn_sync_task(Duration::from_secs(60)).await;
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        // Stopping cancels the running sync instead of waiting for it.
+        tokio::time::timeout(Duration::from_secs(1), sync_task_handle.stop())
+            .await
+            .expect("sync was not cancelled")
+            .unwrap();
+
+        assert_eq!(calendar.version().await.number, 0);
+        assert_eq!(calendar.health().await, SyncHealth::default());
+    }
 }
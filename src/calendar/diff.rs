@@ -0,0 +1,223 @@
+//! Differences between the events before and after a sync.
+//!
+//! Events are matched by ID. Google Calendar events keep their ID when they are edited, while
+//! IDs derived for static events change with the start date and title, so rescheduling or
+//! retitling a static event shows up as a removed and an added event.
+//!
+//! Sources only return events from a window starting today, so events which took place simply
+//! disappear. Only events which haven't started yet are reported as removed.
+
+use super::Event;
+use jiff::Timestamp;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+/// Kinds of changes to an event which exists before and after a sync.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    /// The start or end date changed.
+    Rescheduled,
+    /// The title changed.
+    Retitled,
+    /// The description changed.
+    DescriptionChanged,
+    /// Any other field changed, e.g. the price or the poster.
+    DetailsChanged,
+}
+
+impl ChangeKind {
+    /// Returns the kind of change as a &str.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ChangeKind::Rescheduled => "rescheduled",
+            ChangeKind::Retitled => "retitled",
+            ChangeKind::DescriptionChanged => "description_changed",
+            ChangeKind::DetailsChanged => "details_changed",
+        }
+    }
+}
+
+/// An event which exists before and after a sync, but changed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EventUpdate {
+    /// The event before the sync.
+    pub previous: Event,
+    /// The event after the sync.
+    pub event: Event,
+    /// The kinds of changes, never empty.
+    pub changes: Vec<ChangeKind>,
+}
+
+impl EventUpdate {
+    /// Compares two versions of an event. Returns `None` if they are equal.
+    fn new(previous: &Event, event: &Event) -> Option<EventUpdate> {
+        if previous == event {
+            return None;
+        }
+
+        let mut changes = Vec::new();
+
+        if previous.start_date != event.start_date || previous.end_date != event.end_date {
+            changes.push(ChangeKind::Rescheduled);
+        }
+
+        if previous.title != event.title {
+            changes.push(ChangeKind::Retitled);
+        }
+
+        if previous.description != event.description {
+            changes.push(ChangeKind::DescriptionChanged);
+        }
+
+        // Compare the remaining fields by copying the ones checked above.
+        let details = Event {
+            start_date: event.start_date,
+            end_date: event.end_date,
+            title: event.title.clone(),
+            description: event.description.clone(),
+            description_text: event.description_text.clone(),
+            excerpt: event.excerpt.clone(),
+            ..previous.clone()
+        };
+
+        if details != *event {
+            changes.push(ChangeKind::DetailsChanged);
+        }
+
+        Some(EventUpdate {
+            previous: previous.clone(),
+            event: event.clone(),
+            changes,
+        })
+    }
+}
+
+/// The changes between the events before and after a sync.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct EventDiff {
+    /// `true` for the first successful sync, which reports all events as added.
+    pub initial: bool,
+    /// Events which didn't exist before.
+    pub added: Vec<Event>,
+    /// Upcoming events which don't exist anymore.
+    pub removed: Vec<Event>,
+    /// Events which changed.
+    pub updated: Vec<EventUpdate>,
+}
+
+impl EventDiff {
+    /// Compares the previous and new events by ID. Keeps the order of the given events. Events
+    /// which started before `now` are not reported as removed.
+    pub fn new(previous: &[Event], events: &[Event], now: Timestamp) -> EventDiff {
+        let by_id: HashMap<&str, &Event> = previous
+            .iter()
+            .map(|event| (event.id.as_str(), event))
+            .collect();
+
+        let mut diff = EventDiff::default();
+
+        for event in events {
+            match by_id.get(event.id.as_str()) {
+                None => diff.added.push(event.clone()),
+                Some(prev) => diff.updated.extend(EventUpdate::new(prev, event)),
+            }
+        }
+
+        let ids: HashSet<&str> = events.iter().map(|event| event.id.as_str()).collect();
+
+        diff.removed = previous
+            .iter()
+            .filter(|event| event.start_date > now && !ids.contains(event.id.as_str()))
+            .cloned()
+            .collect();
+
+        diff
+    }
+
+    /// Returns `true` if no event was added, removed or updated.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.updated.is_empty()
+    }
+
+    /// Returns the updated events with a change of the given kind.
+    pub fn updated_with(&self, kind: ChangeKind) -> impl Iterator<Item = &EventUpdate> {
+        self.updated
+            .iter()
+            .filter(move |update| update.changes.contains(&kind))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(id: &str, start_date: &str, title: &str) -> Event {
+        Event {
+            id: id.into(),
+            start_date: start_date.parse().unwrap(),
+            title: title.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn diff() {
+        let previous = [
+            event("a", "2025-03-01T18:00:00Z", "Barabend"),
+            event("b", "2025-03-02T18:00:00Z", "Kneipenquiz"),
+            event("c", "2025-03-03T18:00:00Z", "Lesung"),
+            event("d", "2025-03-04T18:00:00Z", "Konzert"),
+        ];
+
+        let mut rescheduled = event("b", "2025-03-09T18:00:00Z", "Kneipenquiz!");
+        rescheduled.price = Some("5 €".into());
+        let mut described = previous[2].clone();
+        described.description = Some("<p>Mit Autorin</p>".into());
+        described.excerpt = Some("Mit Autorin".into());
+
+        let events = [
+            previous[0].clone(),
+            described,
+            rescheduled,
+            event("e", "2025-03-10T18:00:00Z", "Party"),
+        ];
+
+        let now = "2025-02-28T12:00:00Z".parse().unwrap();
+        let diff = EventDiff::new(&previous, &events, now);
+
+        assert!(!diff.initial);
+        assert_eq!(diff.added, [events[3].clone()]);
+        assert_eq!(diff.removed, [previous[3].clone()]);
+        assert_eq!(
+            diff.updated
+                .iter()
+                .map(|update| (update.event.id.as_str(), update.changes.as_slice()))
+                .collect::<Vec<_>>(),
+            [
+                ("c", &[ChangeKind::DescriptionChanged][..]),
+                (
+                    "b",
+                    &[
+                        ChangeKind::Rescheduled,
+                        ChangeKind::Retitled,
+                        ChangeKind::DetailsChanged
+                    ][..]
+                ),
+            ]
+        );
+        assert_eq!(diff.updated[1].previous.title, "Kneipenquiz");
+        assert_eq!(diff.updated_with(ChangeKind::Retitled).count(), 1);
+        assert_eq!(diff.updated_with(ChangeKind::DetailsChanged).count(), 1);
+
+        assert!(EventDiff::new(&previous, &previous, now).is_empty());
+
+        // Events which took place drop out of the synced window, but weren't removed.
+        let later = "2025-03-03T12:00:00Z".parse().unwrap();
+        assert!(EventDiff::new(&previous, &previous[2..], later).is_empty());
+        assert_eq!(
+            EventDiff::new(&previous, &previous[..3], later).removed,
+            [previous[3].clone()]
+        );
+    }
+}
@@ -41,6 +41,14 @@ pub enum ClientError {
     /// Error while obtaining an authentication token.
     #[error("failed to obtain authentication token: {0}")]
     Token(String),
+
+    /// Error when a downloaded file exceeds the size limit.
+    #[error("file is larger than {0} bytes")]
+    TooLarge(usize),
+
+    /// Error when a Google Drive file ID contains unexpected characters.
+    #[error("invalid file ID {0:?}")]
+    InvalidFileId(String),
 }
 
 impl From<ClientError> for reqwest_middleware::Error {
@@ -100,7 +108,11 @@ impl GoogleCalendarClient {
             Err(_) => return Err(ClientError::MissingCalendarID),
         };
 
-        let scopes = ["https://www.googleapis.com/auth/calendar.readonly"];
+        let scopes = [
+            "https://www.googleapis.com/auth/calendar.readonly",
+            // Required to download event attachments.
+            "https://www.googleapis.com/auth/drive.readonly",
+        ];
         let config = gcloud_auth::project::Config::default().with_scopes(&scopes);
 
         let token_source = DefaultTokenSourceProvider::new(config)
@@ -145,6 +157,91 @@ impl GoogleCalendarClient {
 
         Ok(events_request.query(&query).send().await?.json().await?)
     }
+
+    /// Registers a channel which receives push notifications at `address` whenever events of the
+    /// calendar change. Google sends `token` along with every notification.
+    pub async fn watch_events(
+        &self,
+        channel_id: &str,
+        address: &str,
+        token: &str,
+        ttl: Duration,
+    ) -> Result<models::Channel, ClientError> {
+        let body = models::WatchRequest {
+            id: channel_id,
+            kind: "web_hook",
+            address,
+            token,
+            params: models::WatchParams {
+                ttl: ttl.as_secs().to_string(),
+            },
+        };
+
+        let resp = self
+            .client
+            .post(format!(
+                "https://www.googleapis.com/calendar/v3/calendars/{}/events/watch",
+                self.calendar_id
+            ))
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(resp.json().await?)
+    }
+
+    /// Stops receiving push notifications for a channel.
+    pub async fn stop_channel(&self, channel: &models::Channel) -> Result<(), ClientError> {
+        self.client
+            .post("https://www.googleapis.com/calendar/v3/channels/stop")
+            .json(&serde_json::json!({
+                "id": channel.id,
+                "resourceId": channel.resource_id,
+            }))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+
+    /// Downloads the content of a Google Drive file, e.g. an event attachment. This requires the
+    /// file to be shared with the service account. Fails if the file is larger than `max_bytes`.
+    pub async fn download_file(
+        &self,
+        file_id: &str,
+        max_bytes: usize,
+    ) -> Result<Vec<u8>, ClientError> {
+        // The ID becomes part of the URL path, which must not point to another endpoint.
+        if file_id.is_empty()
+            || !file_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(ClientError::InvalidFileId(file_id.into()));
+        }
+
+        let mut resp = self
+            .client
+            .get(format!(
+                "https://www.googleapis.com/drive/v3/files/{file_id}"
+            ))
+            .query(&[("alt", "media")])
+            .send()
+            .await?
+            .error_for_status()?;
+
+        let mut bytes = Vec::new();
+        while let Some(chunk) = resp.chunk().await? {
+            if bytes.len() + chunk.len() > max_bytes {
+                return Err(ClientError::TooLarge(max_bytes));
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+
+        Ok(bytes)
+    }
 }
 
 fn build_query_parameters(
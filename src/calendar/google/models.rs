@@ -1,5 +1,6 @@
 use jiff::{Timestamp, civil::Date, tz::TimeZone};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all(deserialize = "camelCase"))]
@@ -46,6 +47,26 @@ pub struct Attachment {
     pub file_id: String,
 }
 
+/// Key-value pairs attached to an event, which are not shown in Google Calendar.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ExtendedProperties {
+    /// Properties private to the copy of the event in this calendar.
+    pub private: HashMap<String, String>,
+    /// Properties shared between the copies of the event in all attendees' calendars.
+    pub shared: HashMap<String, String>,
+}
+
+impl ExtendedProperties {
+    /// Returns the private or shared property with the given key.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.private
+            .get(key)
+            .or_else(|| self.shared.get(key))
+            .map(String::as_str)
+    }
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all(deserialize = "camelCase"))]
 pub struct Event {
@@ -68,6 +89,8 @@ pub struct Event {
     pub sequence: u64,
     pub event_type: String,
     pub attachments: Option<Vec<Attachment>>,
+    #[serde(default)]
+    pub extended_properties: ExtendedProperties,
 }
 
 #[derive(Debug, Deserialize)]
@@ -84,3 +107,41 @@ pub struct Events {
     pub items: Vec<Event>,
     pub next_page_token: Option<String>,
 }
+
+/// Request body for watching a resource via push notifications.
+#[derive(Debug, Serialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct WatchRequest<'a> {
+    pub id: &'a str,
+    #[serde(rename(serialize = "type"))]
+    pub kind: &'static str,
+    pub address: &'a str,
+    pub token: &'a str,
+    pub params: WatchParams,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WatchParams {
+    /// Requested lifetime of the channel in seconds. Google may choose a shorter one.
+    pub ttl: String,
+}
+
+/// A notification channel returned by a watch request.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Channel {
+    pub id: String,
+    pub resource_id: String,
+    /// Expiration time of the channel as milliseconds since the Unix epoch.
+    pub expiration: Option<String>,
+}
+
+impl Channel {
+    /// Returns the expiration time of the channel, if any.
+    pub fn expires_at(&self) -> Option<Timestamp> {
+        self.expiration
+            .as_deref()
+            .and_then(|millis| millis.parse().ok())
+            .and_then(|millis| Timestamp::from_millisecond(millis).ok())
+    }
+}
@@ -0,0 +1,383 @@
+//! Events stored in a local SQLite database, which are edited in the admin UI.
+//!
+//! Descriptions are stored as markdown and converted to HTML when events are fetched, like the
+//! descriptions of static events.
+
+use super::{Event, EventSource, PublishState};
+use crate::markdown;
+use crate::{Error, Result, SanitizeConfig};
+use async_trait::async_trait;
+use jiff::Timestamp;
+use jiff::civil::Time;
+use rusqlite::{Connection, OptionalExtension, Row, params};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Schema migrations, applied in order. The number of applied migrations is stored as
+/// `user_version`. Timestamps are stored as UNIX seconds.
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE events (
+        id TEXT PRIMARY KEY,
+        start_date INTEGER NOT NULL,
+        end_date INTEGER,
+        title TEXT NOT NULL,
+        description TEXT NOT NULL,
+        price TEXT,
+        doors TEXT,
+        ticket_url TEXT,
+        tags TEXT NOT NULL,
+        image TEXT,
+        updated_at INTEGER NOT NULL,
+        updated_by TEXT NOT NULL
+    );
+
+    CREATE INDEX events_start_date ON events (start_date);
+",
+    "
+    ALTER TABLE events ADD COLUMN publish_state TEXT NOT NULL DEFAULT 'published';
+    ALTER TABLE events ADD COLUMN publish_at INTEGER;
+",
+];
+
+/// An event as stored in the database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalEvent {
+    /// The ID of the event, which is part of its URL.
+    pub id: String,
+    /// The start date of the event.
+    pub start_date: Timestamp,
+    /// The end date of the event, if any.
+    pub end_date: Option<Timestamp>,
+    /// The event title.
+    pub title: String,
+    /// The description as markdown, may be empty.
+    pub description: String,
+    /// The admission price, if any.
+    pub price: Option<String>,
+    /// The time when doors open, if any.
+    pub doors: Option<Time>,
+    /// URL of the ticket shop, if any.
+    pub ticket_url: Option<String>,
+    /// Tags of the event, e.g. genres.
+    pub tags: Vec<String>,
+    /// Source of a poster image, if any.
+    pub image: Option<String>,
+    /// The time of the last change.
+    pub updated_at: Timestamp,
+    /// The user who made the last change.
+    pub updated_by: String,
+    /// Whether the event is published or still a draft.
+    pub publish_state: PublishState,
+    /// The time of the official announcement, if any.
+    pub publish_at: Option<Timestamp>,
+}
+
+impl LocalEvent {
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<LocalEvent> {
+        let timestamp = |seconds: i64| {
+            Timestamp::from_second(seconds).map_err(|err| {
+                rusqlite::Error::FromSqlConversionFailure(
+                    0,
+                    rusqlite::types::Type::Integer,
+                    err.into(),
+                )
+            })
+        };
+        let invalid_text = |index, err: Box<dyn std::error::Error + Send + Sync>| {
+            rusqlite::Error::FromSqlConversionFailure(index, rusqlite::types::Type::Text, err)
+        };
+
+        Ok(LocalEvent {
+            id: row.get(0)?,
+            start_date: timestamp(row.get(1)?)?,
+            end_date: row.get::<_, Option<i64>>(2)?.map(timestamp).transpose()?,
+            title: row.get(3)?,
+            description: row.get(4)?,
+            price: row.get(5)?,
+            doors: row
+                .get::<_, Option<String>>(6)?
+                .map(|doors| {
+                    doors
+                        .parse()
+                        .map_err(|err: jiff::Error| invalid_text(6, err.into()))
+                })
+                .transpose()?,
+            ticket_url: row.get(7)?,
+            tags: serde_json::from_str(&row.get::<_, String>(8)?)
+                .map_err(|err| invalid_text(8, err.into()))?,
+            image: row.get(9)?,
+            updated_at: timestamp(row.get(10)?)?,
+            updated_by: row.get(11)?,
+            publish_state: match row.get::<_, String>(12)?.as_str() {
+                "draft" => PublishState::Draft,
+                _ => PublishState::Published,
+            },
+            publish_at: row.get::<_, Option<i64>>(13)?.map(timestamp).transpose()?,
+        })
+    }
+}
+
+impl From<LocalEvent> for Event {
+    fn from(event: LocalEvent) -> Self {
+        Event {
+            id: event.id,
+            start_date: event.start_date,
+            end_date: event.end_date,
+            title: event.title,
+            description: Some(event.description)
+                .filter(|description| !description.trim().is_empty())
+                .and_then(markdown::to_html),
+            description_text: None,
+            excerpt: None,
+            price: event.price,
+            doors: event.doors,
+            ticket_url: event.ticket_url,
+            tags: event.tags,
+            image: event.image,
+            poster: None,
+            publish_state: event.publish_state,
+            publish_at: event.publish_at,
+        }
+    }
+}
+
+/// Renders a markdown description like it is shown on the event pages.
+pub fn preview_description(description: &str, config: &SanitizeConfig) -> String {
+    markdown::to_html(description)
+        .map(|html| markdown::render_description(html, config).html)
+        .unwrap_or_default()
+}
+
+/// Events stored in a SQLite database. Clones share the same connection.
+#[derive(Clone)]
+pub struct LocalEventStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl LocalEventStore {
+    /// Opens the database at `path`, creating it if necessary.
+    pub fn open(path: &Path) -> Result<LocalEventStore> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let conn = Connection::open(path)?;
+        conn.busy_timeout(Duration::from_secs(5))?;
+        LocalEventStore::migrate(conn)
+    }
+
+    /// Opens a temporary in-memory database.
+    pub fn open_in_memory() -> Result<LocalEventStore> {
+        LocalEventStore::migrate(Connection::open_in_memory()?)
+    }
+
+    fn migrate(conn: Connection) -> Result<LocalEventStore> {
+        let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            log::info!("migrating local events database to version {}", i + 1);
+            conn.execute_batch(&format!(
+                "BEGIN; {migration} PRAGMA user_version = {}; COMMIT;",
+                i + 1
+            ))?;
+        }
+
+        Ok(LocalEventStore {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Runs a function with the connection on the blocking thread pool.
+    async fn with_conn<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.lock().unwrap_or_else(|err| err.into_inner());
+            f(&conn)
+        })
+        .await
+        .map_err(|err| Error::Admin(format!("database task failed: {err}")))?
+        .map_err(Error::from)
+    }
+
+    /// Returns all events, the latest first.
+    pub async fn list(&self) -> Result<Vec<LocalEvent>> {
+        self.with_conn(|conn| {
+            conn.prepare("SELECT * FROM events ORDER BY start_date DESC, id")?
+                .query_map([], LocalEvent::from_row)?
+                .collect()
+        })
+        .await
+    }
+
+    /// Returns the event with the given ID, if any.
+    pub async fn get(&self, id: &str) -> Result<Option<LocalEvent>> {
+        let id = id.to_string();
+
+        self.with_conn(move |conn| {
+            conn.query_row(
+                "SELECT * FROM events WHERE id = ?1",
+                params![id],
+                LocalEvent::from_row,
+            )
+            .optional()
+        })
+        .await
+    }
+
+    /// Inserts a new event or replaces the event with the same ID.
+    pub async fn save(&self, event: LocalEvent) -> Result<()> {
+        let tags = serde_json::to_string(&event.tags)
+            .map_err(|err| Error::Admin(format!("failed to serialize tags: {err}")))?;
+
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO events (id, start_date, end_date, title, description, price,
+                     doors, ticket_url, tags, image, updated_at, updated_by, publish_state,
+                     publish_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+                params![
+                    event.id,
+                    event.start_date.as_second(),
+                    event.end_date.map(|date| date.as_second()),
+                    event.title,
+                    event.description,
+                    event.price,
+                    event.doors.map(|doors| doors.strftime("%H:%M").to_string()),
+                    event.ticket_url,
+                    tags,
+                    event.image,
+                    event.updated_at.as_second(),
+                    event.updated_by,
+                    match event.publish_state {
+                        PublishState::Published => "published",
+                        PublishState::Draft => "draft",
+                    },
+                    event.publish_at.map(|date| date.as_second()),
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Deletes the event with the given ID. Returns whether it existed.
+    pub async fn delete(&self, id: &str) -> Result<bool> {
+        let id = id.to_string();
+
+        self.with_conn(move |conn| {
+            Ok(conn.execute("DELETE FROM events WHERE id = ?1", params![id])? > 0)
+        })
+        .await
+    }
+}
+
+/// An `EventSource` that returns the events of a `LocalEventStore`.
+pub struct LocalEventSource {
+    store: LocalEventStore,
+}
+
+impl LocalEventSource {
+    /// Creates a new `LocalEventSource` from a store.
+    pub fn new(store: LocalEventStore) -> LocalEventSource {
+        LocalEventSource { store }
+    }
+}
+
+#[async_trait]
+impl EventSource for LocalEventSource {
+    async fn fetch_events(&self) -> Result<Vec<Event>> {
+        Ok(self
+            .store
+            .list()
+            .await?
+            .into_iter()
+            .map(Event::from)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(id: &str, start_date: &str) -> LocalEvent {
+        LocalEvent {
+            id: id.into(),
+            start_date: start_date.parse().unwrap(),
+            end_date: None,
+            title: format!("Event {id}"),
+            description: "**Live** im Alhambra".into(),
+            price: Some("5 €".into()),
+            doors: Some("19:00".parse().unwrap()),
+            ticket_url: None,
+            tags: vec!["Konzert".into()],
+            image: None,
+            updated_at: "2025-03-01T12:00:00Z".parse().unwrap(),
+            updated_by: "anna".into(),
+            publish_state: PublishState::Published,
+            publish_at: None,
+        }
+    }
+
+    #[actix_web::test]
+    async fn store() {
+        let store = LocalEventStore::open_in_memory().unwrap();
+        let first = event("first", "2025-03-07T18:00:00Z");
+        let mut second = event("second", "2025-03-14T18:00:00Z");
+
+        store.save(first.clone()).await.unwrap();
+        store.save(second.clone()).await.unwrap();
+        assert_eq!(store.list().await.unwrap(), [second.clone(), first.clone()]);
+
+        second.title = "Kneipenquiz".into();
+        second.doors = None;
+        second.tags.clear();
+        second.publish_state = PublishState::Draft;
+        second.publish_at = Some("2025-03-02T10:00:00Z".parse().unwrap());
+        store.save(second.clone()).await.unwrap();
+        assert_eq!(store.get("second").await.unwrap(), Some(second));
+
+        assert!(store.delete("first").await.unwrap());
+        assert!(!store.delete("first").await.unwrap());
+        assert_eq!(store.get("first").await.unwrap(), None);
+    }
+
+    #[actix_web::test]
+    async fn event_source() {
+        let store = LocalEventStore::open_in_memory().unwrap();
+        store
+            .save(event("first", "2025-03-07T18:00:00Z"))
+            .await
+            .unwrap();
+        let mut empty = event("empty", "2025-03-14T18:00:00Z");
+        empty.description = " ".into();
+        store.save(empty).await.unwrap();
+
+        let events = LocalEventSource::new(store).fetch_events().await.unwrap();
+        assert_eq!(events[0].id, "empty");
+        assert_eq!(events[0].description, None);
+        assert_eq!(events[1].id, "first");
+        assert_eq!(
+            events[1].description.as_deref(),
+            Some("<p><strong>Live</strong> im Alhambra</p>")
+        );
+        assert_eq!(events[1].doors, Some("19:00".parse().unwrap()));
+    }
+
+    #[test]
+    fn preview() {
+        let html = preview_description(
+            "Hallo <script>alert(1)</script>",
+            &SanitizeConfig::default(),
+        );
+        assert!(html.contains("Hallo"));
+        assert!(!html.contains("<script"));
+    }
+}
@@ -1,6 +1,7 @@
 use super::Event;
+use crate::images::Poster;
 use jiff::{SignedDuration, Zoned, civil::Weekday, fmt::strtime, tz::TimeZone};
-use minijinja::value::{Object, Value};
+use minijinja::value::{Enumerator, Object, Value};
 use std::sync::Arc;
 
 impl Object for Event {
@@ -30,8 +31,17 @@ impl Object for Event {
                     None => Value::from(format!("{start_time}")),
                 }
             }
+            "id" => Value::from(&self.id),
+            "url" => Value::from(self.url()),
             "title" => Value::from(&self.title),
             "description" => return self.description.as_ref().map(Value::from),
+            "description_text" => return self.description_text.as_ref().map(Value::from),
+            "excerpt" => return self.excerpt.as_ref().map(Value::from),
+            "price" => return self.price.as_ref().map(Value::from),
+            "doors" => Value::from(self.doors?.strftime("%H:%M").to_string()),
+            "ticket_url" => return self.ticket_url.as_ref().map(Value::from),
+            "tags" => Value::from(self.tags.clone()),
+            "poster" => Value::from_object(self.poster.clone()?),
             _ => return None,
         };
 
@@ -39,11 +49,28 @@ impl Object for Event {
     }
 }
 
-fn format_time(date: &Zoned) -> strtime::Display<'_> {
+impl Object for Poster {
+    fn get_value(self: &Arc<Self>, field: &Value) -> Option<Value> {
+        let value = match field.as_str()? {
+            "src" => Value::from(self.src()?),
+            "srcset" => Value::from(self.srcset()),
+            _ => return None,
+        };
+
+        Some(value)
+    }
+
+    fn enumerate(self: &Arc<Self>) -> Enumerator {
+        // Makes the poster truthy in templates.
+        Enumerator::Str(&["src", "srcset"])
+    }
+}
+
+pub(crate) fn format_time(date: &Zoned) -> strtime::Display<'_> {
     date.strftime("%H:%M")
 }
 
-fn format_date(date: &Zoned) -> String {
+pub(crate) fn format_date(date: &Zoned) -> String {
     format!(
         "{}, {}. {}",
         german_weekday(date),
@@ -85,6 +112,7 @@ fn german_month_name(date: &Zoned) -> &'static str {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::images::PosterVariant;
     use jiff::Timestamp;
 
     macro_rules! event {
@@ -93,7 +121,7 @@ mod tests {
                 start_date: $start_date,
                 end_date: $end_date,
                 title: "The event".into(),
-                description: None,
+                ..Default::default()
             })
         };
     }
@@ -126,6 +154,44 @@ mod tests {
         assert_field_value!(event, "date", expected_date);
     }
 
+    #[test]
+    fn event_metadata() {
+        let event = Arc::new(Event {
+            price: Some("5 €".into()),
+            doors: Some(jiff::civil::time(19, 30, 0, 0)),
+            tags: vec!["Punk".into()],
+            ..Default::default()
+        });
+
+        assert_field_value!(event, "price", "5 €");
+        assert_field_value!(event, "doors", "19:30");
+        assert_field_value!(event, "tags", vec!["Punk"]);
+        assert_eq!(event.get_value(&Value::from("ticket_url")), None);
+    }
+
+    #[test]
+    fn event_poster() {
+        let event = Arc::new(Event {
+            poster: Some(Poster {
+                variants: vec![PosterVariant {
+                    width: 320,
+                    url: "/images/a-320.webp".into(),
+                }],
+            }),
+            ..Default::default()
+        });
+
+        let env = minijinja::Environment::new();
+        let rendered = env
+            .render_str(
+                "{% if event.poster %}{{ event.poster.srcset }}{% endif %}",
+                minijinja::context! { event => Value::from_dyn_object(event) },
+            )
+            .unwrap();
+
+        assert_eq!(rendered, "/images/a-320.webp 320w");
+    }
+
     #[test]
     fn event_time_without_end_date() {
         let event = event!("2025-02-05T18:00:00Z".parse().unwrap(), None);
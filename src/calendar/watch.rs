@@ -0,0 +1,329 @@
+//! Push notifications from Google Calendar via watch channels.
+//!
+//! On startup a notification channel is registered, which makes Google call a webhook whenever
+//! events change. Channels expire, so they are renewed ahead of time and stopped on shutdown.
+
+use super::google::{ClientError, GoogleCalendarClient, models::Channel};
+use crate::PushConfig;
+use jiff::Timestamp;
+use std::io;
+use tokio::sync::oneshot::{self, Receiver, Sender};
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
+
+/// Header containing the ID of the channel a notification belongs to.
+pub const CHANNEL_ID_HEADER: &str = "X-Goog-Channel-ID";
+/// Header containing the token given when registering the channel.
+pub const CHANNEL_TOKEN_HEADER: &str = "X-Goog-Channel-Token";
+/// Header containing the state of the watched resource, `sync` for the initial notification.
+pub const RESOURCE_STATE_HEADER: &str = "X-Goog-Resource-State";
+/// Header containing the sequence number of the notification.
+pub const MESSAGE_NUMBER_HEADER: &str = "X-Goog-Message-Number";
+
+/// Minimum delay between channel registrations, also used after a failed registration.
+const RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// A push notification received via the webhook.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    /// The ID of the channel.
+    pub channel_id: String,
+    /// The state of the watched resource, e.g. `sync` or `exists`.
+    pub resource_state: String,
+    /// The sequence number of the notification, if any.
+    pub message_number: Option<u64>,
+}
+
+/// Reasons for rejecting a push notification.
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum NotificationError {
+    /// A required header is missing.
+    #[error("missing header `{0}`")]
+    MissingHeader(&'static str),
+    /// The channel token doesn't match the configured one.
+    #[error("invalid channel token")]
+    InvalidToken,
+}
+
+impl Notification {
+    /// Parses a notification from the request headers returned by `header` and checks that it
+    /// carries the expected `token`.
+    pub fn from_headers(
+        header: impl Fn(&str) -> Option<String>,
+        token: &str,
+    ) -> Result<Notification, NotificationError> {
+        let required =
+            |name: &'static str| header(name).ok_or(NotificationError::MissingHeader(name));
+
+        if !crate::secret_eq(token, &required(CHANNEL_TOKEN_HEADER)?) {
+            return Err(NotificationError::InvalidToken);
+        }
+
+        Ok(Notification {
+            channel_id: required(CHANNEL_ID_HEADER)?,
+            resource_state: required(RESOURCE_STATE_HEADER)?,
+            message_number: header(MESSAGE_NUMBER_HEADER).and_then(|number| number.parse().ok()),
+        })
+    }
+
+    /// Returns `true` for the initial notification Google sends after registering a channel. It
+    /// doesn't indicate any change.
+    pub fn is_sync(&self) -> bool {
+        self.resource_state == "sync"
+    }
+}
+
+/// Keeps a notification channel registered.
+pub struct ChannelWatcher {
+    client: GoogleCalendarClient,
+    address: String,
+    token: String,
+    ttl: Duration,
+    renew_before: Duration,
+}
+
+impl ChannelWatcher {
+    /// Creates a new `ChannelWatcher` for notifications sent to the webhook at `address`.
+    pub fn new(
+        client: GoogleCalendarClient,
+        address: String,
+        config: &PushConfig,
+    ) -> ChannelWatcher {
+        ChannelWatcher {
+            client,
+            address,
+            token: config.token.clone().unwrap_or_default(),
+            ttl: Duration::from_secs(config.ttl_seconds),
+            renew_before: Duration::from_secs(config.renew_before_seconds),
+        }
+    }
+
+    /// Starts a background task which registers a channel and renews it before it expires.
+    /// Returns a `WatchTaskHandle` to stop the task and the channel.
+    pub fn spawn(self) -> WatchTaskHandle {
+        let (stop_tx, stop_rx) = oneshot::channel();
+
+        let join_handle = tokio::spawn(async move {
+            self.run(stop_rx).await;
+        });
+
+        WatchTaskHandle {
+            join_handle,
+            stop_tx,
+        }
+    }
+
+    async fn run(&self, mut stop: Receiver<()>) {
+        let mut channel: Option<Channel> = None;
+
+        loop {
+            let delay = match self.register().await {
+                Ok(new) => {
+                    log::info!(
+                        "registered push notification channel {} for {}, expires at {}",
+                        new.id,
+                        self.address,
+                        new.expires_at()
+                            .map_or_else(|| "unknown".to_string(), |time| time.to_string())
+                    );
+
+                    let delay = renew_delay(&new, Timestamp::now(), self.renew_before, self.ttl);
+
+                    // Notifications may arrive via both channels for a moment, which is harmless.
+                    if let Some(old) = channel.replace(new) {
+                        self.stop_channel(&old).await;
+                    }
+
+                    delay
+                }
+                Err(err) => {
+                    log::error!("failed to register push notification channel: {err}");
+                    RETRY_DELAY
+                }
+            };
+
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = &mut stop => break,
+            }
+        }
+
+        if let Some(channel) = channel {
+            self.stop_channel(&channel).await;
+        }
+    }
+
+    async fn register(&self) -> Result<Channel, ClientError> {
+        let mut id = [0; 16];
+        getrandom::fill(&mut id).expect("failed to generate channel ID");
+
+        self.client
+            .watch_events(&hex::encode(id), &self.address, &self.token, self.ttl)
+            .await
+    }
+
+    async fn stop_channel(&self, channel: &Channel) {
+        match self.client.stop_channel(channel).await {
+            Ok(()) => log::info!("stopped push notification channel {}", channel.id),
+            Err(err) => log::warn!(
+                "failed to stop push notification channel {}: {err}",
+                channel.id
+            ),
+        }
+    }
+}
+
+/// Returns the time until `channel` needs to be renewed. Channels without expiration are renewed
+/// after the requested `ttl`.
+fn renew_delay(
+    channel: &Channel,
+    now: Timestamp,
+    renew_before: Duration,
+    ttl: Duration,
+) -> Duration {
+    let remaining = match channel.expires_at() {
+        Some(expires_at) => Duration::try_from(expires_at.duration_since(now)).unwrap_or_default(),
+        None => ttl,
+    };
+
+    remaining.saturating_sub(renew_before).max(RETRY_DELAY)
+}
+
+/// A handle for stopping a `ChannelWatcher` task.
+pub struct WatchTaskHandle {
+    join_handle: JoinHandle<()>,
+    stop_tx: Sender<()>,
+}
+
+impl WatchTaskHandle {
+    /// Stops the watcher task and the current channel. Blocks until the background task is
+    /// finished.
+    pub async fn stop(self) -> io::Result<()> {
+        if self.stop_tx.send(()).is_ok() {
+            self.join_handle.await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jiff::SignedDuration;
+    use std::collections::HashMap;
+
+    fn headers(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let headers: HashMap<String, String> = pairs
+            .iter()
+            .map(|(name, value)| (name.to_lowercase(), value.to_string()))
+            .collect();
+
+        move |name| headers.get(&name.to_lowercase()).cloned()
+    }
+
+    #[test]
+    fn notification() {
+        let notification = Notification::from_headers(
+            headers(&[
+                ("x-goog-channel-id", "abc"),
+                ("x-goog-channel-token", "secret"),
+                ("x-goog-resource-state", "exists"),
+                ("x-goog-message-number", "2"),
+            ]),
+            "secret",
+        )
+        .unwrap();
+
+        assert_eq!(
+            notification,
+            Notification {
+                channel_id: "abc".into(),
+                resource_state: "exists".into(),
+                message_number: Some(2),
+            }
+        );
+        assert!(!notification.is_sync());
+
+        let sync = Notification::from_headers(
+            headers(&[
+                ("x-goog-channel-id", "abc"),
+                ("x-goog-channel-token", "secret"),
+                ("x-goog-resource-state", "sync"),
+            ]),
+            "secret",
+        )
+        .unwrap();
+        assert!(sync.is_sync());
+        assert_eq!(sync.message_number, None);
+    }
+
+    #[test]
+    fn invalid_notifications() {
+        assert_eq!(
+            Notification::from_headers(
+                headers(&[
+                    ("x-goog-channel-id", "abc"),
+                    ("x-goog-channel-token", "guessed"),
+                    ("x-goog-resource-state", "exists"),
+                ]),
+                "secret",
+            ),
+            Err(NotificationError::InvalidToken)
+        );
+        assert_eq!(
+            Notification::from_headers(
+                headers(&[
+                    ("x-goog-channel-id", "abc"),
+                    ("x-goog-resource-state", "exists")
+                ]),
+                "secret",
+            ),
+            Err(NotificationError::MissingHeader(CHANNEL_TOKEN_HEADER))
+        );
+        assert_eq!(
+            Notification::from_headers(
+                headers(&[
+                    ("x-goog-channel-id", "abc"),
+                    ("x-goog-channel-token", "secret")
+                ]),
+                "secret",
+            ),
+            Err(NotificationError::MissingHeader(RESOURCE_STATE_HEADER))
+        );
+    }
+
+    #[test]
+    fn renewal() {
+        let now: Timestamp = "2025-03-01T12:00:00Z".parse().unwrap();
+        let day = Duration::from_secs(24 * 60 * 60);
+        let channel = |expires_at: Option<Timestamp>| Channel {
+            id: "abc".into(),
+            resource_id: "def".into(),
+            expiration: expires_at.map(|time| time.as_millisecond().to_string()),
+        };
+
+        // Renewed a day before expiry.
+        assert_eq!(
+            renew_delay(
+                &channel(Some(now + SignedDuration::from_hours(7 * 24))),
+                now,
+                day,
+                day
+            ),
+            6 * day
+        );
+        // Channels without expiration are renewed according to the requested TTL.
+        assert_eq!(renew_delay(&channel(None), now, day, 7 * day), 6 * day);
+        // Short lived channels are not renewed in a tight loop.
+        assert_eq!(
+            renew_delay(
+                &channel(Some(now + SignedDuration::from_mins(1))),
+                now,
+                day,
+                day
+            ),
+            RETRY_DELAY
+        );
+    }
+}
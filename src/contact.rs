@@ -0,0 +1,571 @@
+//! Contact form for private bookings, band applications and other requests.
+//!
+//! Requests are validated server-side and delivered as email to the configured recipient, either
+//! via SMTP or by saving them to a mailbox directory. The email is rendered from the
+//! `contact/request.txt` template, which sets the `subject` variable at the top level.
+//!
+//! Spam is kept out without captchas: a hidden honeypot field has to stay empty, and every form
+//! carries a signed timestamp, so that submissions which come too fast are dropped. In addition,
+//! the number of requests per client is limited. Clients are identified by a keyed hash of their
+//! IP address, so that no addresses are kept in memory. The same [`SpamGuard`] protects the
+//! newsletter signup.
+
+use crate::{ContactConfig, Error, Result};
+use hmac::{Hmac, Mac};
+use jiff::civil::Date;
+use jiff::{SignedDuration, Timestamp};
+use lettre::message::Mailbox;
+use lettre::message::header::ContentType;
+use lettre::{
+    Address, AsyncFileTransport, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use minijinja::{Environment, context};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Mutex;
+
+/// Path of the contact page.
+pub const PATH: &str = "/kontakt";
+
+/// Template of the email for a request.
+const REQUEST_TEMPLATE: &str = "contact/request.txt";
+
+const MAX_NAME_LEN: usize = 100;
+const MAX_PHONE_LEN: usize = 50;
+const MIN_MESSAGE_LEN: usize = 10;
+const MAX_MESSAGE_LEN: usize = 5000;
+
+/// Kind of a contact request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestKind {
+    /// Booking the venue for a private event.
+    Booking,
+    /// A band applying for a concert.
+    Band,
+    /// Anything else.
+    Other,
+}
+
+impl RequestKind {
+    /// Parses the value of the select field in the form.
+    fn parse(value: &str) -> Option<RequestKind> {
+        match value {
+            "booking" => Some(RequestKind::Booking),
+            "band" => Some(RequestKind::Band),
+            "other" => Some(RequestKind::Other),
+            _ => None,
+        }
+    }
+
+    /// Returns the human-readable name of the kind.
+    pub fn label(self) -> &'static str {
+        match self {
+            RequestKind::Booking => "Private Veranstaltung",
+            RequestKind::Band => "Bandbewerbung",
+            RequestKind::Other => "Sonstiges",
+        }
+    }
+}
+
+/// The submitted contact form. All fields are kept as entered, so that the form can be shown
+/// again if it is invalid.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ContactForm {
+    pub kind: String,
+    pub name: String,
+    pub email: String,
+    pub phone: String,
+    /// Desired date of a booking, as entered into a date input (`YYYY-MM-DD`).
+    pub date: String,
+    pub message: String,
+    /// Honeypot field, which is hidden from humans.
+    #[serde(skip_serializing)]
+    pub website: String,
+    /// Signed time at which the form was rendered.
+    #[serde(skip_serializing)]
+    pub token: String,
+}
+
+/// A validated contact request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContactRequest {
+    pub kind: RequestKind,
+    pub name: String,
+    pub email: Address,
+    pub phone: Option<String>,
+    pub date: Option<Date>,
+    pub message: String,
+}
+
+/// Problems with a submitted form, keyed by field name.
+pub type ValidationErrors = BTreeMap<&'static str, &'static str>;
+
+/// Trims a single line input. Returns `None` if it contains control characters.
+fn single_line(value: &str) -> Option<&str> {
+    let value = value.trim();
+    (!value.chars().any(char::is_control)).then_some(value)
+}
+
+impl ContactForm {
+    /// Validates the form. Dates of bookings have to be after `today`.
+    pub fn validate(&self, today: Date) -> Result<ContactRequest, ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+
+        let kind = RequestKind::parse(&self.kind);
+        if kind.is_none() {
+            errors.insert("kind", "Bitte wähle aus, worum es geht.");
+        }
+
+        let name = single_line(&self.name).unwrap_or_default();
+        if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+            errors.insert("name", "Bitte gib deinen Namen an.");
+        }
+
+        let email = self.email.trim().parse::<Address>().ok();
+        if email.is_none() {
+            errors.insert("email", "Bitte gib eine gültige E-Mail-Adresse an.");
+        }
+
+        let phone = single_line(&self.phone);
+        if phone.is_none_or(|phone| phone.chars().count() > MAX_PHONE_LEN) {
+            errors.insert("phone", "Bitte gib eine gültige Telefonnummer an.");
+        }
+
+        let date = match self.date.trim() {
+            "" => None,
+            date => match date.parse::<Date>() {
+                Ok(date) if date > today => Some(date),
+                _ => {
+                    errors.insert("date", "Bitte gib ein Datum in der Zukunft an.");
+                    None
+                }
+            },
+        };
+
+        let message = self.message.trim();
+        if !(MIN_MESSAGE_LEN..=MAX_MESSAGE_LEN).contains(&message.chars().count()) {
+            errors.insert(
+                "message",
+                "Bitte schreib uns eine Nachricht mit 10 bis 5000 Zeichen.",
+            );
+        }
+
+        match (kind, email) {
+            (Some(kind), Some(email)) if errors.is_empty() => Ok(ContactRequest {
+                kind,
+                name: name.into(),
+                email,
+                phone: phone.filter(|phone| !phone.is_empty()).map(String::from),
+                date,
+                message: message.into(),
+            }),
+            _ => Err(errors),
+        }
+    }
+}
+
+/// Outcome of the spam checks of a submission.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// The submission looks like it was made by a human.
+    Accepted,
+    /// The submission is spam and should be dropped silently.
+    Spam,
+    /// The form is too old or its token is invalid, e.g. because it was rendered before a
+    /// restart. The form should be shown again.
+    Expired,
+}
+
+/// Limits the number of requests per client within a sliding time window.
+#[derive(Debug, Default)]
+pub(crate) struct RateLimiter {
+    requests: HashMap<String, VecDeque<Timestamp>>,
+}
+
+impl RateLimiter {
+    /// Records a request of `client` if it is within the limit. Returns whether it is allowed.
+    pub(crate) fn allow(
+        &mut self,
+        client: &str,
+        limit: u32,
+        window: SignedDuration,
+        now: Timestamp,
+    ) -> bool {
+        if self.is_limited(client, limit, window, now) {
+            return false;
+        }
+
+        self.requests
+            .entry(client.into())
+            .or_default()
+            .push_back(now);
+        true
+    }
+
+    /// Returns whether `client` reached the limit, without recording a request.
+    pub(crate) fn is_limited(
+        &mut self,
+        client: &str,
+        limit: u32,
+        window: SignedDuration,
+        now: Timestamp,
+    ) -> bool {
+        // Forget clients without recent requests, so that the map doesn't grow without bounds.
+        self.requests.retain(|_, times| {
+            while times.front().is_some_and(|time| *time <= now - window) {
+                times.pop_front();
+            }
+            !times.is_empty()
+        });
+
+        self.requests
+            .get(client)
+            .is_some_and(|times| times.len() >= limit as usize)
+    }
+}
+
+/// Spam checks and rate limiting of a public form.
+pub struct SpamGuard {
+    /// Random key for signing form tokens and hashing client IPs. Forms rendered before a
+    /// restart are treated as expired.
+    key: [u8; 32],
+    min_fill: SignedDuration,
+    max_age: SignedDuration,
+    rate_limit: u32,
+    rate_limit_window: SignedDuration,
+    limiter: Mutex<RateLimiter>,
+}
+
+impl SpamGuard {
+    /// Creates a guard which drops submissions made less than `min_fill_seconds` after the form
+    /// was rendered, expires forms after `max_form_age_seconds` and allows `rate_limit` requests
+    /// per client within `rate_limit_window_seconds`.
+    pub fn new(
+        min_fill_seconds: u64,
+        max_form_age_seconds: u64,
+        rate_limit: u32,
+        rate_limit_window_seconds: u64,
+    ) -> Result<SpamGuard> {
+        let mut key = [0; 32];
+        getrandom::fill(&mut key)
+            .map_err(|err| Error::Contact(format!("failed to generate key: {err}")))?;
+
+        Ok(SpamGuard {
+            key,
+            min_fill: SignedDuration::from_secs(min_fill_seconds as i64),
+            max_age: SignedDuration::from_secs(max_form_age_seconds as i64),
+            rate_limit,
+            rate_limit_window: SignedDuration::from_secs(rate_limit_window_seconds as i64),
+            limiter: Mutex::new(RateLimiter::default()),
+        })
+    }
+
+    fn mac(&self, purpose: &str, value: &str) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any size");
+        mac.update(purpose.as_bytes());
+        mac.update(b":");
+        mac.update(value.as_bytes());
+        mac
+    }
+
+    /// Returns the token for a form rendered at `now`.
+    pub fn form_token(&self, now: Timestamp) -> String {
+        let time = now.as_second().to_string();
+        let signature = hex::encode(self.mac("form", &time).finalize().into_bytes());
+        format!("{time}.{signature}")
+    }
+
+    /// Checks a submission with the given honeypot field and form token for spam.
+    pub fn check(&self, honeypot: &str, token: &str, now: Timestamp) -> Verdict {
+        if !honeypot.is_empty() {
+            return Verdict::Spam;
+        }
+
+        let Some(rendered_at) = token.split_once('.').and_then(|(time, signature)| {
+            let signature = hex::decode(signature).ok()?;
+            self.mac("form", time).verify_slice(&signature).ok()?;
+            Timestamp::from_second(time.parse().ok()?).ok()
+        }) else {
+            return Verdict::Expired;
+        };
+
+        let age = now.duration_since(rendered_at);
+
+        if age < self.min_fill {
+            Verdict::Spam
+        } else if age > self.max_age {
+            Verdict::Expired
+        } else {
+            Verdict::Accepted
+        }
+    }
+
+    /// Records a request of the client with the IP address `ip`. Returns whether it is within
+    /// the rate limit.
+    pub fn allow(&self, ip: &str, now: Timestamp) -> bool {
+        let client = hex::encode(self.mac("client", ip).finalize().into_bytes());
+
+        self.limiter
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .allow(&client, self.rate_limit, self.rate_limit_window, now)
+    }
+}
+
+/// Delivers emails either via SMTP or to a mailbox directory.
+enum Transport {
+    Smtp(AsyncSmtpTransport<Tokio1Executor>),
+    Mailbox(AsyncFileTransport<Tokio1Executor>),
+}
+
+/// Checks and delivers contact requests.
+pub struct Contact {
+    to: Mailbox,
+    from: Mailbox,
+    transport: Transport,
+    templates: Environment<'static>,
+    guard: SpamGuard,
+}
+
+impl Contact {
+    /// Creates the contact form handling from its configuration. The email templates are
+    /// loaded from `templates`.
+    pub fn new(config: ContactConfig, templates: Environment<'static>) -> Result<Contact> {
+        let parse = |value: &str| {
+            value
+                .parse()
+                .map_err(|err| Error::Contact(format!("invalid mailbox `{value}`: {err}")))
+        };
+        let to = parse(&config.to)?;
+        let from = parse(&config.from)?;
+
+        let transport = match (&config.mailbox_dir, config.smtp_url.as_deref()) {
+            (Some(dir), _) => {
+                std::fs::create_dir_all(dir)?;
+                Transport::Mailbox(AsyncFileTransport::new(dir))
+            }
+            (None, Some(smtp_url)) => Transport::Smtp(
+                AsyncSmtpTransport::<Tokio1Executor>::from_url(smtp_url)
+                    .map_err(|err| Error::Contact(format!("invalid SMTP URL: {err}")))?
+                    .build(),
+            ),
+            (None, None) => {
+                return Err(Error::Contact(
+                    "contact.smtp_url or contact.mailbox_dir must be set".into(),
+                ));
+            }
+        };
+
+        let guard = SpamGuard::new(
+            config.min_fill_seconds,
+            config.max_form_age_seconds,
+            config.rate_limit,
+            config.rate_limit_window_seconds,
+        )?;
+
+        Ok(Contact {
+            to,
+            from,
+            transport,
+            templates,
+            guard,
+        })
+    }
+
+    /// Returns the token for a form rendered at `now`.
+    pub fn form_token(&self, now: Timestamp) -> String {
+        self.guard.form_token(now)
+    }
+
+    /// Checks a submission for spam.
+    pub fn check(&self, form: &ContactForm, now: Timestamp) -> Verdict {
+        self.guard.check(&form.website, &form.token, now)
+    }
+
+    /// Records a request of the client with the IP address `ip`. Returns whether it is within
+    /// the rate limit.
+    pub fn allow(&self, ip: &str, now: Timestamp) -> bool {
+        self.guard.allow(ip, now)
+    }
+
+    /// Renders the email for a request. Returns the subject and the body.
+    fn render(&self, request: &ContactRequest) -> Result<(String, String)> {
+        let ctx = context! {
+            kind => request.kind.label(),
+            name => request.name,
+            email => request.email.to_string(),
+            phone => request.phone,
+            date => request.date.map(|date| date.strftime("%d.%m.%Y").to_string()),
+            message => request.message,
+        };
+
+        let rendered = self
+            .templates
+            .get_template(REQUEST_TEMPLATE)
+            .and_then(|tmpl| tmpl.render_captured(&ctx))
+            .map_err(|err| Error::Contact(format!("failed to render email template: {err:#}")))?;
+
+        let subject = rendered
+            .state()
+            .lookup("subject")
+            .map(|subject| subject.to_string())
+            .ok_or_else(|| Error::Contact("email template does not set a subject".into()))?;
+
+        Ok((subject, rendered.into_output()))
+    }
+
+    /// Delivers a request to the recipient. Replies go to the sender of the request.
+    pub async fn send(&self, request: &ContactRequest) -> Result<()> {
+        let (subject, body) = self.render(request)?;
+
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(self.to.clone())
+            .reply_to(Mailbox::new(
+                Some(request.name.clone()),
+                request.email.clone(),
+            ))
+            .subject(subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(body)
+            .map_err(|err| Error::Contact(format!("failed to build email: {err}")))?;
+
+        match &self.transport {
+            Transport::Smtp(transport) => transport
+                .send(message)
+                .await
+                .map(|_| ())
+                .map_err(|err| Error::Contact(format!("failed to send email: {err}"))),
+            Transport::Mailbox(transport) => transport
+                .send(message)
+                .await
+                .map(|_| ())
+                .map_err(|err| Error::Contact(format!("failed to save email: {err}"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn form() -> ContactForm {
+        ContactForm {
+            kind: "booking".into(),
+            name: " Erika Mustermann ".into(),
+            email: "erika@example.com".into(),
+            phone: "".into(),
+            date: "2025-06-14".into(),
+            message: "Wir möchten unseren Geburtstag bei euch feiern.".into(),
+            ..Default::default()
+        }
+    }
+
+    fn contact(dir: &std::path::Path) -> Contact {
+        let mut templates = Environment::new();
+        templates
+            .add_template(
+                REQUEST_TEMPLATE,
+                "{%- set subject = kind ~ ': ' ~ name -%}\n{{ message }}\n{{ date }}",
+            )
+            .unwrap();
+
+        Contact::new(
+            ContactConfig {
+                enabled: true,
+                mailbox_dir: Some(dir.into()),
+                rate_limit: 2,
+                ..Default::default()
+            },
+            templates,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn validate() {
+        let today = "2025-03-01".parse().unwrap();
+
+        let request = form().validate(today).unwrap();
+        assert_eq!(request.kind, RequestKind::Booking);
+        assert_eq!(request.name, "Erika Mustermann");
+        assert_eq!(request.phone, None);
+        assert_eq!(request.date, Some("2025-06-14".parse().unwrap()));
+
+        let invalid = ContactForm {
+            kind: "party".into(),
+            name: "Erika\r\nBcc: spam@example.com".into(),
+            email: "erika".into(),
+            phone: "0".repeat(51),
+            date: "2025-02-28".into(),
+            message: "Hallo".into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            invalid
+                .validate(today)
+                .unwrap_err()
+                .into_keys()
+                .collect::<Vec<_>>(),
+            ["date", "email", "kind", "message", "name", "phone"]
+        );
+    }
+
+    #[test]
+    fn spam_checks() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let contact = contact(dir);
+        let now: Timestamp = "2025-03-01T12:00:00Z".parse().unwrap();
+
+        let submitted = |form: &ContactForm, after: i64| {
+            contact.check(form, now + SignedDuration::from_secs(after))
+        };
+
+        let mut form = form();
+        form.token = contact.form_token(now);
+        assert_eq!(submitted(&form, 10), Verdict::Accepted);
+        assert_eq!(submitted(&form, 1), Verdict::Spam);
+        assert_eq!(submitted(&form, 2 * 24 * 60 * 60), Verdict::Expired);
+
+        form.website = "https://spam.example.com".into();
+        assert_eq!(submitted(&form, 10), Verdict::Spam);
+
+        form.website.clear();
+        form.token = format!("{}.{}", now.as_second() - 60, "00".repeat(32));
+        assert_eq!(submitted(&form, 10), Verdict::Expired);
+        form.token = "garbage".into();
+        assert_eq!(submitted(&form, 10), Verdict::Expired);
+
+        assert!(contact.allow("192.0.2.1", now));
+        assert!(contact.allow("192.0.2.1", now));
+        assert!(!contact.allow("192.0.2.1", now));
+        assert!(contact.allow("192.0.2.2", now));
+        assert!(contact.allow("192.0.2.1", now + SignedDuration::from_hours(1)));
+    }
+
+    #[actix_web::test]
+    async fn send_to_mailbox() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let contact = contact(dir);
+
+        let request = form().validate("2025-03-01".parse().unwrap()).unwrap();
+        contact.send(&request).await.unwrap();
+
+        let files = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        assert_eq!(files.len(), 1);
+
+        let email = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(email.contains("Subject: Private Veranstaltung: Erika Mustermann"));
+        assert!(email.contains("Reply-To: \"Erika Mustermann\" <erika@example.com>"));
+        assert!(email.contains("To: \"Alhambra Luckenwalde\" <info@alhambra-luckenwalde.de>"));
+        assert!(email.contains("14.06.2025"));
+    }
+}
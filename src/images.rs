@@ -0,0 +1,730 @@
+use crate::calendar::{Event, EventSource};
+use crate::{Error, ImageConfig, Result};
+use image::{DynamicImage, ImageReader, Limits, imageops::FilterType};
+use jiff::{SignedDuration, Timestamp};
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::{StatusCode, redirect};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::io::Cursor;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+use url::Url;
+
+/// URL path under which processed images are served.
+pub const URL_PREFIX: &str = "/images";
+
+/// Directory containing static files which may be referenced as image sources.
+const STATIC_DIR: &str = "./static";
+
+/// Maximum width and height of a source image in pixels.
+const MAX_SOURCE_DIMENSION: u32 = 12_000;
+
+/// Maximum memory in bytes which may be allocated while decoding a source image.
+const MAX_DECODER_ALLOC: u64 = 512 * 1024 * 1024;
+
+/// Unused files are kept in the cache for this long after they were written, e.g. for pages which
+/// were rendered before a sync.
+const PRUNE_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A poster image which is available in multiple widths.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Poster {
+    /// Available variants, ordered by width.
+    pub variants: Vec<PosterVariant>,
+}
+
+/// A poster from the cache.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedPoster {
+    /// The processed poster.
+    pub poster: Poster,
+    /// Whether the source is due to be fetched again.
+    pub stale: bool,
+}
+
+/// A single resized variant of a poster.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct PosterVariant {
+    /// The width in pixels.
+    pub width: u32,
+    /// The URL of the variant.
+    pub url: String,
+}
+
+impl Poster {
+    /// Returns the value for the `srcset` attribute of an `img` tag.
+    pub fn srcset(&self) -> String {
+        self.variants
+            .iter()
+            .map(|variant| format!("{} {}w", variant.url, variant.width))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Returns the URL of the largest variant, e.g. as fallback for the `src` attribute.
+    pub fn src(&self) -> Option<&str> {
+        self.variants.last().map(|variant| variant.url.as_str())
+    }
+
+    /// Returns the path of the largest variant in the image cache directory `cache_dir`.
+    pub fn path(&self, cache_dir: &Path) -> Option<PathBuf> {
+        Some(cache_dir.join(self.file_name()?))
+    }
+
+    /// Returns the cache key of the poster.
+    fn key(&self) -> Option<&str> {
+        self.file_name()?.split('-').next()
+    }
+
+    fn file_name(&self) -> Option<&str> {
+        self.src()?.strip_prefix(URL_PREFIX)?.strip_prefix('/')
+    }
+
+    fn new(key: &str, widths: Vec<u32>) -> Poster {
+        Poster {
+            variants: widths
+                .into_iter()
+                .map(|width| PosterVariant {
+                    width,
+                    url: format!("{URL_PREFIX}/{key}-{width}.webp"),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// What is known about an image source from the last time it was fetched.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct SourceState {
+    /// The cache key of the image content.
+    key: String,
+    /// The `ETag` header of the response, if any.
+    etag: Option<String>,
+    /// The `Last-Modified` header of the response, if any.
+    last_modified: Option<String>,
+    /// The time when the source was fetched or revalidated.
+    checked_at: Timestamp,
+}
+
+/// The result of fetching an image source.
+enum Fetched {
+    /// The source did not change since it was fetched last.
+    NotModified,
+    /// The content of the source.
+    Image {
+        bytes: Vec<u8>,
+        etag: Option<String>,
+        last_modified: Option<String>,
+    },
+}
+
+/// Fetches poster images, resizes them to the configured widths, converts them to WebP and stores
+/// the results in a local cache directory.
+///
+/// Supported image sources are:
+///
+/// - `http://` and `https://` URLs of hosts listed in `allowed_hosts`.
+/// - Absolute paths of files under `/static/`.
+/// - `drive:<file-id>` for attachments which are fetched via the event source.
+///
+/// Processed images are cached by content. Sources are fetched again after
+/// `refresh_interval_seconds`, using conditional requests for URLs, so that a poster which is
+/// replaced at the same URL or Drive file is updated.
+pub struct ImagePipeline {
+    config: ImageConfig,
+    client: reqwest::Client,
+}
+
+impl ImagePipeline {
+    /// Creates a new `ImagePipeline` and ensures the cache directory exists.
+    pub fn new(config: ImageConfig) -> Result<ImagePipeline> {
+        std::fs::create_dir_all(&config.cache_dir)?;
+
+        // Redirects must not lead to hosts which are not allowed.
+        let allowed_hosts = config.allowed_hosts.clone();
+        let redirect_policy = redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= 5 {
+                attempt.error("too many redirects")
+            } else if is_allowed_url(attempt.url(), &allowed_hosts) {
+                attempt.follow()
+            } else {
+                let err = format!("redirect to {} is not allowed", attempt.url());
+                attempt.error(err)
+            }
+        });
+
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(30))
+            .redirect(redirect_policy)
+            .build()?;
+
+        Ok(ImagePipeline { config, client })
+    }
+
+    /// Processes an image source into a `Poster`, reusing previously processed images from the
+    /// cache.
+    ///
+    /// If a source can't be fetched again after `refresh_interval_seconds`, the previously
+    /// processed image is kept.
+    pub async fn process(&self, source: &str, event_source: &dyn EventSource) -> Result<Poster> {
+        let state_path = self.state_path(source);
+        let cached = self.read_cached(source).await;
+        let now = Timestamp::now();
+
+        if let Some((state, widths)) = &cached
+            && !self.is_stale(state, now)
+        {
+            return Ok(Poster::new(&state.key, widths.clone()));
+        }
+
+        let validators = cached.as_ref().map(|(state, _)| state);
+        let fetched = match self.fetch(source, event_source, validators).await {
+            Ok(fetched) => fetched,
+            Err(err) => match cached {
+                Some((state, widths)) => {
+                    log::warn!("failed to refresh image {source}, keeping cached image: {err}");
+                    return Ok(Poster::new(&state.key, widths));
+                }
+                None => return Err(err),
+            },
+        };
+
+        let (state, widths) = match (fetched, cached) {
+            (Fetched::NotModified, Some((state, widths))) => (
+                SourceState {
+                    checked_at: now,
+                    ..state.clone()
+                },
+                widths,
+            ),
+            (Fetched::NotModified, None) => {
+                return Err(Error::ImagePipeline(format!(
+                    "unexpected response for image {source}: not modified"
+                )));
+            }
+            (
+                Fetched::Image {
+                    bytes,
+                    etag,
+                    last_modified,
+                },
+                _,
+            ) => {
+                let key = cache_key(&bytes);
+                let widths = match self.read_manifest(&key).await {
+                    Some(widths) => widths,
+                    None => {
+                        log::info!("processing image {source}");
+                        self.resize_and_store(&key, bytes).await?
+                    }
+                };
+
+                let state = SourceState {
+                    key,
+                    etag,
+                    last_modified,
+                    checked_at: now,
+                };
+                (state, widths)
+            }
+        };
+
+        write_json(&state_path, &state).await?;
+
+        Ok(Poster::new(&state.key, widths))
+    }
+
+    /// Returns the processed poster of a source from the cache without fetching it, even if it
+    /// is due to be refreshed.
+    pub async fn cached(&self, source: &str) -> Option<CachedPoster> {
+        let (state, widths) = self.read_cached(source).await?;
+
+        Some(CachedPoster {
+            stale: self.is_stale(&state, Timestamp::now()),
+            poster: Poster::new(&state.key, widths),
+        })
+    }
+
+    fn state_path(&self, source: &str) -> PathBuf {
+        self.config
+            .cache_dir
+            .join(format!("{}.source.json", cache_key(source.as_bytes())))
+    }
+
+    /// Returns the state of a source and the widths of its processed image, if both are cached.
+    async fn read_cached(&self, source: &str) -> Option<(SourceState, Vec<u32>)> {
+        let state: SourceState = read_json(&self.state_path(source)).await?;
+        let widths = self.read_manifest(&state.key).await?;
+        Some((state, widths))
+    }
+
+    /// Returns whether a source needs to be fetched again.
+    fn is_stale(&self, state: &SourceState, now: Timestamp) -> bool {
+        let refresh_interval =
+            SignedDuration::from_secs(self.config.refresh_interval_seconds as i64);
+        now.duration_since(state.checked_at) >= refresh_interval
+    }
+
+    /// Deletes cached files which are not used by any of `events`, unless they were written
+    /// recently.
+    pub async fn prune(&self, events: &[Event]) -> Result<()> {
+        let mut keep = HashSet::new();
+
+        for event in events {
+            if let Some(source) = &event.image {
+                keep.insert(cache_key(source.as_bytes()));
+            }
+
+            if let Some(key) = event.poster.as_ref().and_then(Poster::key) {
+                keep.insert(key.to_string());
+            }
+        }
+
+        let mut entries = tokio::fs::read_dir(&self.config.cache_dir).await?;
+
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            let name = entry.file_name();
+
+            // Subdirectories like `og` are managed elsewhere.
+            let (true, Some(name)) = (metadata.is_file(), name.to_str()) else {
+                continue;
+            };
+
+            let key = name.split(['-', '.']).next().unwrap_or_default();
+            let recent = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .is_none_or(|age| age < PRUNE_AFTER);
+
+            if !keep.contains(key) && !recent {
+                log::debug!("removing unused cached image {name}");
+                tokio::fs::remove_file(entry.path()).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the widths of the processed image with the given key, if it is in the cache.
+    async fn read_manifest(&self, key: &str) -> Option<Vec<u32>> {
+        read_json(&self.config.cache_dir.join(format!("{key}.json"))).await
+    }
+
+    /// Resizes an image and stores its variants and manifest in the cache. Returns the widths.
+    async fn resize_and_store(&self, key: &str, bytes: Vec<u8>) -> Result<Vec<u32>> {
+        let config = self.config.clone();
+        let task_key = key.to_string();
+
+        let widths =
+            tokio::task::spawn_blocking(move || resize_and_store(&config, &task_key, &bytes))
+                .await
+                .map_err(|err| Error::ImagePipeline(err.to_string()))??;
+
+        // The manifest is written last, so its presence means that all variants exist.
+        write_json(&self.config.cache_dir.join(format!("{key}.json")), &widths).await?;
+
+        Ok(widths)
+    }
+
+    /// Fetches the raw bytes of an image source. Sources with a `cached` state are only fetched
+    /// if they changed, if the source supports conditional requests.
+    async fn fetch(
+        &self,
+        source: &str,
+        event_source: &dyn EventSource,
+        cached: Option<&SourceState>,
+    ) -> Result<Fetched> {
+        let max_bytes = self.config.max_source_bytes;
+        let too_large = || Error::ImagePipeline(format!("image {source} is too large"));
+
+        if let Some(file_id) = source.strip_prefix("drive:") {
+            return Ok(Fetched::Image {
+                bytes: event_source.fetch_attachment(file_id, max_bytes).await?,
+                etag: None,
+                last_modified: None,
+            });
+        }
+
+        if let Some(path) = source.strip_prefix("/static/") {
+            let bytes = tokio::fs::read(static_path(path)?).await?;
+            if bytes.len() > max_bytes {
+                return Err(too_large());
+            }
+
+            return Ok(Fetched::Image {
+                bytes,
+                etag: None,
+                last_modified: None,
+            });
+        }
+
+        let url = match Url::parse(source) {
+            Ok(url) if is_allowed_url(&url, &self.config.allowed_hosts) => url,
+            Ok(url) if matches!(url.scheme(), "http" | "https") => {
+                return Err(Error::ImagePipeline(format!(
+                    "host of image {source} is not listed in `allowed_hosts`"
+                )));
+            }
+            _ => {
+                return Err(Error::ImagePipeline(format!(
+                    "unsupported image source {source}"
+                )));
+            }
+        };
+
+        let mut req = self.client.get(url);
+        if let Some(state) = cached {
+            if let Some(etag) = &state.etag {
+                req = req.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &state.last_modified {
+                req = req.header(IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+
+        let resp = req.send().await?;
+        if cached.is_some() && resp.status() == StatusCode::NOT_MODIFIED {
+            return Ok(Fetched::NotModified);
+        }
+
+        let mut resp = resp.error_for_status()?;
+        if resp
+            .content_length()
+            .is_some_and(|len| len > max_bytes as u64)
+        {
+            return Err(too_large());
+        }
+
+        let header = |name| {
+            resp.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(String::from)
+        };
+        let etag = header(ETAG);
+        let last_modified = header(LAST_MODIFIED);
+
+        // The body is read in chunks, as the `Content-Length` header may be missing.
+        let mut bytes = Vec::new();
+        while let Some(chunk) = resp.chunk().await? {
+            if bytes.len() + chunk.len() > max_bytes {
+                return Err(too_large());
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+
+        Ok(Fetched::Image {
+            bytes,
+            etag,
+            last_modified,
+        })
+    }
+}
+
+/// Returns whether `url` is an http(s) URL of one of `allowed_hosts` or their subdomains.
+fn is_allowed_url(url: &Url, allowed_hosts: &[String]) -> bool {
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+
+    matches!(url.scheme(), "http" | "https")
+        && allowed_hosts.iter().any(|allowed| {
+            let allowed = allowed.trim_start_matches('.').to_ascii_lowercase();
+            host == allowed
+                || host
+                    .strip_suffix(&allowed)
+                    .is_some_and(|prefix| prefix.ends_with('.'))
+        })
+}
+
+/// Reads a JSON file from the cache. Missing and invalid files are treated as absent.
+async fn read_json<T: serde::de::DeserializeOwned>(path: &Path) -> Option<T> {
+    let bytes = tokio::fs::read(path).await.ok()?;
+
+    match serde_json::from_slice(&bytes) {
+        Ok(value) => Some(value),
+        Err(err) => {
+            log::warn!("ignoring invalid cache file {path:?}: {err}");
+            None
+        }
+    }
+}
+
+/// Writes a JSON file to the cache. Writing it via a temporary file ensures that a sync cancelled
+/// on shutdown never leaves a truncated file behind.
+async fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    let tmp_path = path.with_extension("json.tmp");
+    tokio::fs::write(&tmp_path, serde_json::to_vec(value).unwrap()).await?;
+    tokio::fs::rename(tmp_path, path).await?;
+    Ok(())
+}
+
+/// Resolves a path relative to the static directory, rejecting paths which would escape it.
+fn static_path(path: &str) -> Result<PathBuf> {
+    let path = Path::new(path);
+
+    if !path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Err(Error::ImagePipeline(format!(
+            "invalid static image path {path:?}"
+        )));
+    }
+
+    Ok(Path::new(STATIC_DIR).join(path))
+}
+
+/// Derives a cache key from an image source or the image content.
+fn cache_key(data: &[u8]) -> String {
+    let hash = Sha256::digest(data);
+    hex::encode(&hash[..8])
+}
+
+/// Returns the widths to generate for an image of `original_width`. Images are never upscaled,
+/// so widths larger than the original are replaced by the original width.
+fn target_widths(configured: &[u32], original_width: u32) -> Vec<u32> {
+    let mut widths: Vec<_> = configured
+        .iter()
+        .map(|&width| width.min(original_width))
+        .filter(|&width| width > 0)
+        .collect();
+    widths.sort_unstable();
+    widths.dedup();
+    widths
+}
+
+/// Decodes an image, stores WebP encoded variants for all target widths in the cache directory
+/// and returns the widths.
+fn resize_and_store(config: &ImageConfig, key: &str, bytes: &[u8]) -> Result<Vec<u32>> {
+    // Small files can declare huge dimensions, so limit what the decoder may allocate.
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_SOURCE_DIMENSION);
+    limits.max_image_height = Some(MAX_SOURCE_DIMENSION);
+    limits.max_alloc = Some(MAX_DECODER_ALLOC);
+
+    let mut reader = ImageReader::new(Cursor::new(bytes)).with_guessed_format()?;
+    reader.limits(limits);
+    let image = reader.decode()?;
+    let widths = target_widths(&config.widths, image.width());
+
+    for &width in &widths {
+        let resized = if width < image.width() {
+            image.resize(width, u32::MAX, FilterType::Lanczos3)
+        } else {
+            image.clone()
+        };
+
+        let encoded = encode_webp(&resized, config.quality)?;
+        let path = config.cache_dir.join(format!("{key}-{width}.webp"));
+
+        // Write to a temporary file first to never serve partially written images.
+        let tmp_path = path.with_extension("webp.tmp");
+        std::fs::write(&tmp_path, encoded)?;
+        std::fs::rename(tmp_path, path)?;
+    }
+
+    Ok(widths)
+}
+
+/// Encodes an image as lossy WebP.
+fn encode_webp(image: &DynamicImage, quality: f32) -> Result<Vec<u8>> {
+    let image = if image.color().has_alpha() {
+        DynamicImage::ImageRgba8(image.to_rgba8())
+    } else {
+        DynamicImage::ImageRgb8(image.to_rgb8())
+    };
+
+    let encoder =
+        webp::Encoder::from_image(&image).map_err(|err| Error::ImagePipeline(err.to_string()))?;
+
+    Ok(encoder.encode(quality).to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calendar::StaticEventSource;
+    use image::{GenericImageView, ImageFormat, RgbImage};
+    use std::io::Cursor;
+
+    #[test]
+    fn widths() {
+        assert_eq!(target_widths(&[320, 640, 1280], 2000), vec![320, 640, 1280]);
+        assert_eq!(target_widths(&[1280, 320, 640], 800), vec![320, 640, 800]);
+        assert_eq!(target_widths(&[320, 640], 100), vec![100]);
+    }
+
+    #[test]
+    fn static_paths() {
+        assert_eq!(
+            static_path("images/alhambra.png").unwrap(),
+            Path::new("./static/images/alhambra.png")
+        );
+        assert!(static_path("../config/production.toml").is_err());
+        assert!(static_path("/etc/passwd").is_err());
+    }
+
+    #[test]
+    fn allowed_urls() {
+        let hosts = ["example.com".to_string()];
+        let allowed = |url: &str| is_allowed_url(&Url::parse(url).unwrap(), &hosts);
+
+        assert!(allowed("https://example.com/poster.png"));
+        assert!(allowed("http://cdn.Example.com/poster.png"));
+        assert!(!allowed("https://badexample.com/poster.png"));
+        assert!(!allowed("https://example.com.evil.net/poster.png"));
+        assert!(!allowed("https://169.254.169.254/latest/meta-data"));
+        assert!(!allowed("ftp://example.com/poster.png"));
+        assert!(!is_allowed_url(
+            &Url::parse("https://example.com/").unwrap(),
+            &[]
+        ));
+    }
+
+    #[test]
+    fn decoder_limits() {
+        let mut png = Vec::new();
+        DynamicImage::ImageLuma8(image::GrayImage::new(1, MAX_SOURCE_DIMENSION + 1))
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+
+        let cache_dir = tempfile::tempdir().unwrap();
+        let config = ImageConfig {
+            cache_dir: cache_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            resize_and_store(&config, "limits", &png),
+            Err(Error::Image(_))
+        ));
+    }
+
+    #[test]
+    fn srcset() {
+        let poster = Poster {
+            variants: vec![
+                PosterVariant {
+                    width: 320,
+                    url: "/images/a-320.webp".into(),
+                },
+                PosterVariant {
+                    width: 640,
+                    url: "/images/a-640.webp".into(),
+                },
+            ],
+        };
+
+        assert_eq!(
+            poster.srcset(),
+            "/images/a-320.webp 320w, /images/a-640.webp 640w"
+        );
+        assert_eq!(poster.src(), Some("/images/a-640.webp"));
+    }
+
+    #[actix_rt::test]
+    async fn process_and_cache() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cache_dir = tmp.path();
+
+        let pipeline = ImagePipeline::new(ImageConfig {
+            cache_dir: cache_dir.to_path_buf(),
+            widths: vec![100, 400],
+            ..Default::default()
+        })
+        .unwrap();
+
+        let png = |width| {
+            let mut png = Vec::new();
+            DynamicImage::ImageRgb8(RgbImage::new(width, 100))
+                .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+                .unwrap();
+            png
+        };
+
+        // An event source serving the source image as attachment.
+        struct Attachment(Vec<u8>);
+
+        #[async_trait::async_trait]
+        impl EventSource for Attachment {
+            async fn fetch_events(&self) -> Result<Vec<crate::calendar::Event>> {
+                Ok(Vec::new())
+            }
+
+            async fn fetch_attachment(&self, _file_id: &str, _max_bytes: usize) -> Result<Vec<u8>> {
+                Ok(self.0.clone())
+            }
+        }
+
+        let poster = pipeline
+            .process("drive:poster", &Attachment(png(200)))
+            .await
+            .unwrap();
+        let key = cache_key(&png(200));
+
+        assert_eq!(
+            poster.srcset(),
+            format!("/images/{key}-100.webp 100w, /images/{key}-200.webp 200w")
+        );
+
+        let variant = image::open(cache_dir.join(format!("{key}-100.webp"))).unwrap();
+        assert_eq!(variant.dimensions(), (100, 50));
+
+        // Processed images are served from the cache without fetching the source again.
+        let no_attachments = StaticEventSource::new(Vec::<crate::calendar::Event>::new());
+        let cached = pipeline
+            .process("drive:poster", &no_attachments)
+            .await
+            .unwrap();
+        assert_eq!(cached, poster);
+
+        // After the refresh interval, a replaced image is processed again.
+        let pipeline = ImagePipeline::new(ImageConfig {
+            cache_dir: cache_dir.to_path_buf(),
+            widths: vec![100, 400],
+            refresh_interval_seconds: 0,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let replaced = pipeline
+            .process("drive:poster", &Attachment(png(300)))
+            .await
+            .unwrap();
+        let replaced_key = cache_key(&png(300));
+        assert_eq!(replaced.key(), Some(replaced_key.as_str()));
+
+        // The cached image is kept if the source can't be fetched.
+        let cached = pipeline
+            .process("drive:poster", &no_attachments)
+            .await
+            .unwrap();
+        assert_eq!(cached, replaced);
+
+        // Files of the replaced image are removed once they are old enough.
+        let event = crate::calendar::Event {
+            image: Some("drive:poster".into()),
+            poster: Some(replaced),
+            ..Default::default()
+        };
+
+        let old = std::time::SystemTime::now() - 2 * PRUNE_AFTER;
+        for entry in std::fs::read_dir(cache_dir).unwrap() {
+            let file = std::fs::File::options()
+                .write(true)
+                .open(entry.unwrap().path())
+                .unwrap();
+            file.set_modified(old).unwrap();
+        }
+
+        pipeline.prune(&[event]).await.unwrap();
+        assert!(!cache_dir.join(format!("{key}-100.webp")).exists());
+        assert!(!cache_dir.join(format!("{key}.json")).exists());
+        assert!(cache_dir.join(format!("{replaced_key}-100.webp")).exists());
+        assert!(cache_dir.join(format!("{replaced_key}.json")).exists());
+    }
+}
@@ -1,43 +1,157 @@
-use actix_files::Files;
-use actix_utils::future::{Ready, ready};
-use actix_web::dev::{self, ServiceRequest, ServiceResponse};
+use actix_files::{Files, NamedFile};
+use actix_utils::future::{Either, Ready, ready};
+use actix_web::dev::{self, Service, ServiceRequest, ServiceResponse};
 use actix_web::error::{
     ErrorBadRequest, ErrorInternalServerError, ErrorNotFound, ErrorUnauthorized,
 };
 use actix_web::http::StatusCode;
-use actix_web::http::header::{self, ContentType};
-use actix_web::middleware::{Compress, Condition, ErrorHandlerResponse, ErrorHandlers, Logger};
+use actix_web::http::header::{
+    self, AcceptEncoding, CacheControl, CacheDirective, ContentEncoding, ContentType, ETag,
+    Encoding, EntityTag, Header, HttpDate, IfModifiedSince, IfNoneMatch, LastModified, Preference,
+};
+use actix_web::middleware::{
+    Compress, Condition, DefaultHeaders, ErrorHandlerResponse, ErrorHandlers, Logger,
+};
 use actix_web::web::{self, Data, Html};
 use actix_web::{
-    App, FromRequest, HttpRequest, HttpResponse, HttpServer, Responder, Result, route,
+    App, FromRequest, HttpMessage, HttpRequest, HttpResponse, HttpServer, Responder, Result, route,
 };
 use actix_web_httpauth::extractors::bearer::BearerAuth;
 use actix_web_httpauth::middleware::HttpAuthentication;
 use actix_web_prom::PrometheusMetricsBuilder;
-use jiff::{Timestamp, ToSpan, Zoned};
+use anyhow::Context;
+use base64::prelude::*;
+use clap::{Parser, Subcommand, ValueEnum};
+use jiff::{Timestamp, ToSpan, Zoned, tz::TimeZone};
 use minijinja::value::Value;
 use minijinja_autoreload::AutoReloader;
 #[cfg(target_os = "linux")]
 use prometheus::process_collector::ProcessCollector;
 use prometheus::{Encoder, Registry, TextEncoder};
-use tokio::time;
-use wohnzimmer::calendar::{Calendar, EventsByYear};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, LazyLock};
+use std::time::{Instant, SystemTime};
+use wohnzimmer::admin::{self, Admin};
+use wohnzimmer::assets::{self, AssetManifest, Precompressed};
+use wohnzimmer::calendar::google::GoogleCalendarClient;
+use wohnzimmer::calendar::local::LocalEventStore;
+use wohnzimmer::calendar::watch::{self, ChannelWatcher, Notification, NotificationError};
+use wohnzimmer::calendar::{Calendar, EventSourceKind, EventsByYear, Readiness};
+use wohnzimmer::contact::{self, Contact};
+use wohnzimmer::images;
+use wohnzimmer::mastodon::MastodonPoster;
+use wohnzimmer::meta::PageMeta;
 use wohnzimmer::metrics::NAMESPACE;
-use wohnzimmer::{AppConfig, MetricsConfig};
+use wohnzimmer::newsletter::{self, Newsletter};
+use wohnzimmer::og::PreviewRenderer;
+use wohnzimmer::reminders::webpush::VapidKey;
+use wohnzimmer::reminders::{self, Reminders};
+use wohnzimmer::webhooks::WebhookDispatcher;
+use wohnzimmer::{
+    AppConfig, CalendarConfig, MetricsConfig, PushConfig, SecurityConfig, ServerConfig, SiteConfig,
+};
+
+mod routes;
+
+/// Directory containing the minijinja templates.
+const TEMPLATE_DIR: &str = "./templates";
+
+/// Directory containing the static files.
+const STATIC_DIR: &str = "./static";
+
+/// Time when the templates were last loaded. This is part of the validators of rendered pages,
+/// because templates change on deploys or, while auto-reloading is enabled, at any time.
+#[derive(Default)]
+struct TemplatesLoadedAt(std::sync::Mutex<Timestamp>);
+
+impl TemplatesLoadedAt {
+    fn get(&self) -> Timestamp {
+        *self.0.lock().unwrap()
+    }
+
+    fn update(&self) {
+        *self.0.lock().unwrap() = Timestamp::now();
+    }
+}
+
+/// Random nonce which allows inline scripts under the Content-Security-Policy of a response.
+///
+/// It is generated per request by the security headers middleware and stored in the request
+/// extensions.
+#[derive(Clone)]
+struct CspNonce(String);
+
+impl CspNonce {
+    /// Generates a nonce. It is URL-safe base64, which templates don't need to escape.
+    fn generate() -> CspNonce {
+        let mut bytes = [0; 16];
+        getrandom::fill(&mut bytes).expect("failed to generate CSP nonce");
+        CspNonce(BASE64_URL_SAFE_NO_PAD.encode(bytes))
+    }
+
+    /// Returns the nonce of the request, if any.
+    fn of(req: &HttpRequest) -> Option<String> {
+        req.extensions()
+            .get::<CspNonce>()
+            .map(|nonce| nonce.0.clone())
+    }
+}
+
+/// Placeholder rendered instead of the CSP nonce into pages of the `PageCache`, so cached pages
+/// never replay the nonce of another response. It is random per process, so event content can't
+/// contain it to obtain the nonce of a response.
+static CSP_NONCE_PLACEHOLDER: LazyLock<String> = LazyLock::new(|| CspNonce::generate().0);
 
 struct MiniJinjaRenderer {
     tmpl_env: Data<AutoReloader>,
+    csp_nonce: Option<String>,
 }
 
 impl MiniJinjaRenderer {
     fn render(&self, tmpl: &str, ctx: impl Into<minijinja::value::Value>) -> Result<Html> {
+        self.render_to_string(tmpl, ctx).map(Html::new)
+    }
+
+    fn render_to_string(
+        &self,
+        tmpl: &str,
+        ctx: impl Into<minijinja::value::Value>,
+    ) -> Result<String> {
+        self.render_with_nonce(tmpl, ctx, self.csp_nonce.as_deref())
+    }
+
+    /// Renders a page for the `PageCache`. It contains a placeholder instead of the CSP nonce,
+    /// which `with_csp_nonce` replaces with the nonce of each response.
+    fn render_cacheable(
+        &self,
+        tmpl: &str,
+        ctx: impl Into<minijinja::value::Value>,
+    ) -> Result<String> {
+        self.render_with_nonce(tmpl, ctx, Some(&CSP_NONCE_PLACEHOLDER))
+    }
+
+    /// Inserts the CSP nonce of the request into a page rendered by `render_cacheable`.
+    fn with_csp_nonce(&self, page: &str) -> String {
+        page.replace(
+            CSP_NONCE_PLACEHOLDER.as_str(),
+            self.csp_nonce.as_deref().unwrap_or_default(),
+        )
+    }
+
+    fn render_with_nonce(
+        &self,
+        tmpl: &str,
+        ctx: impl Into<minijinja::value::Value>,
+        csp_nonce: Option<&str>,
+    ) -> Result<String> {
         self.tmpl_env
             .acquire_env()
             .map_err(|_| ErrorInternalServerError("could not acquire template env"))?
             .get_template(tmpl)
             .map_err(|_| ErrorInternalServerError("could not find template"))?
-            .render(ctx.into())
-            .map(Html::new)
+            .render(minijinja::context! { csp_nonce, ..ctx.into() })
             .map_err(|err| {
                 log::error!("{err}");
                 ErrorInternalServerError("template error")
@@ -51,8 +165,229 @@ impl FromRequest for MiniJinjaRenderer {
 
     fn from_request(req: &HttpRequest, _pl: &mut dev::Payload) -> Self::Future {
         let tmpl_env = <Data<AutoReloader>>::extract(req).into_inner().unwrap();
+        let csp_nonce = CspNonce::of(req);
+
+        ready(Ok(Self {
+            tmpl_env,
+            csp_nonce,
+        }))
+    }
+}
+
+/// Validators for conditional requests of pages which are rendered from calendar events.
+///
+/// Rendered pages only change when the synchronized events change, at midnight when past events
+/// disappear and when templates are loaded again.
+struct Validators {
+    etag: EntityTag,
+    last_modified: HttpDate,
+}
+
+impl Validators {
+    async fn new(req: &HttpRequest, calendar: &Calendar) -> Validators {
+        let reloader = <Data<AutoReloader>>::extract(req).into_inner().unwrap();
+        let templates_loaded_at = <Data<TemplatesLoadedAt>>::extract(req)
+            .into_inner()
+            .unwrap();
+
+        // Acquiring the environment reloads changed templates, which updates their load time.
+        if let Err(err) = reloader.acquire_env() {
+            log::error!("failed to load templates: {err}");
+        }
+
+        let templates_loaded_at = templates_loaded_at.get();
+        let version = calendar.version().await;
+        let today = Zoned::now().start_of_day().unwrap();
+
+        let etag = EntityTag::new_strong(format!(
+            "{}-{}-{}",
+            version.number,
+            today.strftime("%Y%m%d"),
+            templates_loaded_at.as_millisecond()
+        ));
+
+        let last_modified = version
+            .modified
+            .max(today.timestamp())
+            .max(templates_loaded_at);
+        // HTTP dates have a resolution of seconds.
+        let last_modified = Timestamp::from_second(last_modified.as_second()).unwrap();
+
+        Validators {
+            etag,
+            last_modified: HttpDate::from(SystemTime::from(last_modified)),
+        }
+    }
+
+    /// Returns `true` if the client's cached copy of the page is still fresh.
+    fn is_fresh(&self, req: &HttpRequest) -> bool {
+        // If-Modified-Since must be ignored if If-None-Match is present.
+        if req.headers().contains_key(header::IF_NONE_MATCH) {
+            return match IfNoneMatch::parse(req) {
+                Ok(IfNoneMatch::Any) => true,
+                Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&self.etag)),
+                Err(_) => false,
+            };
+        }
+
+        IfModifiedSince::parse(req).is_ok_and(|IfModifiedSince(since)| {
+            SystemTime::from(since) >= SystemTime::from(self.last_modified)
+        })
+    }
+
+    /// Returns a `304 Not Modified` response.
+    fn not_modified(&self) -> HttpResponse {
+        HttpResponse::NotModified()
+            .insert_header(ETag(self.etag.clone()))
+            .insert_header(LastModified(self.last_modified))
+            .finish()
+    }
+
+    /// Returns the response for a rendered page including the validators.
+    fn respond(&self, req: &HttpRequest, body: Html) -> HttpResponse {
+        body.customize()
+            .insert_header(ETag(self.etag.clone()))
+            .insert_header(LastModified(self.last_modified))
+            // Clients may store the page but have to revalidate it before using it.
+            .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+            .respond_to(req)
+            .map_into_boxed_body()
+    }
+}
+
+/// Maximum number of pages kept in the `PageCache`. This bounds memory usage if pages are
+/// requested via many different hosts.
+const PAGE_CACHE_CAPACITY: usize = 256;
+
+/// In-memory cache of rendered event pages.
+///
+/// Pages are cached per generation, which is the ETag of the `Validators`. It changes whenever the
+/// calendar or the templates change, so all cached pages are dropped after a sync changed the
+/// events or templates were reloaded.
+///
+/// Pages are rendered with a placeholder instead of the CSP nonce, see
+/// `MiniJinjaRenderer::render_cacheable`.
+#[derive(Default)]
+struct PageCache {
+    inner: std::sync::Mutex<CachedPages>,
+}
+
+#[derive(Default)]
+struct CachedPages {
+    generation: String,
+    pages: HashMap<String, String>,
+}
+
+impl PageCache {
+    /// Builds the cache key of a page.
+    fn key(tmpl: &str, meta: &PageMeta) -> String {
+        format!("{tmpl}\n{}\n{}", meta.locale, meta.url)
+    }
+
+    /// Returns a cached page, if any.
+    fn get(&self, validators: &Validators, key: &str) -> Option<String> {
+        let inner = self.inner.lock().unwrap();
+
+        if inner.generation != validators.etag.tag() {
+            return None;
+        }
 
-        ready(Ok(Self { tmpl_env }))
+        inner.pages.get(key).cloned()
+    }
+
+    /// Adds a rendered page to the cache.
+    fn insert(&self, validators: &Validators, key: String, html: String) {
+        let mut inner = self.inner.lock().unwrap();
+
+        if inner.generation != validators.etag.tag() {
+            inner.generation = validators.etag.tag().to_string();
+            inner.pages.clear();
+        }
+
+        if inner.pages.len() < PAGE_CACHE_CAPACITY {
+            inner.pages.insert(key, html);
+        }
+    }
+}
+
+/// Adds the configured security headers to a response.
+fn add_security_headers<B>(security: &SecurityConfig, res: &mut ServiceResponse<B>) {
+    let csp = CspNonce::of(res.request())
+        .map(|nonce| security.content_security_policy(&nonce))
+        .unwrap_or_else(|| security.content_security_policy.clone());
+
+    let headers = [
+        (header::CONTENT_SECURITY_POLICY, csp.as_str()),
+        (
+            header::STRICT_TRANSPORT_SECURITY,
+            &security.strict_transport_security,
+        ),
+        (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+        (header::REFERRER_POLICY, &security.referrer_policy),
+        (
+            header::HeaderName::from_static("permissions-policy"),
+            &security.permissions_policy,
+        ),
+    ];
+
+    for (name, value) in headers {
+        if value.is_empty() {
+            continue;
+        }
+
+        match header::HeaderValue::from_str(value) {
+            Ok(value) => {
+                res.headers_mut().insert(name, value);
+            }
+            Err(err) => log::error!("invalid {name} header: {err}"),
+        }
+    }
+}
+
+/// Opens the precompressed variant of the static file `path` which is preferred by the client, if
+/// any.
+fn precompressed_file(req: &HttpRequest, assets: &AssetManifest, path: &str) -> Option<NamedFile> {
+    let accept = AcceptEncoding::parse(req).ok()?;
+
+    let encoding = accept.ranked().into_iter().find_map(|preference| {
+        let candidates: &[Precompressed] = match preference {
+            Preference::Any => &[Precompressed::Brotli, Precompressed::Gzip],
+            Preference::Specific(enc) if enc == Encoding::brotli() => &[Precompressed::Brotli],
+            Preference::Specific(enc) if enc == Encoding::gzip() => &[Precompressed::Gzip],
+            Preference::Specific(_) => &[],
+        };
+
+        candidates
+            .iter()
+            .copied()
+            .find(|&encoding| assets.has_precompressed(path, encoding))
+    })?;
+
+    let extension = std::path::Path::new(path).extension()?.to_str()?;
+    let file_path = format!("{STATIC_DIR}/{path}.{}", encoding.extension());
+
+    let file = NamedFile::open(file_path)
+        .ok()?
+        .set_content_type(actix_files::file_extension_to_mime(extension))
+        .set_content_encoding(match encoding {
+            Precompressed::Brotli => ContentEncoding::Brotli,
+            Precompressed::Gzip => ContentEncoding::Gzip,
+        })
+        .disable_content_disposition();
+
+    Some(file)
+}
+
+/// Returns the base URL used for absolute links, i.e. the canonical URL if configured or the
+/// scheme and host of the request otherwise. The latter is controlled by the client, so
+/// `site.canonical_url` should always be set in production.
+fn base_url(req: &HttpRequest, site: &SiteConfig) -> String {
+    match &site.canonical_url {
+        Some(canonical_url) => canonical_url.clone(),
+        None => {
+            let conn = req.connection_info();
+            format!("{}://{}", conn.scheme(), conn.host())
+        }
     }
 }
 
@@ -61,8 +396,23 @@ async fn render_events(
     tmpl_env: MiniJinjaRenderer,
     tmpl: &str,
     calendar: Data<Calendar>,
+    cache: Data<PageCache>,
     months: i8,
-) -> Result<impl Responder + use<>> {
+    meta: PageMeta,
+) -> Result<HttpResponse> {
+    let validators = Validators::new(&req, &calendar).await;
+
+    if validators.is_fresh(&req) {
+        return Ok(validators.not_modified());
+    }
+
+    let key = PageCache::key(tmpl, &meta);
+
+    if let Some(page) = cache.get(&validators, &key) {
+        let page = tmpl_env.with_csp_nonce(&page);
+        return Ok(validators.respond(&req, Html::new(page)));
+    }
+
     let now = Zoned::now();
     let start = now.start_of_day().unwrap();
     let end = &start + months.months();
@@ -83,13 +433,19 @@ async fn render_events(
         })
         .collect::<indexmap::IndexMap<i16, Vec<Value>>>();
 
-    tmpl_env.render(
+    let page = tmpl_env.render_cacheable(
         tmpl,
         minijinja::context! {
             request_path => req.uri().path(),
-            events_by_year
+            events_by_year,
+            meta
         },
-    )
+    )?;
+
+    let html = tmpl_env.with_csp_nonce(&page);
+    cache.insert(&validators, key, page);
+
+    Ok(validators.respond(&req, Html::new(html)))
 }
 
 #[route("/", method = "GET", method = "HEAD")]
@@ -97,8 +453,11 @@ async fn index(
     req: HttpRequest,
     tmpl_env: MiniJinjaRenderer,
     calendar: Data<Calendar>,
+    cache: Data<PageCache>,
+    site: Data<SiteConfig>,
 ) -> Result<impl Responder> {
-    render_events(req, tmpl_env, "index.html", calendar, 3).await
+    let meta = PageMeta::new(&site, &base_url(&req, &site), req.uri().path(), None);
+    render_events(req, tmpl_env, "index.html", calendar, cache, 3, meta).await
 }
 
 #[route("/events", method = "GET", method = "HEAD")]
@@ -106,18 +465,208 @@ async fn events(
     req: HttpRequest,
     tmpl_env: MiniJinjaRenderer,
     calendar: Data<Calendar>,
+    cache: Data<PageCache>,
+    site: Data<SiteConfig>,
 ) -> Result<impl Responder> {
-    render_events(req, tmpl_env, "events.html", calendar, 12).await
+    let meta = PageMeta::new(
+        &site,
+        &base_url(&req, &site),
+        req.uri().path(),
+        Some("Termine"),
+    );
+    render_events(req, tmpl_env, "events.html", calendar, cache, 12, meta).await
+}
+
+#[route("/events/{id}", method = "GET", method = "HEAD")]
+async fn event_detail(
+    req: HttpRequest,
+    id: web::Path<String>,
+    tmpl_env: MiniJinjaRenderer,
+    calendar: Data<Calendar>,
+    cache: Data<PageCache>,
+    site: Data<SiteConfig>,
+) -> Result<impl Responder> {
+    let validators = Validators::new(&req, &calendar).await;
+
+    let event = calendar
+        .get_event(&id)
+        .await
+        .ok_or_else(|| ErrorNotFound("not found"))?;
+
+    if validators.is_fresh(&req) {
+        return Ok(validators.not_modified());
+    }
+
+    let meta = PageMeta::for_event(&site, &base_url(&req, &site), &event);
+    let key = PageCache::key("event.html", &meta);
+
+    if let Some(page) = cache.get(&validators, &key) {
+        let page = tmpl_env.with_csp_nonce(&page);
+        return Ok(validators.respond(&req, Html::new(page)));
+    }
+
+    let page = tmpl_env.render_cacheable(
+        "event.html",
+        minijinja::context! {
+            request_path => req.uri().path(),
+            event => Value::from_object(event),
+            meta
+        },
+    )?;
+
+    let html = tmpl_env.with_csp_nonce(&page);
+    cache.insert(&validators, key, page);
+
+    Ok(validators.respond(&req, Html::new(html)))
+}
+
+#[route("/events/{id}/og.png", method = "GET", method = "HEAD")]
+async fn event_preview_image(
+    id: web::Path<String>,
+    calendar: Data<Calendar>,
+    renderer: Data<PreviewRenderer>,
+) -> Result<impl Responder> {
+    let event = calendar
+        .get_event(&id)
+        .await
+        .ok_or_else(|| ErrorNotFound("not found"))?;
+
+    // Rendering is CPU bound, so keep it off the async workers.
+    let path = web::block(move || renderer.render(&event))
+        .await?
+        .map_err(|err| {
+            log::error!("failed to render preview image: {err}");
+            ErrorInternalServerError("failed to render preview image")
+        })?;
+
+    // PNG images are not worth compressing.
+    Ok(NamedFile::open_async(path)
+        .await?
+        .set_content_encoding(ContentEncoding::Identity))
 }
 
 #[route("/impressum", method = "GET", method = "HEAD")]
-async fn imprint(req: HttpRequest, tmpl_env: MiniJinjaRenderer) -> Result<impl Responder> {
+async fn imprint(
+    req: HttpRequest,
+    tmpl_env: MiniJinjaRenderer,
+    site: Data<SiteConfig>,
+) -> Result<impl Responder> {
+    let meta = PageMeta::new(
+        &site,
+        &base_url(&req, &site),
+        req.uri().path(),
+        Some("Impressum"),
+    );
+
     tmpl_env.render(
         "imprint.html",
-        minijinja::context! { request_path => req.uri().path() },
+        minijinja::context! { request_path => req.uri().path(), meta },
     )
 }
 
+/// Webhook receiving Google Calendar push notifications, which triggers a sync.
+async fn google_calendar_hook(
+    req: HttpRequest,
+    calendar: Data<Calendar>,
+    config: Data<CalendarConfig>,
+) -> Result<impl Responder> {
+    let push = &config.push;
+
+    // If push notifications are disabled we just pretend the webhook does not exist.
+    let Some(token) = push.token.as_deref().filter(|_| push.enabled) else {
+        return Err(ErrorNotFound("not found"));
+    };
+
+    let header = |name: &str| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned)
+    };
+
+    let notification = Notification::from_headers(header, token).map_err(|err| match err {
+        NotificationError::InvalidToken => ErrorUnauthorized("unauthorized"),
+        NotificationError::MissingHeader(_) => ErrorBadRequest(err.to_string()),
+    })?;
+
+    if notification.is_sync() {
+        log::info!(
+            "push notification channel {} is active",
+            notification.channel_id
+        );
+        return Ok(HttpResponse::Ok().finish());
+    }
+
+    log::debug!(
+        "received push notification {:?} for channel {}",
+        notification.message_number,
+        notification.channel_id
+    );
+
+    // Google expects a quick response, so sync in the background. Notifications often arrive in
+    // bursts, which share a single sync.
+    actix_web::rt::spawn(async move {
+        if let Err(err) = calendar.sync_coalesced().await {
+            log::error!("failed to sync calendar events after push notification: {err}");
+        }
+    });
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Liveness probe which succeeds as long as the server handles requests.
+#[route("/healthz", method = "GET", method = "HEAD")]
+async fn healthz() -> impl Responder {
+    HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(serde_json::json!({ "status": "ok" }))
+}
+
+/// Response of the readiness probe. The route is public, so error details are only available
+/// via `/admin/health`.
+#[derive(Serialize)]
+struct ReadinessReport {
+    status: Readiness,
+    last_success: Option<Timestamp>,
+    last_error_at: Option<Timestamp>,
+    events: usize,
+}
+
+/// Readiness probe which fails until the first successful calendar sync. The calendar is reported
+/// as degraded, but still ready, if the last successful sync is older than
+/// `calendar.degraded_after_syncs` sync periods.
+#[route("/readyz", method = "GET", method = "HEAD")]
+async fn readyz(calendar: Data<Calendar>, config: Data<CalendarConfig>) -> impl Responder {
+    let health = calendar.health().await;
+    let max_age = config.sync_period() * config.degraded_after_syncs;
+    let status = health.readiness(Timestamp::now(), max_age);
+
+    let mut res = match status {
+        Readiness::NotReady => HttpResponse::ServiceUnavailable(),
+        Readiness::Ready | Readiness::Degraded => HttpResponse::Ok(),
+    };
+
+    res.insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(ReadinessReport {
+            status,
+            last_success: health.last_success,
+            last_error_at: health.last_error_at,
+            events: health.events,
+        })
+}
+
+/// Returns the IP address of the client, taken from `server.client_ip_header` behind a proxy.
+fn client_ip(req: &HttpRequest, config: &ServerConfig) -> String {
+    config
+        .client_ip_header
+        .as_deref()
+        .and_then(|name| req.headers().get(name))
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+        .or_else(|| req.peer_addr().map(|addr| addr.ip().to_string()))
+        .unwrap_or_default()
+}
+
 async fn metrics(registry: Data<Registry>) -> Result<impl Responder> {
     let mut buf = Vec::new();
     let metrics_families = registry.gather();
@@ -151,7 +700,7 @@ async fn metrics_auth(
         // Token required.
         Some(token) => match credentials {
             // Valid token.
-            Some(creds) if creds.token() == token => Ok(req),
+            Some(creds) if wohnzimmer::secret_eq(token, creds.token()) => Ok(req),
             // Invalid token.
             Some(_) => Err((ErrorUnauthorized("unauthorized"), req)),
             // Missing token.
@@ -162,42 +711,365 @@ async fn metrics_auth(
     }
 }
 
+/// Command line interface of the wohnzimmer server.
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Starts the HTTP server (default).
+    Serve,
+    /// Fetches events from the configured event source once and prints them.
+    Sync {
+        /// Output format for the fetched events.
+        #[arg(long, value_enum, default_value_t = DumpFormat::Table)]
+        dump: DumpFormat,
+    },
+    /// Configuration related commands.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Template related commands.
+    Templates {
+        #[command(subcommand)]
+        command: TemplatesCommand,
+    },
+    /// Static file related commands.
+    Assets {
+        #[command(subcommand)]
+        command: AssetsCommand,
+    },
+    /// Google Calendar push notification related commands.
+    Push {
+        #[command(subcommand)]
+        command: PushCommand,
+    },
+    /// Newsletter related commands.
+    Newsletter {
+        #[command(subcommand)]
+        command: NewsletterCommand,
+    },
+    /// Web Push reminder related commands.
+    Reminders {
+        #[command(subcommand)]
+        command: RemindersCommand,
+    },
+    /// Admin UI related commands.
+    Admin {
+        #[command(subcommand)]
+        command: AdminCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Loads and validates the application configuration.
+    Check,
+}
+
+#[derive(Subcommand)]
+enum TemplatesCommand {
+    /// Compiles every template to detect syntax errors.
+    Check,
+}
+
+#[derive(Subcommand)]
+enum AssetsCommand {
+    /// Writes brotli and gzip compressed variants of static files, which are served instead of
+    /// compressing responses on the fly.
+    Compress,
+}
+
+#[derive(Subcommand)]
+enum PushCommand {
+    /// Sends a push notification like Google Calendar would to a running server.
+    Simulate {
+        /// The webhook URL. Defaults to the webhook of the server at `server.listen_addr`.
+        #[arg(long)]
+        url: Option<String>,
+        /// The resource state to send, `sync` for the initial notification of a channel.
+        #[arg(long, default_value = "exists")]
+        state: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum NewsletterCommand {
+    /// Prints the digest which would be sent now.
+    Preview {
+        /// Prints the HTML version instead of the plain text version.
+        #[arg(long)]
+        html: bool,
+    },
+    /// Sends the digest now, regardless of the schedule.
+    Send {
+        /// Sends the digest only to this address instead of all subscribers, e.g. for testing.
+        #[arg(long)]
+        to: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum RemindersCommand {
+    /// Generates a VAPID key pair and prints it as configuration.
+    GenerateKeys,
+}
+
+#[derive(Subcommand)]
+enum AdminCommand {
+    /// Reads a password from stdin and prints its hash as configuration of a user.
+    HashPassword {
+        /// The name of the user.
+        user: String,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum DumpFormat {
+    Json,
+    Table,
+}
+
 #[actix_web::main]
 async fn main() -> anyhow::Result<()> {
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
 
-    let config = AppConfig::load()?;
+    let cli = Cli::parse();
 
-    let calendar = Calendar::from_config(&config.calendar).await?;
+    let config = AppConfig::load()?;
 
-    let period = time::Duration::from_secs(config.calendar.sync_period_seconds.unwrap_or(60));
-    let sync_task_handle = calendar.spawn_sync_task(period).await;
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(config).await,
+        Command::Sync { dump } => sync(config, dump).await,
+        Command::Config {
+            command: ConfigCommand::Check,
+        } => check_config(config),
+        Command::Templates {
+            command: TemplatesCommand::Check,
+        } => check_templates(config),
+        Command::Assets {
+            command: AssetsCommand::Compress,
+        } => compress_assets(),
+        Command::Push {
+            command: PushCommand::Simulate { url, state },
+        } => simulate_push(config, url, state).await,
+        Command::Newsletter { command } => run_newsletter_command(config, command).await,
+        Command::Reminders {
+            command: RemindersCommand::GenerateKeys,
+        } => generate_vapid_keys(),
+        Command::Admin {
+            command: AdminCommand::HashPassword { user },
+        } => hash_admin_password(&user),
+    }
+}
 
-    if config.server.template_autoreload {
-        log::info!("template auto-reloading is enabled");
-    } else {
-        log::info!("template auto-reloading is disabled");
+/// Opens the store of the events edited in the admin UI, if the admin UI is enabled. Its events
+/// are merged with the events of the configured event source.
+fn local_event_store(config: &AppConfig) -> anyhow::Result<Option<LocalEventStore>> {
+    if config.admin.users.is_empty() {
+        return Ok(None);
     }
 
-    let mut env: minijinja::Environment<'static> = minijinja::Environment::new();
+    Ok(Some(LocalEventStore::open(
+        &config.calendar.local.database,
+    )?))
+}
+
+/// Creates the template environment shared by all template consumers.
+fn template_env(config: &AppConfig, assets: Arc<AssetManifest>) -> minijinja::Environment<'static> {
+    let mut env = minijinja::Environment::new();
     env.set_auto_escape_callback(|_| minijinja::AutoEscape::None);
-    env.add_global("config", Value::from_serialize(&config));
-    env.add_global("cache_buster", Timestamp::now().as_second());
+    // Secrets are skipped when serializing the configuration, so templates can't leak them.
+    env.add_global("config", Value::from_serialize(config));
+    env.add_function("asset", move |path: &str| assets.url(path));
+    env
+}
+
+/// Creates a template environment which loads templates from the template directory, for
+/// consumers outside of request handling, e.g. emails. Unlike rendered pages, these don't
+/// auto-reload.
+fn loaded_template_env(
+    config: &AppConfig,
+    assets: Arc<AssetManifest>,
+) -> minijinja::Environment<'static> {
+    let mut env = template_env(config, assets);
+    env.set_loader(minijinja::path_loader(TEMPLATE_DIR));
+    env
+}
+
+/// Creates the template environment for rendering pages, which loads templates from `dir`. If
+/// `autoreload` is enabled, templates are reloaded when they change.
+fn template_reloader(
+    env: minijinja::Environment<'static>,
+    dir: impl Into<PathBuf>,
+    autoreload: bool,
+    loaded_at: Data<TemplatesLoadedAt>,
+) -> AutoReloader {
+    let dir = dir.into();
 
     // The closure is invoked every time the environment is outdated to recreate it.
-    let reloader = AutoReloader::new(move |notifier| {
+    AutoReloader::new(move |notifier| {
         let mut env = env.clone();
 
         // if watch_path is never called, no fs watcher is created
-        if config.server.template_autoreload {
-            notifier.watch_path("./templates", true);
+        if autoreload {
+            notifier.watch_path(&dir, true);
         }
 
-        env.set_loader(minijinja::path_loader("./templates"));
+        env.set_loader(minijinja::path_loader(&dir));
+        loaded_at.update();
 
         Ok(env)
+    })
+}
+
+async fn serve(config: AppConfig) -> anyhow::Result<()> {
+    // Refuse to start with a configuration which `config check` rejects.
+    let problems = config.validate();
+    if !problems.is_empty() {
+        for problem in &problems {
+            log::error!("{problem}");
+        }
+
+        anyhow::bail!("found {} configuration problem(s)", problems.len());
+    }
+
+    if config.site.canonical_url.is_none() {
+        log::warn!(
+            "site.canonical_url is not set, absolute URLs in pages are derived from the Host header of requests"
+        );
+    }
+
+    let local_events = local_event_store(&config)?;
+    let calendar = Calendar::from_config(&config.calendar, local_events.clone()).await?;
+    let assets = Arc::new(AssetManifest::load(STATIC_DIR)?);
+
+    let webhooks = if config.webhooks.endpoints.is_empty() {
+        None
+    } else {
+        // Subscribe before the first sync, so that no changes are missed.
+        let changes = calendar.subscribe_changes();
+        Some((WebhookDispatcher::new(config.webhooks.clone())?, changes))
+    };
+
+    let mastodon = if config.mastodon.enabled {
+        let changes = calendar.subscribe_changes();
+        let poster = MastodonPoster::new(
+            config.mastodon.clone(),
+            calendar.clone(),
+            &site_url(&config),
+            &config.calendar.images.cache_dir,
+            loaded_template_env(&config, assets.clone()),
+        )?;
+
+        Some((poster, changes))
+    } else {
+        None
+    };
+
+    let sync_task_handle = calendar
+        .spawn_sync_task(config.calendar.sync_period())
+        .await;
+
+    let watch_task_handle = if config.calendar.push.enabled
+        && matches!(
+            config.calendar.event_source,
+            EventSourceKind::GoogleCalendar
+        ) {
+        let address = config
+            .calendar
+            .push
+            .address(&config.site)
+            .context("calendar.push.address must be set if site.canonical_url is not")?;
+        let client = GoogleCalendarClient::new().await?;
+
+        Some(ChannelWatcher::new(client, address, &config.calendar.push).spawn())
+    } else {
+        if config.calendar.push.enabled {
+            // The webhook still works, which allows trying it with `wohnzimmer push simulate`.
+            log::warn!("push notification channels are only registered for Google Calendar");
+        }
+
+        None
+    };
+    let stop_watch = || async {
+        match watch_task_handle {
+            Some(handle) => handle.stop().await,
+            None => Ok(()),
+        }
+    };
+
+    if config.server.template_autoreload {
+        log::info!("template auto-reloading is enabled");
+    } else {
+        log::info!("template auto-reloading is disabled");
+    }
+
+    let env = template_env(&config, assets.clone());
+
+    let newsletter = if config.newsletter.enabled {
+        log::info!("enabling newsletter at {}", newsletter::PATH);
+        Some(Arc::new(create_newsletter(&config, assets.clone())?))
+    } else {
+        None
+    };
+    let newsletter_task_handle = newsletter
+        .clone()
+        .map(|newsletter| newsletter.spawn_scheduler(calendar.clone()));
+    let stop_newsletter = || async {
+        match newsletter_task_handle {
+            Some(handle) => handle.stop().await,
+            None => Ok(()),
+        }
+    };
+
+    let reminders = if config.reminders.enabled {
+        log::info!("enabling reminders at {}", reminders::PATH);
+        Some(Arc::new(Reminders::new(
+            config.reminders.clone(),
+            &site_url(&config),
+        )?))
+    } else {
+        None
+    };
+    let reminder_task_handle = reminders
+        .clone()
+        .map(|reminders| reminders.spawn_scheduler(calendar.clone()));
+    let stop_reminders = || async {
+        match reminder_task_handle {
+            Some(handle) => handle.stop().await,
+            None => Ok(()),
+        }
+    };
+
+    let contact = if config.contact.enabled {
+        log::info!("enabling contact form at {}", contact::PATH);
+        Some(Contact::new(
+            config.contact.clone(),
+            loaded_template_env(&config, assets.clone()),
+        )?)
+    } else {
+        None
+    };
+
+    let admin = local_events.map(|store| {
+        log::info!("enabling admin UI at {}", admin::PATH);
+        (Admin::new(&config.admin), store)
     });
 
+    let templates_loaded_at = Data::new(TemplatesLoadedAt::default());
+    let reloader = template_reloader(
+        env,
+        TEMPLATE_DIR,
+        config.server.template_autoreload,
+        templates_loaded_at.clone(),
+    );
+
     let registry = Registry::new();
     let prometheus = PrometheusMetricsBuilder::new(NAMESPACE)
         .registry(registry.clone())
@@ -210,26 +1082,192 @@ async fn main() -> anyhow::Result<()> {
         #[cfg(target_os = "linux")]
         registry.register(Box::new(ProcessCollector::for_self()))?;
         calendar.register_metrics(&registry)?;
+
+        if let Some((dispatcher, _)) = &webhooks {
+            dispatcher.register_metrics(&registry)?;
+        }
+
+        if let Some((poster, _)) = &mastodon {
+            poster.register_metrics(&registry)?;
+        }
     }
 
-    let calendar = Data::new(calendar);
+    let webhook_task_handle = webhooks.map(|(dispatcher, changes)| dispatcher.spawn(changes));
+    let stop_webhooks = || async {
+        match webhook_task_handle {
+            Some(handle) => handle.stop().await,
+            None => Ok(()),
+        }
+    };
+
+    let mastodon_task_handle = mastodon.map(|(poster, changes)| poster.spawn(changes));
+    let stop_mastodon = || async {
+        match mastodon_task_handle {
+            Some(handle) => handle.stop().await,
+            None => Ok(()),
+        }
+    };
+
+    let calendar_data = Data::new(calendar.clone());
+    let newsletter_data = newsletter.map(Data::from);
+    let reminders_data = reminders.map(Data::from);
+    let contact_data = contact.map(Data::new);
+    let admin_data = admin.map(|(admin, store)| (Data::new(admin), Data::new(store)));
+    let server_config = Data::new(config.server.clone());
     let reloader = Data::new(reloader);
     let registry = Data::new(registry);
     let metrics_config = Data::new(config.metrics.clone());
+    let admin_config = Data::new(config.admin.clone());
+    let calendar_config = Data::new(config.calendar.clone());
+    let page_cache = Data::new(PageCache::default());
+    let site_config = Data::new(config.site.clone());
+    let preview_renderer = Data::new(PreviewRenderer::new(
+        config.calendar.images.cache_dir.join("og"),
+    )?);
+    let image_config = config.calendar.images.clone();
+    let security = Arc::new(config.server.security.clone());
 
     log::info!("starting HTTP server at {}", config.server.listen_addr);
 
-    HttpServer::new(move || {
+    let server = HttpServer::new(move || {
         App::new()
-            .app_data(calendar.clone())
+            .app_data(calendar_data.clone())
             .app_data(registry.clone())
             .app_data(reloader.clone())
+            .app_data(templates_loaded_at.clone())
             .app_data(metrics_config.clone())
+            .app_data(admin_config.clone())
+            .app_data(calendar_config.clone())
+            .app_data(page_cache.clone())
+            .app_data(site_config.clone())
+            .app_data(preview_renderer.clone())
+            .app_data(server_config.clone())
+            .configure(|cfg| {
+                if let Some(newsletter) = &newsletter_data {
+                    cfg.app_data(newsletter.clone());
+                }
+
+                if let Some(reminders) = &reminders_data {
+                    cfg.app_data(reminders.clone());
+                }
+
+                if let Some(contact) = &contact_data {
+                    cfg.app_data(contact.clone());
+                }
+
+                if let Some((admin, store)) = &admin_data {
+                    cfg.app_data(admin.clone()).app_data(store.clone());
+                }
+            })
             .wrap(Condition::new(config.metrics.enabled, prometheus.clone()))
+            .service(healthz)
+            .service(readyz)
             .service(imprint)
+            .service(event_preview_image)
+            .service(event_detail)
             .service(events)
             .service(index)
-            .service(Files::new("/static", "./static"))
+            .service(
+                web::scope(assets::URL_PREFIX)
+                    .wrap_fn({
+                        let assets = assets.clone();
+                        let autoreload = config.server.template_autoreload;
+
+                        move |req, srv| {
+                            // While templates are auto-reloaded, static files are likely edited as
+                            // well, so hashes and precompressed files from startup can't be
+                            // trusted.
+                            let path = req
+                                .path()
+                                .strip_prefix(assets::URL_PREFIX)
+                                .and_then(|path| path.strip_prefix('/'))
+                                .filter(|_| !autoreload)
+                                .map(str::to_owned);
+
+                            // Images and fonts are already compressed, so the `Compress`
+                            // middleware would only waste CPU on them.
+                            let incompressible = !assets::is_compressible(req.path());
+
+                            // Static files referenced with their current content hash can be
+                            // cached forever.
+                            let current = path
+                                .as_deref()
+                                .is_some_and(|path| assets.is_current(path, req.query_string()));
+
+                            // The response depends on the accepted encodings if precompressed
+                            // variants exist, even if the uncompressed file is served.
+                            let precompressed = path.as_deref().is_some_and(|path| {
+                                assets.has_precompressed(path, Precompressed::Brotli)
+                                    || assets.has_precompressed(path, Precompressed::Gzip)
+                            });
+
+                            let file = path
+                                .as_deref()
+                                .filter(|_| precompressed)
+                                .and_then(|path| precompressed_file(req.request(), &assets, path));
+
+                            // Stylesheets with rewritten references are served from memory.
+                            let stylesheet =
+                                path.as_deref().and_then(|path| assets.stylesheet(path));
+
+                            let res = match (file, stylesheet) {
+                                (Some(file), _) => {
+                                    let res = file.into_response(req.request());
+                                    Either::left(ready(Ok(req.into_response(res))))
+                                }
+                                (None, Some(css)) => {
+                                    let res = HttpResponse::Ok()
+                                        .content_type("text/css; charset=utf-8")
+                                        .body(css.to_string());
+                                    Either::left(ready(Ok(req.into_response(res))))
+                                }
+                                (None, None) => Either::right(srv.call(req)),
+                            };
+
+                            async move {
+                                let mut res = res.await?;
+
+                                if precompressed {
+                                    res.headers_mut().insert(
+                                        header::VARY,
+                                        header::HeaderValue::from_static("accept-encoding"),
+                                    );
+                                }
+
+                                if incompressible
+                                    && !res.headers().contains_key(header::CONTENT_ENCODING)
+                                {
+                                    res.headers_mut().insert(
+                                        header::CONTENT_ENCODING,
+                                        header::HeaderValue::from_static("identity"),
+                                    );
+                                }
+
+                                if current && res.status().is_success() {
+                                    res.headers_mut().insert(
+                                        header::CACHE_CONTROL,
+                                        header::HeaderValue::from_static(
+                                            "public, max-age=31536000, immutable",
+                                        ),
+                                    );
+                                }
+
+                                Ok(res)
+                            }
+                        }
+                    })
+                    .service(Files::new("", STATIC_DIR)),
+            )
+            .configure(|cfg| {
+                if image_config.enabled {
+                    // Processed images are WebP encoded and not worth compressing.
+                    cfg.service(
+                        web::scope(images::URL_PREFIX)
+                            .wrap(DefaultHeaders::new().add((header::CONTENT_ENCODING, "identity")))
+                            .service(Files::new("", &image_config.cache_dir)),
+                    );
+                }
+            })
             .service(
                 // The scoping is a bit of a hack to limit the HttpAuthentication middleware to
                 // just the metrics endpoint.
@@ -237,26 +1275,357 @@ async fn main() -> anyhow::Result<()> {
                     .wrap(HttpAuthentication::with_fn(metrics_auth))
                     .service(web::resource("").get(metrics)),
             )
+            .service(web::resource(PushConfig::WEBHOOK_PATH).post(google_calendar_hook))
+            .configure(routes::newsletter::configure)
+            .configure(routes::contact::configure)
+            .configure(routes::reminders::configure)
+            .configure(routes::admin::configure)
             .wrap(
                 ErrorHandlers::new()
                     .handler(StatusCode::NOT_FOUND, not_found)
                     .handler(StatusCode::INTERNAL_SERVER_ERROR, internal_server_error),
             )
+            .wrap_fn({
+                let security = security.clone();
+
+                move |req, srv| {
+                    req.extensions_mut().insert(CspNonce::generate());
+                    let security = security.clone();
+                    let fut = srv.call(req);
+
+                    async move {
+                        let mut res = fut.await?;
+                        add_security_headers(&security, &mut res);
+                        Ok(res)
+                    }
+                }
+            })
             .wrap(Compress::default())
             // Don't log things that could identify the user, e.g. omit client IP, referrer and
             // user agent.
-            .wrap(Logger::new(r#""%r" %s %b %T"#))
+            .wrap(
+                Logger::new(r#""%r" %s %b %T"#)
+                    // Health checks would drown everything else.
+                    .exclude("/healthz")
+                    .exclude("/readyz"),
+            )
     })
     .workers(2)
+    .shutdown_timeout(config.server.shutdown_timeout_seconds)
+    // Signals are handled below to coordinate the shutdown with the calendar sync.
+    .disable_signals()
     .bind(config.server.listen_addr)?
-    .run()
-    .await?;
+    .run();
+
+    let server_handle = server.handle();
+    let mut server = std::pin::pin!(server);
+
+    let signal = tokio::select! {
+        result = &mut server => {
+            // The server stopped on its own, e.g. due to an error.
+            sync_task_handle.stop().await?;
+            stop_watch().await?;
+            stop_webhooks().await?;
+            stop_newsletter().await?;
+            stop_mastodon().await?;
+            stop_reminders().await?;
+            return Ok(result?);
+        }
+        signal = shutdown_signal() => signal?,
+    };
+
+    log::info!("received {signal}, shutting down");
+    let shutdown_started = Instant::now();
+
+    // Stop accepting connections and let in-flight requests finish, while the calendar sync, the
+    // push notification channel and the background tasks notifying about events are stopped. A
+    // running sync is cancelled and leaves the calendar untouched, pending webhook deliveries and
+    // Mastodon posts are kept on disk.
+    let (
+        _,
+        server_result,
+        sync_result,
+        watch_result,
+        webhooks_result,
+        newsletter_result,
+        mastodon_result,
+        reminders_result,
+    ) = tokio::join!(
+        server_handle.stop(true),
+        server,
+        sync_task_handle.stop(),
+        stop_watch(),
+        stop_webhooks(),
+        stop_newsletter(),
+        stop_mastodon(),
+        stop_reminders()
+    );
+    server_result?;
+    sync_result?;
+    watch_result?;
+    webhooks_result?;
+    newsletter_result?;
+    mastodon_result?;
+    reminders_result?;
 
-    sync_task_handle.stop().await?;
+    let health = calendar.health().await;
+
+    log::info!(
+        "shutdown complete after {:.1?}, last successful sync: {}, {} events",
+        shutdown_started.elapsed(),
+        health
+            .last_success
+            .map_or_else(|| "never".to_string(), |time| time.to_string()),
+        health.events
+    );
+    log::logger().flush();
 
     Ok(())
 }
 
+/// Waits for a signal requesting the server to shut down and returns its name.
+#[cfg(unix)]
+async fn shutdown_signal() -> std::io::Result<&'static str> {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+
+    tokio::select! {
+        _ = sigterm.recv() => Ok("SIGTERM"),
+        _ = sigint.recv() => Ok("SIGINT"),
+    }
+}
+
+/// Waits for a signal requesting the server to shut down and returns its name.
+#[cfg(not(unix))]
+async fn shutdown_signal() -> std::io::Result<&'static str> {
+    tokio::signal::ctrl_c().await?;
+    Ok("Ctrl-C")
+}
+
+/// Fetches events from the configured event source once and prints them to stdout.
+async fn sync(config: AppConfig, format: DumpFormat) -> anyhow::Result<()> {
+    let calendar = Calendar::from_config(&config.calendar, local_event_store(&config)?).await?;
+    calendar.sync_once().await?;
+
+    let all_events = calendar.get_events(Timestamp::MIN..Timestamp::MAX).await?;
+
+    match format {
+        DumpFormat::Json => println!("{}", serde_json::to_string_pretty(&all_events)?),
+        DumpFormat::Table => {
+            for event in &all_events {
+                let start_date = event.start_date.to_zoned(TimeZone::system());
+                let end_date = event
+                    .end_date
+                    .map(|end_date| end_date.to_zoned(TimeZone::system()));
+
+                println!(
+                    "{:<16}  {:<16}  {:<4}  {}",
+                    start_date.strftime("%Y-%m-%d %H:%M").to_string(),
+                    end_date
+                        .map(|end_date| end_date.strftime("%Y-%m-%d %H:%M").to_string())
+                        .unwrap_or_default(),
+                    if event.description.is_some() {
+                        "desc"
+                    } else {
+                        ""
+                    },
+                    event.title
+                );
+            }
+
+            println!("{} events", all_events.len());
+        }
+    }
+
+    Ok(())
+}
+
+/// Validates the loaded configuration and reports all problems found.
+fn check_config(config: AppConfig) -> anyhow::Result<()> {
+    let problems = config.validate();
+
+    if problems.is_empty() {
+        println!("configuration OK");
+        return Ok(());
+    }
+
+    for problem in &problems {
+        println!("error: {problem}");
+    }
+
+    anyhow::bail!("found {} configuration problem(s)", problems.len())
+}
+
+/// Compiles all templates and reports templates that fail to compile.
+fn check_templates(config: AppConfig) -> anyhow::Result<()> {
+    let env = loaded_template_env(&config, Arc::new(AssetManifest::load(STATIC_DIR)?));
+
+    let mut names = template_names(std::path::Path::new(TEMPLATE_DIR))?;
+    names.sort();
+
+    let mut failed = 0;
+
+    for name in &names {
+        match env.get_template(name) {
+            Ok(_) => println!("ok     {name}"),
+            Err(err) => {
+                failed += 1;
+                println!("error  {name}: {err:#}");
+            }
+        }
+    }
+
+    if failed > 0 {
+        anyhow::bail!("{failed} of {} templates failed to compile", names.len());
+    }
+
+    Ok(())
+}
+
+/// Returns the names of all templates in `dir` and its subdirectories, relative to `dir`.
+fn template_names(dir: &std::path::Path) -> std::io::Result<Vec<String>> {
+    let mut names = Vec::new();
+
+    for entry in std::fs::read_dir(dir)?.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+
+        if path.is_dir() {
+            names.extend(
+                template_names(&path)?
+                    .into_iter()
+                    .map(|child| format!("{name}/{child}")),
+            );
+        } else if path.is_file() {
+            names.push(name);
+        }
+    }
+
+    Ok(names)
+}
+
+/// Sends a simulated push notification to the webhook.
+async fn simulate_push(
+    config: AppConfig,
+    url: Option<String>,
+    state: String,
+) -> anyhow::Result<()> {
+    let url = url.unwrap_or_else(|| {
+        format!(
+            "http://{}{}",
+            config.server.listen_addr,
+            PushConfig::WEBHOOK_PATH
+        )
+    });
+    let token = config.calendar.push.token.unwrap_or_default();
+
+    let resp = reqwest::Client::new()
+        .post(&url)
+        .header(watch::CHANNEL_ID_HEADER, "simulator")
+        .header(watch::CHANNEL_TOKEN_HEADER, token)
+        .header(watch::RESOURCE_STATE_HEADER, state)
+        .header(watch::MESSAGE_NUMBER_HEADER, "1")
+        .send()
+        .await?;
+
+    println!("{url}: {}", resp.status());
+
+    if !resp.status().is_success() {
+        anyhow::bail!("push notification was not accepted");
+    }
+
+    Ok(())
+}
+
+/// Returns the base URL for links in emails, i.e. the canonical URL if configured or the listen
+/// address otherwise.
+fn site_url(config: &AppConfig) -> String {
+    config
+        .site
+        .canonical_url
+        .clone()
+        .unwrap_or_else(|| format!("http://{}", config.server.listen_addr))
+}
+
+/// Creates the newsletter from the configuration.
+fn create_newsletter(config: &AppConfig, assets: Arc<AssetManifest>) -> anyhow::Result<Newsletter> {
+    Ok(Newsletter::new(
+        config.newsletter.clone(),
+        &site_url(config),
+        loaded_template_env(config, assets),
+    )?)
+}
+
+/// Previews or sends the newsletter digest with freshly synced events.
+async fn run_newsletter_command(
+    config: AppConfig,
+    command: NewsletterCommand,
+) -> anyhow::Result<()> {
+    let newsletter = create_newsletter(&config, Arc::new(AssetManifest::load(STATIC_DIR)?))?;
+
+    let calendar = Calendar::from_config(&config.calendar, local_event_store(&config)?).await?;
+    calendar.sync_once().await?;
+
+    match command {
+        NewsletterCommand::Preview { html } => {
+            match newsletter
+                .preview_digest(&calendar, Timestamp::now())
+                .await?
+            {
+                Some(email) => {
+                    println!("Subject: {}\n", email.subject);
+                    match email.html.filter(|_| html) {
+                        Some(body) => println!("{body}"),
+                        None => println!("{}", email.text),
+                    }
+                }
+                None => println!("no upcoming events, the newsletter would not be sent"),
+            }
+        }
+        NewsletterCommand::Send { to } => {
+            let sent = newsletter
+                .send_digest(&calendar, Timestamp::now(), to)
+                .await?;
+            println!("sent newsletter to {sent} recipients");
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints a new VAPID key pair for the reminders configuration.
+fn generate_vapid_keys() -> anyhow::Result<()> {
+    let (private_key, public_key) = VapidKey::generate()?;
+    println!("[reminders]");
+    println!("vapid_public_key = \"{public_key}\"");
+    println!("vapid_private_key = \"{private_key}\"");
+    Ok(())
+}
+
+/// Reads a password from stdin and prints its hash for `admin.users`.
+fn hash_admin_password(user: &str) -> anyhow::Result<()> {
+    eprint!("Password for {user}: ");
+    let mut password = String::new();
+    std::io::stdin().read_line(&mut password)?;
+    let password = password.trim_end_matches(['\r', '\n']);
+    anyhow::ensure!(!password.is_empty(), "the password must not be empty");
+
+    println!("[admin.users]");
+    println!("{user} = \"{}\"", admin::hash_password(password)?);
+    Ok(())
+}
+
+/// Precompresses all compressible static files.
+fn compress_assets() -> anyhow::Result<()> {
+    let written = assets::precompress(STATIC_DIR)?;
+    println!("wrote {written} precompressed files");
+    Ok(())
+}
+
 /// Error handler for a 404 Page not found error.
 fn not_found<B>(svc_res: ServiceResponse<B>) -> Result<ErrorHandlerResponse<B>> {
     error_handler(svc_res, "not_found.html")
@@ -305,3 +1674,201 @@ fn error_handler<B>(svc_res: ServiceResponse<B>, tmpl: &str) -> Result<ErrorHand
         res.map_into_right_body(),
     )))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{self, TestRequest};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use wohnzimmer::calendar::{Event, StaticEventSource};
+
+    #[actix_web::test]
+    async fn page_cache() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir).unwrap();
+        std::fs::write(dir.join("index.html"), "v1 {{ render_count() }}").unwrap();
+
+        // Counts how often the page is actually rendered.
+        let renders = Arc::new(AtomicUsize::new(0));
+        let mut env = minijinja::Environment::new();
+        env.add_function("render_count", {
+            let renders = renders.clone();
+            move || renders.fetch_add(1, Ordering::SeqCst) + 1
+        });
+
+        let loaded_at = Data::new(TemplatesLoadedAt::default());
+        let reloader = Data::new(template_reloader(env, dir, false, loaded_at.clone()));
+        let calendar = Calendar::new(StaticEventSource::new(Vec::<Event>::new())).unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(reloader.clone())
+                .app_data(loaded_at)
+                .app_data(Data::new(calendar))
+                .app_data(Data::new(PageCache::default()))
+                .app_data(Data::new(AppConfig::load().unwrap().site))
+                .service(index),
+        )
+        .await;
+
+        let res = test::call_service(&app, TestRequest::get().to_request()).await;
+        let etag = res.headers().get(header::ETAG).unwrap().clone();
+        assert_eq!(test::read_body(res).await, "v1 1");
+
+        // The page is served from the cache without rendering it again.
+        let res = test::call_service(&app, TestRequest::get().to_request()).await;
+        assert_eq!(res.headers().get(header::ETAG), Some(&etag));
+        assert_eq!(test::read_body(res).await, "v1 1");
+
+        let req = TestRequest::get()
+            .insert_header((header::IF_NONE_MATCH, etag.clone()))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+
+        // Reloading changed templates invalidates cached pages. The load time of the templates is
+        // part of the ETag with a resolution of milliseconds.
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        std::fs::write(dir.join("index.html"), "v2 {{ render_count() }}").unwrap();
+        reloader.notifier().request_reload();
+
+        let res = test::call_service(&app, TestRequest::get().to_request()).await;
+        assert_ne!(res.headers().get(header::ETAG), Some(&etag));
+        assert_eq!(test::read_body(res).await, "v2 2");
+        assert_eq!(renders.load(Ordering::SeqCst), 2);
+    }
+
+    #[actix_web::test]
+    async fn page_cache_csp_nonce() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::write(dir.join("index.html"), "{{ csp_nonce }}").unwrap();
+
+        let loaded_at = Data::new(TemplatesLoadedAt::default());
+        let reloader = Data::new(template_reloader(
+            minijinja::Environment::new(),
+            dir,
+            false,
+            loaded_at.clone(),
+        ));
+        let calendar = Calendar::new(StaticEventSource::new(Vec::<Event>::new())).unwrap();
+        let security = SecurityConfig::default();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(reloader)
+                .app_data(loaded_at)
+                .app_data(Data::new(calendar))
+                .app_data(Data::new(PageCache::default()))
+                .app_data(Data::new(AppConfig::load().unwrap().site))
+                .service(index)
+                .wrap_fn(move |req, srv| {
+                    req.extensions_mut().insert(CspNonce::generate());
+                    let security = security.clone();
+                    let fut = srv.call(req);
+
+                    async move {
+                        let mut res = fut.await?;
+                        add_security_headers(&security, &mut res);
+                        Ok(res)
+                    }
+                }),
+        )
+        .await;
+
+        let mut nonces = Vec::new();
+
+        // The cached page gets the fresh nonce of each response.
+        for _ in 0..2 {
+            let res = test::call_service(&app, TestRequest::get().to_request()).await;
+            let csp = res.headers().get(header::CONTENT_SECURITY_POLICY).unwrap();
+            let csp = csp.to_str().unwrap().to_string();
+            let nonce = String::from_utf8(test::read_body(res).await.to_vec()).unwrap();
+
+            assert!(csp.contains(&format!("'nonce-{nonce}'")));
+            assert_ne!(nonce, *CSP_NONCE_PLACEHOLDER);
+            nonces.push(nonce);
+        }
+
+        assert_ne!(nonces[0], nonces[1]);
+    }
+
+    #[test]
+    fn page_cache_capacity() {
+        let cache = PageCache::default();
+        let validators = |etag: &str| Validators {
+            etag: EntityTag::new_strong(etag.into()),
+            last_modified: HttpDate::from(SystemTime::now()),
+        };
+
+        for i in 0..PAGE_CACHE_CAPACITY + 10 {
+            cache.insert(&validators("1"), i.to_string(), format!("page {i}"));
+        }
+
+        assert_eq!(cache.inner.lock().unwrap().pages.len(), PAGE_CACHE_CAPACITY);
+        assert_eq!(cache.get(&validators("1"), "0").as_deref(), Some("page 0"));
+        assert_eq!(
+            cache.get(&validators("1"), &PAGE_CACHE_CAPACITY.to_string()),
+            None
+        );
+
+        // Pages of another generation are never served.
+        assert_eq!(cache.get(&validators("2"), "0"), None);
+    }
+
+    #[actix_web::test]
+    async fn google_calendar_hook() {
+        let mut config = AppConfig::load().unwrap().calendar;
+        config.push.enabled = true;
+        config.push.token = Some("secret".into());
+
+        let calendar =
+            Data::new(Calendar::new(StaticEventSource::new(Vec::<Event>::new())).unwrap());
+        let app = test::init_service(
+            App::new()
+                .app_data(calendar.clone())
+                .app_data(Data::new(config))
+                .service(web::resource(PushConfig::WEBHOOK_PATH).post(super::google_calendar_hook)),
+        )
+        .await;
+
+        let notify = |token: &str, state: &str| {
+            TestRequest::post()
+                .uri(PushConfig::WEBHOOK_PATH)
+                .insert_header((watch::CHANNEL_ID_HEADER, "simulator"))
+                .insert_header((watch::CHANNEL_TOKEN_HEADER, token))
+                .insert_header((watch::RESOURCE_STATE_HEADER, state))
+                .insert_header((watch::MESSAGE_NUMBER_HEADER, "1"))
+                .to_request()
+        };
+
+        let res = test::call_service(&app, notify("guessed", "exists")).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let req = TestRequest::post()
+            .uri(PushConfig::WEBHOOK_PATH)
+            .insert_header((watch::CHANNEL_TOKEN_HEADER, "secret"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        // The initial notification of a channel doesn't trigger a sync.
+        let res = test::call_service(&app, notify("secret", "sync")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(calendar.health().await.last_success, None);
+
+        // Changes are synced in the background.
+        let res = test::call_service(&app, notify("secret", "exists")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        for _ in 0..100 {
+            if calendar.health().await.last_success.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(calendar.health().await.last_success.is_some());
+    }
+}
@@ -1,4 +1,301 @@
+use crate::SanitizeConfig;
+use crate::calendar::PublishState;
+use dom_query::{Document, NodeRef};
+use jiff::Timestamp;
+use jiff::civil::{Date, Time};
+use jiff::tz::TimeZone;
 use serde::Deserialize;
+use url::Url;
+
+/// Structured metadata extracted from the header block of an event description.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct Metadata {
+    /// The admission price, e.g. `5 €`.
+    pub price: Option<String>,
+    /// The time when doors open.
+    pub doors: Option<Time>,
+    /// URL of the ticket shop.
+    pub ticket_url: Option<String>,
+    /// Free-form tags, e.g. genres.
+    pub tags: Vec<String>,
+    /// URL or absolute path of an image for the event.
+    pub image: Option<String>,
+    /// Whether the event is published or still a draft.
+    pub publish_state: Option<PublishState>,
+    /// The time of the official announcement.
+    pub publish_at: Option<Timestamp>,
+}
+
+impl Metadata {
+    // Sets the field identified by `key` if `value` is valid for it. Returns `false` for unknown
+    // keys and invalid values, except for the publication keys: an invalid status or publication
+    // time makes the event a draft, so that a typo doesn't publish it early.
+    fn set(&mut self, key: &str, value: &str) -> bool {
+        let value = value.trim().trim_matches(|c| c == '"' || c == '\'').trim();
+        let key = key.trim().to_lowercase();
+
+        if is_publication_key(&key) {
+            if !self.set_publication(&key, value) {
+                log::warn!(
+                    "invalid value `{value}` for `{key}` in event description, treating the event as a draft"
+                );
+                self.publish_state = Some(PublishState::Draft);
+            }
+            return true;
+        }
+
+        if value.is_empty() {
+            return false;
+        }
+
+        match key.as_str() {
+            "eintritt" | "preis" | "price" => self.price = Some(value.into()),
+            "einlass" | "doors" => match parse_time(value) {
+                Some(time) => self.doors = Some(time),
+                None => return false,
+            },
+            "tickets" | "ticket" | "vorverkauf" => match parse_url(value) {
+                Some(url) if is_http_url(&url) => self.ticket_url = Some(url),
+                _ => return false,
+            },
+            "tags" | "genre" => {
+                self.tags = value
+                    .trim_start_matches('[')
+                    .trim_end_matches(']')
+                    .split(',')
+                    .map(|tag| tag.trim().trim_matches(|c| c == '"' || c == '\'').trim())
+                    .filter(|tag| !tag.is_empty())
+                    .map(Into::into)
+                    .collect()
+            }
+            "bild" | "image" | "poster" => match parse_url(value) {
+                Some(url) if is_http_url(&url) || url.starts_with('/') => self.image = Some(url),
+                _ => return false,
+            },
+            _ => return false,
+        }
+
+        true
+    }
+
+    // Sets the publication status or time. Returns `false` for invalid values.
+    fn set_publication(&mut self, key: &str, value: &str) -> bool {
+        if key == "status" {
+            match value.to_lowercase().as_str() {
+                "entwurf" | "draft" => self.publish_state = Some(PublishState::Draft),
+                // A draft stays a draft if the status is given more than once.
+                "veröffentlicht" | "published" => {
+                    self.publish_state.get_or_insert(PublishState::Published);
+                }
+                _ => return false,
+            }
+        } else {
+            match parse_datetime(value) {
+                Some(publish_at) => self.publish_at = Some(publish_at),
+                None => return false,
+            }
+        }
+
+        true
+    }
+}
+
+// Returns `true` for the keys which control when an event is published.
+fn is_publication_key(key: &str) -> bool {
+    matches!(
+        key,
+        "status" | "veröffentlichung" | "ankündigung" | "publish" | "publish_at"
+    )
+}
+
+/// Extracts structured metadata from the beginning of an event description.
+///
+/// Two formats are recognized: YAML-like front matter enclosed in `---` lines, or a block of
+/// `Key: value` lines at the very top of the description, e.g.
+///
+/// ```text
+/// Eintritt: 5 €
+/// Einlass: 19 Uhr
+/// Tickets: https://tickets.example.com
+/// Veröffentlichung: 01.03.2025 10 Uhr
+///
+/// The actual description.
+/// ```
+///
+/// Lines may also be separated by `<br>` tags since that is how Google Calendar stores line
+/// breaks. Returns the metadata and the remaining description with the header block removed.
+pub(crate) fn extract_metadata(text: &str) -> (Metadata, &str) {
+    let mut metadata = Metadata::default();
+
+    // Front matter.
+    if let Some((first, mut rest)) = next_line(text)
+        && first.trim() == "---"
+    {
+        let mut front_matter = Metadata::default();
+
+        while let Some((line, next)) = next_line(rest) {
+            rest = next;
+
+            if line.trim() == "---" {
+                return (front_matter, rest.trim_start());
+            }
+
+            // Unknown keys are ignored in front matter.
+            if let Some((key, value)) = line.split_once(':') {
+                front_matter.set(key, value);
+            }
+        }
+
+        // No closing `---`, so this is not front matter.
+        return (metadata, text);
+    }
+
+    // Header block.
+    let mut body = text;
+
+    while let Some((line, rest)) = next_line(body) {
+        match line.split_once(':') {
+            Some((key, value)) if metadata.set(key, value) => body = rest,
+            _ => break,
+        }
+    }
+
+    // A publication key after an invalid line would end up in the public description. Keep the
+    // event a draft instead.
+    let mut block = body;
+    while let Some((line, rest)) = next_line(block)
+        && !line.trim().is_empty()
+    {
+        if let Some((key, _)) = line.split_once(':')
+            && is_publication_key(&key.trim().to_lowercase())
+        {
+            log::warn!(
+                "`{}` follows an invalid line in event description, treating the event as a draft",
+                key.trim()
+            );
+            metadata.publish_state = Some(PublishState::Draft);
+            break;
+        }
+        block = rest;
+    }
+
+    if body.len() == text.len() {
+        return (metadata, text);
+    }
+
+    (metadata, trim_start_lines(body))
+}
+
+// Splits off the first line of `text`. Lines are separated by newlines or `<br>` tags.
+fn next_line(text: &str) -> Option<(&str, &str)> {
+    if text.is_empty() {
+        return None;
+    }
+
+    let newline = text.find('\n').map(|pos| (pos, 1));
+    let br = find_br(text);
+
+    match (newline, br) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+    .map(|(pos, len)| (text[..pos].trim_end_matches('\r'), &text[pos + len..]))
+    .or(Some((text, "")))
+}
+
+// Finds the position and length of the first `<br>`, `<br/>` or `<br />` tag.
+fn find_br(text: &str) -> Option<(usize, usize)> {
+    let lowercase = text.to_ascii_lowercase();
+
+    lowercase.match_indices("<br").find_map(|(pos, _)| {
+        let tail = &lowercase[pos + 3..];
+        let len = tail.find('>')?;
+
+        tail[..len]
+            .trim()
+            .trim_end_matches('/')
+            .trim()
+            .is_empty()
+            .then_some((pos, len + 4))
+    })
+}
+
+// Removes leading blank lines.
+fn trim_start_lines(mut text: &str) -> &str {
+    while let Some((line, rest)) = next_line(text) {
+        if !line.trim().is_empty() {
+            break;
+        }
+
+        text = rest;
+    }
+
+    text
+}
+
+// Parses times like `19 Uhr`, `19:30`, `19.30 Uhr` or `7 pm`.
+fn parse_time(value: &str) -> Option<Time> {
+    let value = value.to_lowercase();
+    let (value, pm) = match (value.strip_suffix("pm"), value.strip_suffix("am")) {
+        (Some(value), _) => (value, Some(true)),
+        (_, Some(value)) => (value, Some(false)),
+        _ => (value.as_str(), None),
+    };
+    let value = value.trim().trim_end_matches("uhr").trim();
+
+    let (hour, minute) = match value.split_once([':', '.']) {
+        Some((hour, minute)) => (hour.trim().parse::<i8>().ok()?, minute.trim().parse().ok()?),
+        None => (value.parse::<i8>().ok()?, 0),
+    };
+
+    // 12 am is midnight and 12 pm is noon.
+    let hour = match pm {
+        Some(_) if !(1..=12).contains(&hour) => return None,
+        Some(pm) => hour % 12 + if pm { 12 } else { 0 },
+        None => hour,
+    };
+
+    Time::new(hour, minute, 0, 0).ok()
+}
+
+// Parses dates with an optional time like `01.03.2025 10 Uhr` or `2025-03-01 10:00` in the local
+// time zone, or RFC 3339 timestamps. Dates without a time mean midnight.
+fn parse_datetime(value: &str) -> Option<Timestamp> {
+    if let Ok(timestamp) = value.parse() {
+        return Some(timestamp);
+    }
+
+    let (date, time) = match value.split_once([' ', 'T']) {
+        Some((date, time)) => (date.trim_end_matches(','), parse_time(time)?),
+        None => (value, Time::midnight()),
+    };
+    let date = Date::strptime("%d.%m.%Y", date)
+        .or_else(|_| date.parse())
+        .ok()?;
+
+    date.to_datetime(time)
+        .to_zoned(TimeZone::system())
+        .ok()
+        .map(|zoned| zoned.timestamp())
+}
+
+// Extracts a URL from a plain URL, an autolink (`<https://...>`) or an HTML anchor.
+fn parse_url(value: &str) -> Option<String> {
+    if value.starts_with("<a ") {
+        return Document::fragment(value)
+            .select("a")
+            .attr("href")
+            .map(|href| href.to_string());
+    }
+
+    let url = value.trim_start_matches('<').trim_end_matches('>');
+
+    (!url.contains(char::is_whitespace)).then(|| url.into())
+}
+
+fn is_http_url(url: &str) -> bool {
+    url.starts_with("https://") || url.starts_with("http://")
+}
 
 /// Converts a text potentially containing a mix of markdown and HTML into HTML.
 ///
@@ -9,9 +306,10 @@ pub(crate) fn to_html<T: AsRef<str>>(text: T) -> Option<String> {
 
     let html = markdown::to_html_with_options(text.as_ref(), &options).ok()?;
 
-    let document = dom_query::Document::fragment(html);
+    let document = Document::fragment(html);
 
     remove_empty_anchors(&document);
+    embed_media(&document);
 
     Some(document.html_root().inner_html().to_string())
 }
@@ -29,7 +327,7 @@ pub(crate) fn to_html<T: AsRef<str>>(text: T) -> Option<String> {
 // We'll clean that up to
 //
 //   <a href="https://foo.tld">https://foo.tld</a>
-fn remove_empty_anchors(document: &dom_query::Document) {
+fn remove_empty_anchors(document: &Document) {
     for node in document.select("a").iter() {
         if node.inner_html().is_empty() {
             node.remove();
@@ -37,6 +335,370 @@ fn remove_empty_anchors(document: &dom_query::Document) {
     }
 }
 
+/// Maximum number of characters in a description excerpt.
+const EXCERPT_MAX_CHARS: usize = 200;
+
+/// An event description in different representations.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Description {
+    /// The sanitized HTML.
+    pub html: String,
+    /// Plain text with paragraphs separated by blank lines.
+    pub text: String,
+    /// The first paragraph of the plain text, shortened to at most `EXCERPT_MAX_CHARS`
+    /// characters.
+    pub excerpt: String,
+}
+
+/// Sanitizes an HTML description and derives a plain text version and an excerpt from it.
+pub(crate) fn render_description<T: AsRef<str>>(html: T, config: &SanitizeConfig) -> Description {
+    let document = Document::fragment(html.as_ref());
+    let root = document.html_root();
+
+    sanitize_document(&document, config);
+
+    let text = root.formatted_text().to_string();
+
+    Description {
+        html: root.inner_html().to_string(),
+        excerpt: excerpt(&text, EXCERPT_MAX_CHARS),
+        text,
+    }
+}
+
+// Sanitizes a document according to the allowlist in `config`.
+//
+// Tags which are not allowed are unwrapped, i.e. replaced by their content, unless they are
+// listed in `clobber_tags`, in which case they are removed entirely. Attributes which are not
+// allowed and URLs using a scheme that is not allowed are removed. External links get
+// `rel="noopener noreferrer"` and the configured `target`.
+fn sanitize_document(document: &Document, config: &SanitizeConfig) {
+    // Process nodes in reverse document order so that children are sanitized before their
+    // parents get unwrapped or removed.
+    let nodes: Vec<_> = document.html_root().descendants_it().collect();
+
+    for node in nodes.iter().rev() {
+        if node.is_comment() {
+            node.remove_from_parent();
+        } else if node.is_element() {
+            sanitize_element(node, config);
+        }
+    }
+}
+
+// Returns the first paragraph of `text`, shortened to `max_chars` at a word boundary.
+fn excerpt(text: &str, max_chars: usize) -> String {
+    let paragraph = text.split("\n\n").next().unwrap_or_default().trim();
+
+    if paragraph.chars().count() <= max_chars {
+        return paragraph.into();
+    }
+
+    // Leave room for the ellipsis.
+    let cut = paragraph
+        .char_indices()
+        .nth(max_chars - 1)
+        .map_or(paragraph.len(), |(pos, _)| pos);
+    let shortened = &paragraph[..cut];
+
+    let shortened = match shortened.rfind(char::is_whitespace) {
+        Some(pos) if pos > 0 => &shortened[..pos],
+        // A single very long word.
+        _ => shortened,
+    };
+
+    format!(
+        "{}…",
+        shortened.trim_end_matches(|c: char| c.is_whitespace() || c.is_ascii_punctuation())
+    )
+}
+
+fn sanitize_element(node: &NodeRef, config: &SanitizeConfig) {
+    let Some(name) = node.node_name() else {
+        return;
+    };
+    let name = name.to_ascii_lowercase();
+
+    if config.clobber_tags.contains(&name) {
+        node.remove_from_parent();
+        return;
+    }
+
+    if !config.tags.contains(&name) {
+        match node.first_child() {
+            // Unwrapping the first child removes the node and moves all of its children into its
+            // place.
+            Some(child) => child.unwrap_node(),
+            None => node.remove_from_parent(),
+        }
+        return;
+    }
+
+    let allowed = |attr: &str| {
+        [name.as_str(), "*"].iter().any(|tag| {
+            config
+                .attributes
+                .get(*tag)
+                .is_some_and(|attrs| attrs.iter().any(|a| a == attr))
+        })
+    };
+
+    for attr in node.attrs() {
+        let attr_name = str::to_ascii_lowercase(&attr.name.local);
+
+        let keep = allowed(&attr_name)
+            && match attr_name.as_str() {
+                "href" | "src" => is_allowed_url(&attr.value, &config.url_schemes),
+                _ => true,
+            };
+
+        if !keep {
+            node.remove_attr(&attr.name.local);
+        }
+    }
+
+    if name == "a" {
+        let external = node.attr("href").is_some_and(|href| {
+            url_scheme(&href).is_some_and(|scheme| scheme == "http" || scheme == "https")
+                || is_protocol_relative(&href)
+        });
+
+        if external {
+            node.set_attr("rel", "noopener noreferrer");
+
+            match &config.link_target {
+                Some(target) => node.set_attr("target", target),
+                None => node.remove_attr("target"),
+            }
+        }
+    }
+}
+
+// Returns the lowercased scheme of a URL, if any.
+fn url_scheme(url: &str) -> Option<String> {
+    // Browsers ignore whitespace and control characters within the scheme, e.g. `java\tscript:`.
+    let url: String = url
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect();
+    let (scheme, _) = url.split_once(':')?;
+
+    // Everything before the first `/`, `?` or `#` would be part of a relative path instead.
+    if scheme.is_empty() || scheme.contains(['/', '?', '#']) {
+        return None;
+    }
+
+    Some(scheme.to_ascii_lowercase())
+}
+
+// Returns whether a URL is protocol-relative like `//example.com`, which points to another host.
+// Browsers treat backslashes like slashes here.
+fn is_protocol_relative(url: &str) -> bool {
+    let mut chars = url
+        .trim_start()
+        .chars()
+        .filter(|c| !c.is_control())
+        .map(|c| if c == '\\' { '/' } else { c });
+
+    chars.next() == Some('/') && chars.next() == Some('/')
+}
+
+fn is_allowed_url(url: &str, schemes: &[String]) -> bool {
+    match url_scheme(url) {
+        Some(scheme) => schemes.contains(&scheme),
+        None => true,
+    }
+}
+
+// Media from third party platforms which can be embedded into descriptions.
+#[derive(Debug, PartialEq, Eq)]
+enum Embed {
+    // A YouTube video ID.
+    YouTube(String),
+    // A Spotify resource like `track/<id>`.
+    Spotify(String),
+    // Bandcamp does not allow embedding without a numeric album or track ID which is not part of
+    // the URL, so this is rendered as a link card only.
+    Bandcamp,
+}
+
+impl Embed {
+    // Detects embeddable media from a URL.
+    fn from_url(url: &str) -> Option<Embed> {
+        let url = Url::parse(url).ok()?;
+
+        if !matches!(url.scheme(), "http" | "https") {
+            return None;
+        }
+
+        let host = url.host_str()?.trim_start_matches("www.");
+        let segments: Vec<_> = url.path_segments()?.filter(|s| !s.is_empty()).collect();
+
+        let embed = match (host, segments.as_slice()) {
+            ("youtube.com" | "m.youtube.com" | "music.youtube.com", ["watch"]) => {
+                let (_, id) = url.query_pairs().find(|(key, _)| key == "v")?;
+                Embed::YouTube(id.into_owned())
+            }
+            ("youtube.com" | "m.youtube.com", ["shorts" | "live" | "embed", id]) => {
+                Embed::YouTube(id.to_string())
+            }
+            ("youtu.be", [id]) => Embed::YouTube(id.to_string()),
+            ("open.spotify.com", segments) => {
+                // Localized URLs look like `/intl-de/track/<id>`.
+                let segments = match segments {
+                    [intl, rest @ ..] if intl.starts_with("intl-") => rest,
+                    segments => segments,
+                };
+
+                match segments {
+                    [
+                        kind @ ("track" | "album" | "playlist" | "episode" | "show" | "artist"),
+                        id,
+                    ] => Embed::Spotify(format!("{kind}/{id}")),
+                    _ => return None,
+                }
+            }
+            (host, ["album" | "track", _]) if host.ends_with(".bandcamp.com") => Embed::Bandcamp,
+            _ => return None,
+        };
+
+        // IDs end up in HTML attributes and are used to build iframe URLs client-side.
+        let valid_id = |id: &str| {
+            !id.is_empty()
+                && id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '/'))
+        };
+
+        match &embed {
+            Embed::YouTube(id) | Embed::Spotify(id) if !valid_id(id) => None,
+            _ => Some(embed),
+        }
+    }
+
+    // Renders a click-to-load placeholder which does not make any third party requests until
+    // the visitor explicitly asks for it. Loading the embed is handled by `static/js/embeds.js`.
+    fn to_html(&self, url: &str) -> String {
+        let (provider, name, id) = match self {
+            Embed::YouTube(id) => ("youtube", "YouTube", Some(id)),
+            Embed::Spotify(id) => ("spotify", "Spotify", Some(id)),
+            Embed::Bandcamp => ("bandcamp", "Bandcamp", None),
+        };
+
+        let url = escape_attr(url);
+
+        let (attrs, label) = match id {
+            Some(id) => (
+                format!(" data-embed-provider=\"{provider}\" data-embed-id=\"{id}\""),
+                format!("Inhalt von {name} laden"),
+            ),
+            None => (String::new(), format!("Auf {name} anhören")),
+        };
+
+        format!(
+            "<div class=\"embed\"{attrs}><a href=\"{url}\">\
+             <img src=\"/static/images/embeds/{provider}.svg\" alt=\"{name}\" width=\"480\" height=\"270\">\
+             <span>{label}</span></a></div>"
+        )
+    }
+}
+
+fn escape_attr(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+// Replaces bare links to embeddable media on their own line with embed placeholders.
+//
+// A link is considered to be on its own line if it makes up a whole paragraph, or if it's
+// surrounded by `<br>` tags within a paragraph, which is how Google Calendar stores line breaks.
+// In the latter case the paragraph is split around the placeholder.
+fn embed_media(document: &Document) {
+    // Replacing a paragraph invalidates the nodes of the current selection, so start over after
+    // every replacement.
+    while let Some((paragraph, html)) = find_embeddable(document) {
+        paragraph.replace_with_html(html);
+    }
+}
+
+// Finds the first paragraph containing an embeddable link and returns it together with the HTML
+// to replace it with.
+fn find_embeddable(document: &Document) -> Option<(NodeRef<'_>, String)> {
+    for anchor in document.select("p > a[href]").nodes().iter().cloned() {
+        let Some(href) = anchor.attr("href") else {
+            continue;
+        };
+
+        // Only bare URLs, not links with a custom text.
+        if anchor.text().trim() != href.trim() {
+            continue;
+        }
+
+        let Some(embed) = Embed::from_url(&href) else {
+            continue;
+        };
+
+        let Some(paragraph) = anchor.parent() else {
+            continue;
+        };
+
+        let is_blank = |node: &NodeRef| node.is_text() && node.text().trim().is_empty();
+        let is_br = |node: &NodeRef| node.has_name("br");
+
+        let mut before: Vec<_> = Vec::new();
+        let mut after: Vec<_> = Vec::new();
+        let mut seen_anchor = false;
+
+        for child in paragraph.children() {
+            if child.id == anchor.id {
+                seen_anchor = true;
+            } else if seen_anchor {
+                after.push(child);
+            } else {
+                before.push(child);
+            }
+        }
+
+        // Drop whitespace and the line breaks directly around the link.
+        while before.last().is_some_and(is_blank) {
+            before.pop();
+        }
+        if before.last().is_some_and(is_br) {
+            before.pop();
+        } else if !before.is_empty() {
+            continue;
+        }
+
+        while after.first().is_some_and(is_blank) {
+            after.remove(0);
+        }
+        if after.first().is_some_and(is_br) {
+            after.remove(0);
+        } else if !after.is_empty() {
+            continue;
+        }
+
+        let wrap = |nodes: &[NodeRef]| -> String {
+            let html: String = nodes.iter().map(|node| node.html().to_string()).collect();
+
+            if html.trim().is_empty() {
+                String::new()
+            } else {
+                format!("<p>{html}</p>")
+            }
+        };
+
+        let html = format!("{}{}{}", wrap(&before), embed.to_html(&href), wrap(&after));
+
+        return Some((paragraph, html));
+    }
+
+    None
+}
+
 /// A custom deserializer to automatically convert a markdown text to HTML.
 pub(crate) fn deserialize_to_html<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
 where
@@ -108,6 +770,81 @@ mod tests {
         );
     }
 
+    #[test]
+    fn embeds() {
+        let youtube = |id: &str, url: &str| {
+            format!(
+                "<div class=\"embed\" data-embed-provider=\"youtube\" data-embed-id=\"{id}\"><a href=\"{url}\"><img src=\"/static/images/embeds/youtube.svg\" alt=\"YouTube\" width=\"480\" height=\"270\"><span>Inhalt von YouTube laden</span></a></div>"
+            )
+        };
+
+        assert_to_html!(
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+            youtube("dQw4w9WgXcQ", "https://www.youtube.com/watch?v=dQw4w9WgXcQ"),
+        );
+
+        assert_to_html!(
+            "Our new video:\n\nhttps://youtu.be/dQw4w9WgXcQ\n\nEnjoy!",
+            format!(
+                "<p>Our new video:</p>\n{}\n<p>Enjoy!</p>",
+                youtube("dQw4w9WgXcQ", "https://youtu.be/dQw4w9WgXcQ")
+            ),
+        );
+
+        // Google Calendar separates lines with `<br>`.
+        assert_to_html!(
+            "Our new video:<br>https://youtu.be/dQw4w9WgXcQ<br>Enjoy!",
+            format!(
+                "<p>Our new video:</p>{}<p>Enjoy!</p>",
+                youtube("dQw4w9WgXcQ", "https://youtu.be/dQw4w9WgXcQ")
+            ),
+        );
+
+        assert_to_html!(
+            "https://youtu.be/a<br>https://youtu.be/b",
+            format!(
+                "{}{}",
+                youtube("a", "https://youtu.be/a"),
+                youtube("b", "https://youtu.be/b")
+            ),
+        );
+
+        assert_to_html!(
+            "https://open.spotify.com/intl-de/album/4aawyAB9vmqN3uQ7FjRGTy?si=abc",
+            "<div class=\"embed\" data-embed-provider=\"spotify\" data-embed-id=\"album/4aawyAB9vmqN3uQ7FjRGTy\"><a href=\"https://open.spotify.com/intl-de/album/4aawyAB9vmqN3uQ7FjRGTy?si=abc\"><img src=\"/static/images/embeds/spotify.svg\" alt=\"Spotify\" width=\"480\" height=\"270\"><span>Inhalt von Spotify laden</span></a></div>",
+        );
+
+        assert_to_html!(
+            "https://someband.bandcamp.com/album/some-album",
+            "<div class=\"embed\"><a href=\"https://someband.bandcamp.com/album/some-album\"><img src=\"/static/images/embeds/bandcamp.svg\" alt=\"Bandcamp\" width=\"480\" height=\"270\"><span>Auf Bandcamp anhören</span></a></div>",
+        );
+    }
+
+    #[test]
+    fn no_embeds() {
+        // Not on its own line.
+        assert_to_html!(
+            "Watch https://youtu.be/dQw4w9WgXcQ now",
+            "<p>Watch <a href=\"https://youtu.be/dQw4w9WgXcQ\">https://youtu.be/dQw4w9WgXcQ</a> now</p>",
+        );
+
+        // Link with custom text.
+        assert_to_html!(
+            "[our video](https://youtu.be/dQw4w9WgXcQ)",
+            "<p><a href=\"https://youtu.be/dQw4w9WgXcQ\">our video</a></p>",
+        );
+
+        // Unsupported URLs.
+        assert_to_html!(
+            "https://www.youtube.com/@channel",
+            "<p><a href=\"https://www.youtube.com/@channel\">https://www.youtube.com/@channel</a></p>",
+        );
+        assert_to_html!(
+            "https://someband.bandcamp.com",
+            "<p><a href=\"https://someband.bandcamp.com\">https://someband.bandcamp.com</a></p>",
+        );
+    }
+
     #[test]
     fn advanced() {
         assert_to_html!(
@@ -122,6 +859,265 @@ mod tests {
         assert_to_html!("<div>", "<div></div>");
     }
 
+    fn sanitize(html: &str, config: &SanitizeConfig) -> String {
+        render_description(html, config).html
+    }
+
+    macro_rules! assert_sanitize {
+        ($given:expr, $expected:expr $(,)?) => {
+            assert_eq!(sanitize($given, &SanitizeConfig::default()), $expected);
+        };
+    }
+
+    #[test]
+    fn sanitize_tags() {
+        assert_sanitize!(
+            "<p>Hello <strong>World</strong></p>",
+            "<p>Hello <strong>World</strong></p>"
+        );
+        assert_sanitize!(
+            "<p><font color=\"red\">unwrapped</font> <blink>text</blink></p>",
+            "<p>unwrapped text</p>"
+        );
+        assert_sanitize!(
+            "<p>before<iframe src=\"https://evil.tld\"></iframe>after</p>",
+            "<p>beforeafter</p>"
+        );
+        assert_sanitize!("<p>a<!-- comment -->b</p>", "<p>ab</p>");
+        assert_sanitize!(
+            "<p><custom><em>nested</em></custom></p>",
+            "<p><em>nested</em></p>"
+        );
+    }
+
+    #[test]
+    fn sanitize_attributes() {
+        assert_sanitize!(
+            "<div style=\"position: fixed\" onclick=\"alert(1)\" title=\"t\">x</div>",
+            "<div title=\"t\">x</div>"
+        );
+        assert_sanitize!(
+            "<img src=\"/static/images/alhambra.png\" alt=\"Alhambra\" onerror=\"alert(1)\">",
+            "<img src=\"/static/images/alhambra.png\" alt=\"Alhambra\">"
+        );
+    }
+
+    #[test]
+    fn sanitize_embeds() {
+        let html = to_html("https://youtu.be/dQw4w9WgXcQ").unwrap();
+
+        assert_sanitize!(
+            &html,
+            html.replace(
+                "<a href=\"https://youtu.be/dQw4w9WgXcQ\">",
+                "<a href=\"https://youtu.be/dQw4w9WgXcQ\" rel=\"noopener noreferrer\" target=\"_blank\">"
+            )
+        );
+    }
+
+    #[test]
+    fn sanitize_links() {
+        assert_sanitize!("<a href=\"javascript:alert(1)\">x</a>", "<a>x</a>");
+        assert_sanitize!("<a href=\" JaVa\tScRiPt:alert(1)\">x</a>", "<a>x</a>");
+        assert_sanitize!(
+            "<a href=\"/impressum\" target=\"_top\">x</a>",
+            "<a href=\"/impressum\">x</a>"
+        );
+        assert_sanitize!(
+            "<a href=\"//example.com/a\">x</a>",
+            "<a href=\"//example.com/a\" rel=\"noopener noreferrer\" target=\"_blank\">x</a>"
+        );
+        assert_sanitize!(
+            "<a href=\"mailto:foo@musikundkultur.de\">x</a>",
+            "<a href=\"mailto:foo@musikundkultur.de\">x</a>"
+        );
+        assert_sanitize!(
+            "<a href=\"https://musikundkultur.de\" rel=\"opener\" target=\"_top\">x</a>",
+            "<a href=\"https://musikundkultur.de\" rel=\"noopener noreferrer\" target=\"_blank\">x</a>"
+        );
+
+        let config = SanitizeConfig {
+            link_target: None,
+            url_schemes: vec!["https".into()],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            sanitize(
+                "<a href=\"https://musikundkultur.de\" target=\"_blank\">x</a> <a href=\"http://musikundkultur.de\">y</a>",
+                &config
+            ),
+            "<a href=\"https://musikundkultur.de\" rel=\"noopener noreferrer\">x</a> <a>y</a>"
+        );
+    }
+
+    #[test]
+    fn metadata_header() {
+        let (metadata, body) = extract_metadata(
+            "Eintritt: 5 €\nEinlass: 19 Uhr\nTickets: <https://tickets.example.com/a>\nTags: Punk, Rock\n\nThe band.",
+        );
+
+        assert_eq!(
+            metadata,
+            Metadata {
+                price: Some("5 €".into()),
+                doors: Some(Time::constant(19, 0, 0, 0)),
+                ticket_url: Some("https://tickets.example.com/a".into()),
+                tags: vec!["Punk".into(), "Rock".into()],
+                image: None,
+                publish_state: None,
+                publish_at: None,
+            }
+        );
+        assert_eq!(body, "The band.");
+    }
+
+    #[test]
+    fn metadata_publication() {
+        let local = |datetime: &str| {
+            datetime
+                .parse::<jiff::civil::DateTime>()
+                .unwrap()
+                .to_zoned(TimeZone::system())
+                .unwrap()
+                .timestamp()
+        };
+
+        let (metadata, body) =
+            extract_metadata("Status: Entwurf\nVeröffentlichung: 1.3.2025, 10 Uhr\n\nGeheim.");
+        assert_eq!(metadata.publish_state, Some(PublishState::Draft));
+        assert_eq!(metadata.publish_at, Some(local("2025-03-01T10:00")));
+        assert_eq!(body, "Geheim.");
+
+        for (value, expected) in [
+            ("2025-03-01", local("2025-03-01T00:00")),
+            ("2025-03-01 18:30", local("2025-03-01T18:30")),
+            (
+                "2025-03-01T10:00:00+01:00",
+                "2025-03-01T09:00:00Z".parse().unwrap(),
+            ),
+        ] {
+            let (metadata, _) = extract_metadata(&format!("publish: {value}\n\nText"));
+            assert_eq!(metadata.publish_at, Some(expected), "{value}");
+        }
+
+        // Invalid publication settings keep the event a draft and are not shown.
+        for text in [
+            "Veröffentlichung: bald\n\nText",
+            "Veröffentlichung: 1. März\n\nText",
+            "Status: geheim\n\nText",
+            "Status:\n\nText",
+            "Status: Entwurf\nStatus: veröffentlicht\n\nText",
+        ] {
+            let (metadata, body) = extract_metadata(text);
+            assert_eq!(metadata.publish_state, Some(PublishState::Draft), "{text}");
+            assert_eq!(body, "Text", "{text}");
+        }
+
+        let (metadata, _) = extract_metadata("---\nstatus: geheim\n---\nText");
+        assert_eq!(metadata.publish_state, Some(PublishState::Draft));
+
+        // The header block ends at an invalid line, but a publication setting after it still
+        // keeps the event a draft.
+        let text = "Einlass: abends\nStatus: Entwurf\n\nText";
+        let (metadata, body) = extract_metadata(text);
+        assert_eq!(metadata.publish_state, Some(PublishState::Draft));
+        assert_eq!(body, text);
+
+        let (metadata, _) = extract_metadata("Einlass: abends\n\nStatus: Entwurf im Text");
+        assert_eq!(metadata.publish_state, None);
+    }
+
+    #[test]
+    fn metadata_header_google_html() {
+        let (metadata, body) = extract_metadata(
+            "Einlass: 19.30 Uhr<br>Tickets: <a href=\"https://tickets.example.com\">tickets.example.com</a><br><br>Some <b>text</b><br>Eintritt: 5 €",
+        );
+
+        assert_eq!(metadata.doors, Some(Time::constant(19, 30, 0, 0)));
+        assert_eq!(
+            metadata.ticket_url.as_deref(),
+            Some("https://tickets.example.com")
+        );
+        assert_eq!(metadata.price, None);
+        assert_eq!(body, "Some <b>text</b><br>Eintritt: 5 €");
+    }
+
+    #[test]
+    fn metadata_front_matter() {
+        let (metadata, body) = extract_metadata(
+            "---\nprice: \"5 €\"\ndoors: 19:00\ntags: [Jazz, \"Live\"]\nimage: /static/images/alhambra.png\nunknown: ignored\n---\n\nThe band.",
+        );
+
+        assert_eq!(metadata.price.as_deref(), Some("5 €"));
+        assert_eq!(metadata.doors, Some(Time::constant(19, 0, 0, 0)));
+        assert_eq!(metadata.tags, vec!["Jazz", "Live"]);
+        assert_eq!(
+            metadata.image.as_deref(),
+            Some("/static/images/alhambra.png")
+        );
+        assert_eq!(body, "The band.");
+    }
+
+    #[test]
+    fn times() {
+        for (value, expected) in [
+            ("19 Uhr", Some(Time::constant(19, 0, 0, 0))),
+            ("19.30 Uhr", Some(Time::constant(19, 30, 0, 0))),
+            ("7 pm", Some(Time::constant(19, 0, 0, 0))),
+            ("7:30am", Some(Time::constant(7, 30, 0, 0))),
+            ("12 am", Some(Time::midnight())),
+            ("12 pm", Some(Time::constant(12, 0, 0, 0))),
+            ("13 pm", None),
+            ("0 am", None),
+        ] {
+            assert_eq!(parse_time(value), expected, "{value}");
+        }
+    }
+
+    #[test]
+    fn metadata_absent() {
+        for text in [
+            "Just a description.\nEintritt: 5 €",
+            "Einlass: whenever\nEintritt: 5 €",
+            "Tickets: javascript:alert(1)",
+            "https://musikundkultur.de",
+            "---\nprice: 5 €\nno closing line",
+        ] {
+            assert_eq!(extract_metadata(text), (Metadata::default(), text));
+        }
+    }
+
+    #[test]
+    fn description_text_and_excerpt() {
+        let description = render_description(
+            "<p>First <b>paragraph</b><br>with a line break.</p><p onclick=\"x\">Second paragraph.</p>",
+            &SanitizeConfig::default(),
+        );
+
+        assert_eq!(
+            description,
+            Description {
+                html: "<p>First <b>paragraph</b><br>with a line break.</p><p>Second paragraph.</p>"
+                    .into(),
+                text: "First paragraph\nwith a line break.\n\nSecond paragraph.".into(),
+                excerpt: "First paragraph\nwith a line break.".into(),
+            }
+        );
+    }
+
+    #[test]
+    fn excerpt_word_boundary() {
+        assert_eq!(excerpt("", 10), "");
+        assert_eq!(excerpt("short text", 10), "short text");
+        assert_eq!(excerpt("a bit longer text", 10), "a bit…");
+        assert_eq!(
+            excerpt("Eintritt frei, Spenden erwünscht", 16),
+            "Eintritt frei…"
+        );
+        assert_eq!(excerpt("Überlänge", 5), "Über…");
+    }
+
     #[test]
     fn script() {
         assert_to_html!(
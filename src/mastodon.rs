@@ -0,0 +1,779 @@
+//! Announces new events on a Mastodon-compatible instance.
+//!
+//! New events are posted after a configurable delay, which leaves time to fix mistakes or to
+//! remove an event that was added by accident. The text of a post is rendered from the
+//! `mastodon/status.txt` template, and the poster of the event is attached as image.
+//!
+//! Announced events are recorded in a state file, so they are not posted again after a restart.
+//! If the state file doesn't exist yet, the events of the first sync are recorded without
+//! posting them. Events added while the server was down are posted after the next start.
+
+use crate::calendar::diff::EventDiff;
+use crate::calendar::{Calendar, Event};
+use crate::metrics::{DeliveryStatus, MastodonMetrics};
+use crate::{Error, MastodonConfig, Result};
+use jiff::{SignedDuration, Timestamp};
+use minijinja::value::Value;
+use minijinja::{Environment, context};
+use prometheus::Registry;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::oneshot::{self, Receiver, Sender};
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
+
+/// Template of the post text.
+pub const STATUS_TEMPLATE: &str = "mastodon/status.txt";
+
+/// Delay between checks of the state while no post is pending.
+const IDLE_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// Number of attempts before a post is given up.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Delay before the first retry of a failed post. It doubles with every further attempt.
+const RETRY_DELAY: SignedDuration = SignedDuration::from_mins(5);
+
+/// The announcement of an event.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum Announcement {
+    /// The event will be posted.
+    Pending {
+        start_date: Timestamp,
+        attempts: u32,
+        next_attempt: Timestamp,
+    },
+    /// The event was posted.
+    Posted {
+        start_date: Timestamp,
+        posted_at: Timestamp,
+        url: Option<String>,
+    },
+    /// The event is not posted, e.g. because it existed before posting was enabled.
+    Skipped { start_date: Timestamp },
+}
+
+impl Announcement {
+    fn start_date(&self) -> Timestamp {
+        match self {
+            Announcement::Pending { start_date, .. }
+            | Announcement::Posted { start_date, .. }
+            | Announcement::Skipped { start_date } => *start_date,
+        }
+    }
+}
+
+/// Announcements by event ID, stored as JSON file.
+struct State {
+    path: PathBuf,
+    /// `true` until the state was saved for the first time.
+    new: bool,
+    announcements: BTreeMap<String, Announcement>,
+}
+
+impl State {
+    /// Loads the state from `path`, which may not exist yet.
+    fn open(path: &Path) -> Result<State> {
+        let (new, announcements) = match std::fs::read(path) {
+            Ok(data) => (
+                false,
+                serde_json::from_slice(&data).map_err(|err| {
+                    Error::Mastodon(format!("invalid state file {path:?}: {err}"))
+                })?,
+            ),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (true, BTreeMap::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(State {
+            path: path.to_path_buf(),
+            new,
+            announcements,
+        })
+    }
+
+    /// Writes the state, replacing the file atomically.
+    fn save(&mut self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let data = serde_json::to_vec_pretty(&self.announcements)
+            .map_err(|err| Error::Mastodon(format!("failed to serialize state: {err}")))?;
+
+        let tmp_path = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, data)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+
+        self.new = false;
+        Ok(())
+    }
+
+    fn pending(&self) -> impl Iterator<Item = (&String, u32, Timestamp)> {
+        self.announcements
+            .iter()
+            .filter_map(|(id, announcement)| match announcement {
+                Announcement::Pending {
+                    attempts,
+                    next_attempt,
+                    ..
+                } => Some((id, *attempts, *next_attempt)),
+                _ => None,
+            })
+    }
+
+    /// Returns the time of the earliest pending post.
+    fn next_attempt(&self) -> Option<Timestamp> {
+        self.pending()
+            .map(|(_, _, next_attempt)| next_attempt)
+            .min()
+    }
+
+    /// Returns the IDs and attempts of the posts which are due at `now`, oldest first.
+    fn due(&self, now: Timestamp) -> Vec<(String, u32)> {
+        let mut due: Vec<_> = self
+            .pending()
+            .filter(|(_, _, next_attempt)| *next_attempt <= now)
+            .collect();
+
+        due.sort_by_key(|(_, _, next_attempt)| *next_attempt);
+        due.into_iter()
+            .map(|(id, attempts, _)| (id.clone(), attempts))
+            .collect()
+    }
+
+    /// Removes announcements of past events, which are never posted again anyway.
+    fn prune(&mut self, now: Timestamp) {
+        self.announcements
+            .retain(|_, announcement| announcement.start_date() > now);
+    }
+}
+
+/// A status created on the instance.
+#[derive(Deserialize)]
+struct Status {
+    url: Option<String>,
+}
+
+/// A media attachment uploaded to the instance.
+#[derive(Deserialize)]
+struct MediaAttachment {
+    id: String,
+}
+
+/// Client of the Mastodon REST API.
+struct MastodonClient {
+    instance: String,
+    access_token: String,
+    client: reqwest::Client,
+}
+
+impl MastodonClient {
+    /// Uploads an image and returns the ID of the media attachment.
+    async fn upload_media(
+        &self,
+        data: Vec<u8>,
+        file_name: &str,
+        description: &str,
+    ) -> Result<String> {
+        let file = reqwest::multipart::Part::bytes(data)
+            .file_name(file_name.to_string())
+            .mime_str("image/webp")?;
+        let form = reqwest::multipart::Form::new()
+            .part("file", file)
+            .text("description", description.to_string());
+
+        // Large images may still be processed after the response (202 Accepted). Creating the
+        // post fails in that case and succeeds on the next attempt.
+        let media: MediaAttachment = self
+            .client
+            .post(format!("{}/api/v2/media", self.instance))
+            .bearer_auth(&self.access_token)
+            .multipart(form)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(media.id)
+    }
+
+    /// Creates a status and returns its URL. Requests with the same `idempotency_key` create at
+    /// most one status, e.g. when a request timed out after the status was created.
+    async fn post_status(
+        &self,
+        text: &str,
+        media_ids: &[String],
+        visibility: &str,
+        idempotency_key: &str,
+    ) -> Result<Option<String>> {
+        let status: Status = self
+            .client
+            .post(format!("{}/api/v1/statuses", self.instance))
+            .bearer_auth(&self.access_token)
+            .header("Idempotency-Key", idempotency_key)
+            .json(&serde_json::json!({
+                "status": text,
+                "media_ids": media_ids,
+                "visibility": visibility,
+            }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(status.url)
+    }
+}
+
+/// Posts new events to a Mastodon-compatible instance.
+pub struct MastodonPoster {
+    config: MastodonConfig,
+    client: MastodonClient,
+    calendar: Calendar,
+    base_url: String,
+    images_dir: PathBuf,
+    templates: Environment<'static>,
+    state: State,
+    metrics: MastodonMetrics,
+}
+
+impl MastodonPoster {
+    /// Creates a new `MastodonPoster`, loading the announced events from the state file. Posts
+    /// link to event pages under `base_url` and attach posters from the image cache directory
+    /// `images_dir`. The post text is rendered from `templates`.
+    pub fn new(
+        config: MastodonConfig,
+        calendar: Calendar,
+        base_url: &str,
+        images_dir: &Path,
+        templates: Environment<'static>,
+    ) -> Result<MastodonPoster> {
+        let (Some(instance), Some(access_token)) = (&config.instance, &config.access_token) else {
+            return Err(Error::Mastodon(
+                "mastodon.instance and mastodon.access_token must be set".into(),
+            ));
+        };
+
+        let client = MastodonClient {
+            instance: instance.trim_end_matches('/').into(),
+            access_token: access_token.clone(),
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(config.timeout_seconds))
+                .build()?,
+        };
+
+        let state = State::open(&config.state_file)?;
+        let metrics = MastodonMetrics::new()?;
+
+        Ok(MastodonPoster {
+            config,
+            client,
+            calendar,
+            base_url: base_url.trim_end_matches('/').into(),
+            images_dir: images_dir.to_path_buf(),
+            templates,
+            state,
+            metrics,
+        })
+    }
+
+    /// Registers the Mastodon metrics in a prometheus registry.
+    pub fn register_metrics(&self, registry: &Registry) -> Result<()> {
+        self.metrics.register(registry)
+    }
+
+    /// Records the events added in `diff` as pending posts.
+    fn record(&mut self, diff: &EventDiff, now: Timestamp) -> Result<()> {
+        if diff.initial && self.state.new {
+            log::info!(
+                "recording {} existing events, which are not posted to Mastodon",
+                diff.added.len()
+            );
+
+            for event in &diff.added {
+                self.state.announcements.insert(
+                    event.id.clone(),
+                    Announcement::Skipped {
+                        start_date: event.start_date,
+                    },
+                );
+            }
+        } else {
+            let next_attempt = now + SignedDuration::from_secs(self.config.delay_seconds as i64);
+
+            for event in &diff.added {
+                if event.start_date <= now || self.state.announcements.contains_key(&event.id) {
+                    continue;
+                }
+
+                log::info!(
+                    "posting new event {} to Mastodon at {next_attempt}",
+                    event.id
+                );
+                self.state.announcements.insert(
+                    event.id.clone(),
+                    Announcement::Pending {
+                        start_date: event.start_date,
+                        attempts: 0,
+                        next_attempt,
+                    },
+                );
+            }
+
+            for event in &diff.removed {
+                if let Some(Announcement::Pending { .. }) = self.state.announcements.get(&event.id)
+                {
+                    log::info!("not posting removed event {} to Mastodon", event.id);
+                    self.state.announcements.remove(&event.id);
+                }
+            }
+        }
+
+        self.state.prune(now);
+        self.state.save()
+    }
+
+    /// Posts all events which are due.
+    async fn post_due(&mut self) {
+        for (id, attempts) in self.state.due(Timestamp::now()) {
+            match self.attempt(&id, attempts).await {
+                Ok(status) => self.metrics.posts_total(status).inc(),
+                Err(err) => log::error!("failed to update Mastodon state: {err}"),
+            }
+        }
+    }
+
+    /// Attempts to post a single event and updates the state accordingly.
+    async fn attempt(&mut self, id: &str, attempts: u32) -> Result<DeliveryStatus> {
+        let now = Timestamp::now();
+
+        // Post the current version of the event, which may have been edited in the meantime.
+        let Some(event) = self
+            .calendar
+            .get_event(id)
+            .await
+            .filter(|event| event.start_date > now)
+        else {
+            log::info!("not posting event {id} to Mastodon, which is gone or over");
+            self.state.announcements.remove(id);
+            self.state.save()?;
+            return Ok(DeliveryStatus::Dropped);
+        };
+
+        let announcement = match self.post(&event).await {
+            Ok(url) => {
+                log::info!(
+                    "posted event {id} to Mastodon: {}",
+                    url.as_deref().unwrap_or("no URL")
+                );
+                Announcement::Posted {
+                    start_date: event.start_date,
+                    posted_at: Timestamp::now(),
+                    url,
+                }
+            }
+            Err(err) if attempts + 1 >= MAX_ATTEMPTS => {
+                log::error!(
+                    "giving up posting event {id} to Mastodon after {} attempts: {err}",
+                    attempts + 1
+                );
+                Announcement::Skipped {
+                    start_date: event.start_date,
+                }
+            }
+            Err(err) => {
+                let delay = RETRY_DELAY * 2i32.pow(attempts);
+                log::warn!("failed to post event {id} to Mastodon, retrying in {delay:#}: {err}");
+                Announcement::Pending {
+                    start_date: event.start_date,
+                    attempts: attempts + 1,
+                    next_attempt: Timestamp::now() + delay,
+                }
+            }
+        };
+
+        let status = match announcement {
+            Announcement::Posted { .. } => DeliveryStatus::Success,
+            Announcement::Pending { .. } => DeliveryStatus::Failure,
+            Announcement::Skipped { .. } => DeliveryStatus::Dropped,
+        };
+
+        self.state.announcements.insert(id.into(), announcement);
+        self.state.save()?;
+
+        Ok(status)
+    }
+
+    /// Posts an event with its poster, if any. Returns the URL of the post.
+    async fn post(&self, event: &Event) -> Result<Option<String>> {
+        let text = self.render(event)?;
+        let mut media_ids = Vec::new();
+
+        if let Some(path) = event
+            .poster
+            .as_ref()
+            .and_then(|poster| poster.path(&self.images_dir))
+        {
+            // A missing poster is no reason to not announce the event.
+            match tokio::fs::read(&path).await {
+                Ok(data) => {
+                    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+                    media_ids.push(
+                        self.client
+                            .upload_media(data, &file_name, &event.title)
+                            .await?,
+                    );
+                }
+                Err(err) => log::warn!("failed to read poster {path:?}: {err}"),
+            }
+        }
+
+        self.client
+            .post_status(&text, &media_ids, &self.config.visibility, &event.id)
+            .await
+    }
+
+    /// Renders the text of the post announcing `event`.
+    fn render(&self, event: &Event) -> Result<String> {
+        let ctx = context! {
+            event => Value::from_object(event.clone()),
+            url => format!("{}{}", self.base_url, event.url()),
+        };
+
+        let text = self
+            .templates
+            .get_template(STATUS_TEMPLATE)
+            .and_then(|tmpl| tmpl.render(ctx))
+            .map_err(|err| Error::Mastodon(format!("failed to render post: {err:#}")))?;
+
+        Ok(text.trim().to_string())
+    }
+
+    /// Starts a background task which records the new events received via `changes` and posts
+    /// them. Returns a `MastodonTaskHandle` to stop the task.
+    pub fn spawn(self, changes: broadcast::Receiver<Arc<EventDiff>>) -> MastodonTaskHandle {
+        let (stop_tx, stop_rx) = oneshot::channel();
+
+        let join_handle = tokio::spawn(async move {
+            self.run(changes, stop_rx).await;
+        });
+
+        MastodonTaskHandle {
+            join_handle,
+            stop_tx,
+        }
+    }
+
+    async fn run(
+        mut self,
+        mut changes: broadcast::Receiver<Arc<EventDiff>>,
+        mut stop: Receiver<()>,
+    ) {
+        log::info!("posting new events to {}", self.client.instance);
+
+        loop {
+            let delay = match self.state.next_attempt() {
+                Some(next_attempt) => {
+                    Duration::try_from(next_attempt.duration_since(Timestamp::now()))
+                        .unwrap_or_default()
+                }
+                None => IDLE_DELAY,
+            };
+
+            let post = tokio::select! {
+                result = changes.recv() => {
+                    match result {
+                        Ok(diff) => {
+                            if let Err(err) = self.record(&diff, Timestamp::now()) {
+                                log::error!("failed to record new events for Mastodon: {err}");
+                            }
+                        }
+                        Err(RecvError::Lagged(skipped)) => {
+                            log::warn!("Mastodon poster missed {skipped} calendar changes");
+                        }
+                        Err(RecvError::Closed) => break,
+                    }
+
+                    false
+                }
+                _ = tokio::time::sleep(delay) => true,
+                _ = &mut stop => break,
+            };
+
+            // A post which is interrupted by the stop signal is repeated after a restart. The
+            // idempotency key prevents a duplicate if the instance already created it.
+            if post {
+                tokio::select! {
+                    _ = self.post_due() => {}
+                    _ = &mut stop => break,
+                }
+            }
+        }
+
+        log::info!("stopping Mastodon poster");
+    }
+}
+
+/// A handle for stopping a `MastodonPoster` task.
+pub struct MastodonTaskHandle {
+    join_handle: JoinHandle<()>,
+    stop_tx: Sender<()>,
+}
+
+impl MastodonTaskHandle {
+    /// Stops the poster task. Blocks until the background task is finished.
+    pub async fn stop(self) -> io::Result<()> {
+        if self.stop_tx.send(()).is_ok() {
+            self.join_handle.await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calendar::StaticEventSource;
+    use crate::images::{Poster, PosterVariant};
+    use actix_web::{App, HttpRequest, HttpResponse, HttpServer, web};
+    use std::sync::Mutex;
+
+    fn event(id: &str, start_date: Timestamp) -> Event {
+        Event {
+            id: id.into(),
+            title: format!("Konzert {id}"),
+            start_date,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn state() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let path = dir.join("mastodon.json");
+        let now: Timestamp = "2025-03-01T12:00:00Z".parse().unwrap();
+        let later = now + SignedDuration::from_hours(48);
+
+        let mut state = State::open(&path).unwrap();
+        assert!(state.new);
+        state
+            .announcements
+            .insert("a".into(), Announcement::Skipped { start_date: later });
+        state.announcements.insert(
+            "b".into(),
+            Announcement::Pending {
+                start_date: later,
+                attempts: 1,
+                next_attempt: now + SignedDuration::from_mins(5),
+            },
+        );
+        state.announcements.insert(
+            "c".into(),
+            Announcement::Pending {
+                start_date: later,
+                attempts: 0,
+                next_attempt: now,
+            },
+        );
+        state.announcements.insert(
+            "d".into(),
+            Announcement::Posted {
+                start_date: now - SignedDuration::from_hours(1),
+                posted_at: now - SignedDuration::from_hours(48),
+                url: None,
+            },
+        );
+        state.prune(now);
+        state.save().unwrap();
+
+        let state = State::open(&path).unwrap();
+        assert!(!state.new);
+        assert_eq!(
+            state.announcements.keys().collect::<Vec<_>>(),
+            ["a", "b", "c"]
+        );
+        assert_eq!(state.next_attempt(), Some(now));
+        assert_eq!(state.due(now), [("c".to_string(), 0)]);
+        assert_eq!(
+            state.due(now + SignedDuration::from_mins(10)),
+            [("c".to_string(), 0), ("b".to_string(), 1)]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn posts() {
+        // Records received requests and fails the first status.
+        let received: Arc<Mutex<Vec<(String, String, String)>>> = Default::default();
+        let server_received = received.clone();
+
+        let server = HttpServer::new(move || {
+            let received = server_received.clone();
+
+            App::new().route(
+                "/api/{version}/{resource}",
+                web::post().to(move |req: HttpRequest, body: web::Bytes| {
+                    let received = received.clone();
+
+                    async move {
+                        let header = |name| {
+                            req.headers()
+                                .get(name)
+                                .map(|value| value.to_str().unwrap().to_string())
+                                .unwrap_or_default()
+                        };
+
+                        assert_eq!(header("Authorization"), "Bearer token");
+
+                        let mut received = received.lock().unwrap();
+                        received.push((
+                            req.path().to_string(),
+                            header("Idempotency-Key"),
+                            String::from_utf8_lossy(&body).into_owned(),
+                        ));
+
+                        match req.path() {
+                            "/api/v2/media" => HttpResponse::Ok().json(serde_json::json!({
+                                "id": "m1",
+                            })),
+                            _ if received.len() == 2 => HttpResponse::ServiceUnavailable().finish(),
+                            _ => HttpResponse::Ok().json(serde_json::json!({
+                                "id": "1",
+                                "url": "https://social.example/@alhambra/1",
+                            })),
+                        }
+                    }
+                }),
+            )
+        })
+        .workers(1)
+        .bind("127.0.0.1:0")
+        .unwrap();
+        let instance = format!("http://{}", server.addrs()[0]);
+        let server = server.run();
+        let server_handle = server.handle();
+        actix_rt::spawn(server);
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir).unwrap();
+        std::fs::write(dir.join("poster-480.webp"), b"RIFF").unwrap();
+
+        let now = Timestamp::now();
+        let existing = event("existing", now + SignedDuration::from_hours(24));
+        let mut new = event("new", now + SignedDuration::from_hours(48));
+        new.poster = Some(Poster {
+            variants: vec![PosterVariant {
+                width: 480,
+                url: "/images/poster-480.webp".into(),
+            }],
+        });
+        let past = event("past", now - SignedDuration::from_hours(24));
+
+        let calendar = Calendar::new(StaticEventSource::new([
+            existing.clone(),
+            new.clone(),
+            past.clone(),
+        ]))
+        .unwrap();
+        calendar.sync_once().await.unwrap();
+
+        let config = MastodonConfig {
+            enabled: true,
+            instance: Some(instance),
+            access_token: Some("token".into()),
+            delay_seconds: 0,
+            state_file: dir.join("mastodon.json"),
+            ..Default::default()
+        };
+
+        let mut templates = Environment::new();
+        templates.set_loader(minijinja::path_loader("./templates"));
+
+        let mut poster = MastodonPoster::new(
+            config.clone(),
+            calendar.clone(),
+            "https://example.com",
+            dir,
+            templates.clone(),
+        )
+        .unwrap();
+
+        // Events of the first sync are not posted.
+        let initial = EventDiff {
+            initial: true,
+            added: vec![existing.clone()],
+            ..Default::default()
+        };
+        poster.record(&initial, now).unwrap();
+        assert_eq!(poster.state.next_attempt(), None);
+
+        let diff = EventDiff {
+            added: vec![new.clone(), past],
+            ..Default::default()
+        };
+        poster.record(&diff, now).unwrap();
+        assert_eq!(poster.state.due(now), [("new".to_string(), 0)]);
+
+        // The first attempt fails and is retried later.
+        poster.post_due().await;
+        assert_eq!(poster.state.due(Timestamp::now()), []);
+        assert!(matches!(
+            poster.state.announcements["new"],
+            Announcement::Pending { attempts: 1, .. }
+        ));
+
+        assert!(matches!(
+            poster.attempt("new", 1).await.unwrap(),
+            DeliveryStatus::Success
+        ));
+        let Announcement::Posted { url, .. } = &poster.state.announcements["new"] else {
+            panic!("event was not posted");
+        };
+        assert_eq!(url.as_deref(), Some("https://social.example/@alhambra/1"));
+
+        // After a restart, the initial sync doesn't post anything again.
+        let mut poster =
+            MastodonPoster::new(config, calendar, "https://example.com", dir, templates).unwrap();
+        let initial = EventDiff {
+            initial: true,
+            added: vec![existing, new],
+            ..Default::default()
+        };
+        poster.record(&initial, now).unwrap();
+        assert_eq!(poster.state.next_attempt(), None);
+
+        server_handle.stop(false).await;
+
+        let received = received.lock().unwrap();
+        assert_eq!(
+            received
+                .iter()
+                .map(|(path, key, _)| (path.as_str(), key.as_str()))
+                .collect::<Vec<_>>(),
+            [
+                ("/api/v2/media", ""),
+                ("/api/v1/statuses", "new"),
+                ("/api/v2/media", ""),
+                ("/api/v1/statuses", "new"),
+            ]
+        );
+        assert!(received[2].2.contains("Konzert new"));
+
+        let status: serde_json::Value = serde_json::from_str(&received[3].2).unwrap();
+        assert_eq!(status["media_ids"], serde_json::json!(["m1"]));
+        assert_eq!(status["visibility"], "public");
+        let text = status["status"].as_str().unwrap();
+        assert!(text.starts_with("Neu im Programm: Konzert new\n"));
+        assert!(text.ends_with("\n\nhttps://example.com/events/new"));
+    }
+}
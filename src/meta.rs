@@ -0,0 +1,162 @@
+use crate::SiteConfig;
+use crate::calendar::Event;
+use crate::calendar::templating::{format_date, format_time};
+use jiff::tz::TimeZone;
+use serde::Serialize;
+
+/// Metadata for link previews of a page, rendered as OpenGraph and Twitter card meta tags.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct PageMeta {
+    /// The page title.
+    pub title: String,
+    /// A short plain text description of the page.
+    pub description: Option<String>,
+    /// The absolute URL of the page.
+    pub url: String,
+    /// The absolute URL of the preview image.
+    pub image: Option<String>,
+    /// The OpenGraph type.
+    pub og_type: &'static str,
+    /// The locale of the page content, e.g. `de_DE`.
+    pub locale: String,
+    /// The name of the site.
+    pub site_name: String,
+    /// The Twitter card type.
+    pub twitter_card: &'static str,
+    /// The Twitter handle of the site.
+    pub twitter_site: Option<String>,
+    /// The start time of an event in RFC 3339 format.
+    pub start_time: Option<String>,
+}
+
+impl PageMeta {
+    /// Creates the metadata for a regular page at `path`. If `title` is `None`, the site title
+    /// and tagline are used.
+    pub fn new(site: &SiteConfig, base_url: &str, path: &str, title: Option<&str>) -> PageMeta {
+        let title = match title {
+            Some(title) => format!("{title} | {}", site.title),
+            None => format!("{} | {}", site.title, site.tagline),
+        };
+
+        PageMeta {
+            title,
+            description: site.description.clone(),
+            url: absolute_url(base_url, path),
+            image: site
+                .image
+                .as_deref()
+                .map(|image| absolute_url(base_url, image)),
+            og_type: "website",
+            locale: site.locale.clone(),
+            site_name: site.title.clone(),
+            twitter_card: "summary",
+            twitter_site: site.twitter_site.clone(),
+            start_time: None,
+        }
+    }
+
+    /// Creates the metadata for the detail page of an event.
+    pub fn for_event(site: &SiteConfig, base_url: &str, event: &Event) -> PageMeta {
+        let mut meta = PageMeta::new(site, base_url, &event.url(), Some(&event.title));
+        let start_date = event.start_date.to_zoned(TimeZone::system());
+
+        let date = format!(
+            "{}, {} Uhr",
+            format_date(&start_date),
+            format_time(&start_date)
+        );
+
+        meta.description = Some(match &event.excerpt {
+            Some(excerpt) => format!("{date}: {excerpt}"),
+            None => date,
+        });
+        meta.og_type = "article";
+        meta.start_time = Some(start_date.timestamp().to_string());
+
+        meta.image = Some(absolute_url(base_url, &event.preview_image_url()));
+        meta.twitter_card = "summary_large_image";
+
+        meta
+    }
+}
+
+/// Joins a base URL and a path unless the path is already an absolute URL.
+fn absolute_url(base_url: &str, path: &str) -> String {
+    if path.starts_with("https://") || path.starts_with("http://") {
+        path.into()
+    } else {
+        format!("{}{path}", base_url.trim_end_matches('/'))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn site() -> SiteConfig {
+        SiteConfig {
+            title: "Alhambra Luckenwalde".into(),
+            tagline: "Musik- und Kulturförderverein e.V.".into(),
+            description: Some("Kultur in Luckenwalde".into()),
+            canonical_url: None,
+            links: Vec::new(),
+            locale: "de_DE".into(),
+            image: Some("/static/images/alhambra.png".into()),
+            twitter_site: None,
+        }
+    }
+
+    #[test]
+    fn page() {
+        let meta = PageMeta::new(&site(), "https://example.com", "/events", Some("Termine"));
+
+        assert_eq!(meta.title, "Termine | Alhambra Luckenwalde");
+        assert_eq!(meta.description.as_deref(), Some("Kultur in Luckenwalde"));
+        assert_eq!(meta.url, "https://example.com/events");
+        assert_eq!(
+            meta.image.as_deref(),
+            Some("https://example.com/static/images/alhambra.png")
+        );
+        assert_eq!(meta.twitter_card, "summary");
+
+        let meta = PageMeta::new(&site(), "https://example.com/", "/", None);
+        assert_eq!(
+            meta.title,
+            "Alhambra Luckenwalde | Musik- und Kulturförderverein e.V."
+        );
+        assert_eq!(meta.url, "https://example.com/");
+    }
+
+    #[test]
+    fn event() {
+        let start_date = "2025-03-05T18:00:00Z".parse().unwrap();
+        let zoned = jiff::Timestamp::to_zoned(start_date, TimeZone::system());
+
+        let event = Event {
+            id: "abc".into(),
+            title: "Konzert".into(),
+            start_date,
+            excerpt: Some("Eine Band spielt.".into()),
+            ..Default::default()
+        };
+
+        let meta = PageMeta::for_event(&site(), "https://example.com", &event);
+
+        assert_eq!(meta.title, "Konzert | Alhambra Luckenwalde");
+        assert_eq!(
+            meta.description,
+            Some(format!(
+                "{}, {} Uhr: Eine Band spielt.",
+                format_date(&zoned),
+                format_time(&zoned)
+            ))
+        );
+        assert_eq!(meta.url, "https://example.com/events/abc");
+        assert_eq!(meta.start_time.as_deref(), Some("2025-03-05T18:00:00Z"));
+        assert_eq!(
+            meta.image.as_deref(),
+            Some("https://example.com/events/abc/og.png")
+        );
+        assert_eq!(meta.twitter_card, "summary_large_image");
+    }
+}
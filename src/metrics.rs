@@ -14,6 +14,7 @@ pub(crate) struct CalendarMetrics {
     latest_sync_timestamp_seconds: IntGaugeVec,
     sync_duration_seconds: HistogramVec,
     syncs_total: IntCounterVec,
+    event_changes_total: IntCounterVec,
 }
 
 impl CalendarMetrics {
@@ -59,12 +60,22 @@ impl CalendarMetrics {
             &["status"],
         )?;
 
+        let event_changes_total = IntCounterVec::new(
+            opts!(
+                "calendar_event_changes_total",
+                "Total number of event changes detected by calendar syncs"
+            )
+            .namespace(NAMESPACE),
+            &["change"],
+        )?;
+
         Ok(CalendarMetrics {
             events,
             events_total,
             latest_sync_timestamp_seconds,
             sync_duration_seconds,
             syncs_total,
+            event_changes_total,
         })
     }
 
@@ -75,6 +86,7 @@ impl CalendarMetrics {
         registry.register(Box::new(self.latest_sync_timestamp_seconds.clone()))?;
         registry.register(Box::new(self.sync_duration_seconds.clone()))?;
         registry.register(Box::new(self.syncs_total.clone()))?;
+        registry.register(Box::new(self.event_changes_total.clone()))?;
         Ok(())
     }
 
@@ -107,6 +119,131 @@ impl CalendarMetrics {
     pub fn syncs_total(&self, status: CalendarSyncStatus) -> GenericCounter<AtomicU64> {
         self.syncs_total.with_label_values(&[status.as_str()])
     }
+
+    /// Provides access to the event changes counter for a kind of change, e.g. `added`.
+    pub fn event_changes_total(&self, change: &str) -> GenericCounter<AtomicU64> {
+        self.event_changes_total.with_label_values(&[change])
+    }
+}
+
+/// Container for webhook metrics.
+pub(crate) struct WebhookMetrics {
+    deliveries_total: IntCounterVec,
+    delivery_duration_seconds: Histogram,
+    queued_deliveries: IntGauge,
+}
+
+impl WebhookMetrics {
+    /// Creates new WebhookMetrics.
+    pub fn new() -> Result<WebhookMetrics> {
+        let deliveries_total = IntCounterVec::new(
+            opts!(
+                "webhook_deliveries_total",
+                "Total number of webhook delivery attempts"
+            )
+            .namespace(NAMESPACE),
+            &["status"],
+        )?;
+
+        let delivery_duration_seconds = Histogram::with_opts(
+            histogram_opts!(
+                "webhook_delivery_duration_seconds",
+                "Webhook delivery duration in seconds"
+            )
+            .namespace(NAMESPACE),
+        )?;
+
+        let queued_deliveries = IntGauge::with_opts(
+            opts!(
+                "webhook_queued_deliveries",
+                "Number of webhook deliveries waiting to be sent"
+            )
+            .namespace(NAMESPACE),
+        )?;
+
+        Ok(WebhookMetrics {
+            deliveries_total,
+            delivery_duration_seconds,
+            queued_deliveries,
+        })
+    }
+
+    /// Registers the metrics in a prometheus registry.
+    pub fn register(&self, registry: &Registry) -> Result<()> {
+        registry.register(Box::new(self.deliveries_total.clone()))?;
+        registry.register(Box::new(self.delivery_duration_seconds.clone()))?;
+        registry.register(Box::new(self.queued_deliveries.clone()))?;
+        Ok(())
+    }
+
+    /// Provides access to the webhook deliveries counter.
+    pub fn deliveries_total(&self, status: DeliveryStatus) -> GenericCounter<AtomicU64> {
+        self.deliveries_total.with_label_values(&[status.as_str()])
+    }
+
+    /// Provides access to the webhook delivery duration seconds histogram.
+    pub fn delivery_duration_seconds(&self) -> Histogram {
+        self.delivery_duration_seconds.clone()
+    }
+
+    /// Provides access to the queued webhook deliveries gauge.
+    pub fn queued_deliveries(&self) -> GenericGauge<AtomicI64> {
+        self.queued_deliveries.clone()
+    }
+}
+
+/// Metrics of posting new events to Mastodon.
+pub(crate) struct MastodonMetrics {
+    posts_total: IntCounterVec,
+}
+
+impl MastodonMetrics {
+    /// Creates new MastodonMetrics.
+    pub fn new() -> Result<MastodonMetrics> {
+        let posts_total = IntCounterVec::new(
+            opts!(
+                "mastodon_posts_total",
+                "Total number of Mastodon post attempts"
+            )
+            .namespace(NAMESPACE),
+            &["status"],
+        )?;
+
+        Ok(MastodonMetrics { posts_total })
+    }
+
+    /// Registers the metrics in a prometheus registry.
+    pub fn register(&self, registry: &Registry) -> Result<()> {
+        registry.register(Box::new(self.posts_total.clone()))?;
+        Ok(())
+    }
+
+    /// Provides access to the Mastodon posts counter.
+    pub fn posts_total(&self, status: DeliveryStatus) -> GenericCounter<AtomicU64> {
+        self.posts_total.with_label_values(&[status.as_str()])
+    }
+}
+
+/// Outcome of a webhook delivery or Mastodon post attempt.
+#[derive(Debug, Copy, Clone)]
+pub(crate) enum DeliveryStatus {
+    /// The endpoint accepted the delivery or post.
+    Success,
+    /// The attempt failed and will be retried.
+    Failure,
+    /// The attempt failed and the delivery or post was given up.
+    Dropped,
+}
+
+impl DeliveryStatus {
+    /// Returns the status as a &str.
+    pub fn as_str(&self) -> &str {
+        match self {
+            DeliveryStatus::Success => "success",
+            DeliveryStatus::Failure => "failure",
+            DeliveryStatus::Dropped => "dropped",
+        }
+    }
 }
 
 /// Status of a calendar sync operation.
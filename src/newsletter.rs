@@ -0,0 +1,752 @@
+//! Weekly email digest of upcoming events.
+//!
+//! Visitors sign up with their email address and receive a confirmation email (double opt-in).
+//! The signup form is protected against spam like the contact form, so that scripts can't send
+//! confirmation emails to arbitrary addresses.
+//! Confirmed subscribers receive a digest of the upcoming events once a week, rendered from the
+//! `newsletter/digest.txt` and `newsletter/digest.html` templates. Every subscriber has a secret
+//! token, which is part of the confirmation and unsubscribe links.
+//!
+//! Email templates set the `subject` variable at the top level, which is used as the subject of
+//! the email.
+
+pub mod store;
+
+use crate::calendar::{Calendar, Event};
+use crate::contact::SpamGuard;
+use crate::{Error, NewsletterConfig, Result};
+use jiff::civil::{ISOWeekDate, Weekday};
+use jiff::{SignedDuration, Timestamp, Zoned, tz::TimeZone};
+use lettre::message::header::{ContentType, HeaderName, HeaderValue};
+use lettre::message::{Mailbox, MultiPart};
+use lettre::{Address, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use minijinja::value::Value;
+use minijinja::{Environment, context};
+use std::io;
+use std::sync::{Arc, Mutex};
+use store::{Subscriber, SubscriberStore};
+use tokio::sync::oneshot::{self, Receiver, Sender};
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
+
+/// Path of the signup page.
+pub const PATH: &str = "/newsletter";
+
+/// Time after which unconfirmed subscriptions are removed.
+const PENDING_TTL: SignedDuration = SignedDuration::from_hours(7 * 24);
+
+/// Delay before the scheduler tries again if the digest could not be sent yet.
+const RETRY_DELAY: SignedDuration = SignedDuration::from_mins(5);
+
+/// Normalizes an email address entered in the signup form. Returns `None` if it is invalid.
+pub fn parse_address(input: &str) -> Option<String> {
+    let address: Address = input.trim().parse().ok()?;
+    Some(address.to_string().to_lowercase())
+}
+
+/// Returns the ISO week of a date, e.g. `2025-W10`. Digests are sent once per week.
+fn week_key(date: &Zoned) -> String {
+    let week = date.date().iso_week_date();
+    format!("{:04}-W{:02}", week.year(), week.week())
+}
+
+/// Returns the time at which the digest is sent in the week of `now`.
+fn scheduled_time(config: &NewsletterConfig, now: &Zoned) -> Result<Zoned> {
+    let week = now.date().iso_week_date();
+    let invalid = |err: jiff::Error| Error::Newsletter(format!("invalid schedule: {err}"));
+
+    let weekday = Weekday::from_monday_one_offset(config.weekday).map_err(invalid)?;
+
+    ISOWeekDate::new(week.year(), week.week(), weekday)
+        .map_err(invalid)?
+        .date()
+        .at(config.hour, 0, 0, 0)
+        .to_zoned(now.time_zone().clone())
+        .map_err(invalid)
+}
+
+/// A rendered email.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Email {
+    pub subject: String,
+    pub text: String,
+    pub html: Option<String>,
+}
+
+/// Manages subscriptions and sends the digest.
+pub struct Newsletter {
+    config: NewsletterConfig,
+    base_url: String,
+    from: Mailbox,
+    store: Arc<Mutex<SubscriberStore>>,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    templates: Environment<'static>,
+    guard: SpamGuard,
+}
+
+impl Newsletter {
+    /// Creates a newsletter from its configuration. Links in emails point to `base_url`, the
+    /// templates are loaded from `templates`.
+    pub fn new(
+        config: NewsletterConfig,
+        base_url: &str,
+        templates: Environment<'static>,
+    ) -> Result<Newsletter> {
+        let smtp_url = config
+            .smtp_url
+            .as_deref()
+            .ok_or_else(|| Error::Newsletter("newsletter.smtp_url must be set".into()))?;
+
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::from_url(smtp_url)
+            .map_err(|err| Error::Newsletter(format!("invalid SMTP URL: {err}")))?
+            .build();
+
+        let from = config
+            .from
+            .parse()
+            .map_err(|err| Error::Newsletter(format!("invalid sender: {err}")))?;
+
+        let store = SubscriberStore::open(&config.database)?;
+
+        let guard = SpamGuard::new(
+            config.min_fill_seconds,
+            config.max_form_age_seconds,
+            config.rate_limit,
+            config.rate_limit_window_seconds,
+        )?;
+
+        Ok(Newsletter {
+            config,
+            base_url: base_url.trim_end_matches('/').into(),
+            from,
+            store: Arc::new(Mutex::new(store)),
+            transport,
+            templates,
+            guard,
+        })
+    }
+
+    /// Returns the spam checks and rate limit of the signup form.
+    pub fn spam_guard(&self) -> &SpamGuard {
+        &self.guard
+    }
+
+    /// Runs a function with the subscriber store on the blocking thread pool.
+    async fn with_store<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&SubscriberStore) -> Result<T> + Send + 'static,
+    {
+        let store = self.store.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let store = store.lock().unwrap_or_else(|err| err.into_inner());
+            f(&store)
+        })
+        .await
+        .map_err(|err| Error::Newsletter(format!("database task failed: {err}")))?
+    }
+
+    /// Adds a pending subscription and sends the confirmation email. Nothing is sent if the
+    /// address is already subscribed or a confirmation email was sent recently.
+    pub async fn subscribe(&self, email: String) -> Result<()> {
+        let mut bytes = [0; 32];
+        getrandom::fill(&mut bytes)
+            .map_err(|err| Error::Newsletter(format!("failed to generate token: {err}")))?;
+        let token = hex::encode(bytes);
+        let now = Timestamp::now();
+
+        let (address, token) = self
+            .with_store(move |store| {
+                store.purge_pending(now - PENDING_TTL)?;
+                let token = store.subscribe(&email, &token, now)?;
+                Ok((email, token))
+            })
+            .await?;
+
+        let Some(token) = token else {
+            log::debug!("not sending a confirmation email to {address}");
+            return Ok(());
+        };
+
+        let confirm_url = format!("{}{PATH}/confirm?token={token}", self.base_url);
+        let email = self.render("newsletter/confirm.txt", None, context! { confirm_url })?;
+
+        self.send(&address, email, None).await
+    }
+
+    /// Confirms the subscription with `token`. Returns `false` if the token is unknown.
+    pub async fn confirm(&self, token: String) -> Result<bool> {
+        let subscriber = self
+            .with_store(move |store| store.confirm(&token, Timestamp::now()))
+            .await?;
+
+        if let Some(subscriber) = &subscriber {
+            log::info!("confirmed newsletter subscription of {}", subscriber.email);
+        }
+
+        Ok(subscriber.is_some())
+    }
+
+    /// Removes the subscriber with `token`. Returns `false` if the token is unknown.
+    pub async fn unsubscribe(&self, token: String) -> Result<bool> {
+        self.with_store(move |store| store.unsubscribe(&token))
+            .await
+    }
+
+    /// Returns the subscriber with `token`, if any.
+    pub async fn subscriber(&self, token: String) -> Result<Option<Subscriber>> {
+        self.with_store(move |store| store.subscriber(&token)).await
+    }
+
+    /// Returns the events included in the digest sent at `now`.
+    async fn upcoming_events(&self, calendar: &Calendar, now: Timestamp) -> Result<Vec<Event>> {
+        let end = now + SignedDuration::from_hours(self.config.days * 24);
+        calendar.get_events(now..end).await
+    }
+
+    /// Renders the digest of `events` for the subscriber with `token`.
+    fn render_digest(&self, events: &[Event], token: &str) -> Result<Email> {
+        let ctx = context! {
+            events => events
+                .iter()
+                .map(|event| Value::from_object(event.clone()))
+                .collect::<Vec<_>>(),
+            days => self.config.days,
+            base_url => self.base_url,
+            unsubscribe_url => self.unsubscribe_url(token),
+        };
+
+        self.render("newsletter/digest.txt", Some("newsletter/digest.html"), ctx)
+    }
+
+    /// Renders the digest sent at `now` for previews, or `None` if there are no upcoming events.
+    pub async fn preview_digest(
+        &self,
+        calendar: &Calendar,
+        now: Timestamp,
+    ) -> Result<Option<Email>> {
+        let events = self.upcoming_events(calendar, now).await?;
+
+        if events.is_empty() {
+            return Ok(None);
+        }
+
+        self.render_digest(&events, "preview").map(Some)
+    }
+
+    /// Sends the digest of the events following `now` to all confirmed subscribers, or to `to`
+    /// only if given. Nothing is sent if there are no upcoming events. Returns the number of
+    /// recipients.
+    ///
+    /// A digest sent to all subscribers is recorded as the digest of the week of `now`. Every
+    /// delivery is recorded as well, so a digest which was interrupted, e.g. by a shutdown, is
+    /// only sent to the remaining subscribers when it is sent again in the same week.
+    pub async fn send_digest(
+        &self,
+        calendar: &Calendar,
+        now: Timestamp,
+        to: Option<String>,
+    ) -> Result<usize> {
+        let to_single = to.is_some();
+        let events = self.upcoming_events(calendar, now).await?;
+        let week = week_key(&now.to_zoned(TimeZone::system()));
+
+        let subscribers = match to {
+            Some(email) => vec![Subscriber {
+                email,
+                token: "preview".into(),
+                confirmed_at: None,
+            }],
+            None => {
+                let week = week.clone();
+                self.with_store(move |store| store.undelivered(&week))
+                    .await?
+            }
+        };
+
+        let recipients = if events.is_empty() {
+            log::info!("no upcoming events, not sending the newsletter");
+            0
+        } else {
+            let week = (!to_single).then_some(week.as_str());
+            self.send_to(&subscribers, &events, week).await?
+        };
+
+        if to_single {
+            return Ok(recipients);
+        }
+
+        // Record digests sent to all subscribers, so that the scheduler doesn't send another one
+        // in the same week.
+        self.with_store(move |store| store.record_digest(&week, Timestamp::now()))
+            .await
+    }
+
+    /// Sends the digest of `events` to `subscribers`. Deliveries are recorded for the digest of
+    /// `week`, if given. Returns the number of recipients.
+    async fn send_to(
+        &self,
+        subscribers: &[Subscriber],
+        events: &[Event],
+        week: Option<&str>,
+    ) -> Result<usize> {
+        let mut sent = 0;
+
+        for subscriber in subscribers {
+            let email = match self.render_digest(events, &subscriber.token) {
+                Ok(email) => email,
+                Err(err) => {
+                    log::error!("{err}");
+                    break;
+                }
+            };
+            let unsubscribe_url = self.unsubscribe_url(&subscriber.token);
+
+            // A single bad address must not prevent the others from receiving the digest.
+            match self
+                .send(&subscriber.email, email, Some(&unsubscribe_url))
+                .await
+            {
+                Ok(()) => sent += 1,
+                Err(err) => {
+                    log::error!("failed to send newsletter to {}: {err}", subscriber.email);
+                    continue;
+                }
+            }
+
+            if let Some(week) = week {
+                let (week, email) = (week.to_string(), subscriber.email.clone());
+                self.with_store(move |store| {
+                    store.record_delivery(&week, &email, Timestamp::now())
+                })
+                .await?;
+            }
+        }
+
+        log::info!(
+            "sent newsletter with {} events to {sent} of {} subscribers",
+            events.len(),
+            subscribers.len()
+        );
+
+        Ok(sent)
+    }
+
+    fn unsubscribe_url(&self, token: &str) -> String {
+        format!("{}{PATH}/unsubscribe?token={token}", self.base_url)
+    }
+
+    /// Renders the plain text template `text` and the optional HTML alternative `html`.
+    fn render(&self, text: &str, html: Option<&str>, ctx: Value) -> Result<Email> {
+        let render_err = |err: minijinja::Error| {
+            Error::Newsletter(format!("failed to render email template: {err:#}"))
+        };
+
+        let rendered = self
+            .templates
+            .get_template(text)
+            .and_then(|tmpl| tmpl.render_captured(&ctx))
+            .map_err(render_err)?;
+
+        let subject = rendered
+            .state()
+            .lookup("subject")
+            .map(|subject| subject.to_string())
+            .ok_or_else(|| Error::Newsletter("email template does not set a subject".into()))?;
+
+        let html = match html {
+            Some(html) => Some(
+                self.templates
+                    .get_template(html)
+                    .and_then(|tmpl| tmpl.render(&ctx))
+                    .map_err(render_err)?,
+            ),
+            None => None,
+        };
+
+        Ok(Email {
+            subject,
+            text: rendered.into_output(),
+            html,
+        })
+    }
+
+    /// Sends an email. Emails with an `unsubscribe_url` support one-click unsubscribing in mail
+    /// clients (RFC 8058).
+    async fn send(&self, to: &str, email: Email, unsubscribe_url: Option<&str>) -> Result<()> {
+        let to: Mailbox = to
+            .parse()
+            .map_err(|err| Error::Newsletter(format!("invalid recipient `{to}`: {err}")))?;
+
+        let mut builder = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(email.subject);
+
+        if let Some(url) = unsubscribe_url {
+            builder = builder
+                .raw_header(HeaderValue::new(
+                    HeaderName::new_from_ascii_str("List-Unsubscribe"),
+                    format!("<{url}>"),
+                ))
+                .raw_header(HeaderValue::new(
+                    HeaderName::new_from_ascii_str("List-Unsubscribe-Post"),
+                    "List-Unsubscribe=One-Click".into(),
+                ));
+        }
+
+        let message = match email.html {
+            Some(html) => builder.multipart(MultiPart::alternative_plain_html(email.text, html)),
+            None => builder.header(ContentType::TEXT_PLAIN).body(email.text),
+        }
+        .map_err(|err| Error::Newsletter(format!("failed to build email: {err}")))?;
+
+        self.transport
+            .send(message)
+            .await
+            .map_err(|err| Error::Newsletter(format!("failed to send email: {err}")))?;
+
+        Ok(())
+    }
+
+    /// Starts a background task which sends the digest once a week at the configured time.
+    /// Returns a `NewsletterTaskHandle` to stop the task.
+    pub fn spawn_scheduler(self: Arc<Self>, calendar: Calendar) -> NewsletterTaskHandle {
+        let (stop_tx, stop_rx) = oneshot::channel();
+
+        let join_handle = tokio::spawn(async move {
+            self.run_scheduler(calendar, stop_rx).await;
+        });
+
+        NewsletterTaskHandle {
+            join_handle,
+            stop_tx,
+        }
+    }
+
+    async fn run_scheduler(&self, calendar: Calendar, mut stop: Receiver<()>) {
+        loop {
+            // Stop while the digest is being sent, too. Deliveries are recorded one by one, so the
+            // digest is only sent to the remaining subscribers after a restart.
+            let result = tokio::select! {
+                result = self.send_scheduled(&calendar) => result,
+                _ = &mut stop => break,
+            };
+
+            let next = match result {
+                Ok(next) => next,
+                Err(err) => {
+                    log::error!("failed to send newsletter: {err}");
+                    Timestamp::now() + RETRY_DELAY
+                }
+            };
+
+            let delay = Duration::try_from(Timestamp::now().duration_until(next))
+                .unwrap_or_default()
+                .max(Duration::from_secs(1));
+
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = &mut stop => break,
+            }
+        }
+    }
+
+    /// Sends the digest of the current week if it is due and wasn't sent yet. Returns the time
+    /// of the next check.
+    async fn send_scheduled(&self, calendar: &Calendar) -> Result<Timestamp> {
+        let now = Zoned::now();
+        let scheduled = scheduled_time(&self.config, &now)?;
+
+        if now < scheduled {
+            return Ok(scheduled.timestamp());
+        }
+
+        let next = scheduled.timestamp() + SignedDuration::from_hours(7 * 24);
+        let week = week_key(&now);
+
+        if self
+            .with_store(move |store| store.digest_sent(&week))
+            .await?
+        {
+            return Ok(next);
+        }
+
+        // Without a successful sync the digest would be empty.
+        if calendar.health().await.last_success.is_none() {
+            log::info!("calendar is not synced yet, delaying the newsletter");
+            return Ok(now.timestamp() + RETRY_DELAY);
+        }
+
+        self.send_digest(calendar, now.timestamp(), None).await?;
+
+        Ok(next)
+    }
+}
+
+/// Handle to the newsletter scheduler task.
+pub struct NewsletterTaskHandle {
+    join_handle: JoinHandle<()>,
+    stop_tx: Sender<()>,
+}
+
+impl NewsletterTaskHandle {
+    /// Stops the scheduler task. Blocks until the background task is finished.
+    pub async fn stop(self) -> io::Result<()> {
+        if self.stop_tx.send(()).is_ok() {
+            self.join_handle.await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calendar::StaticEventSource;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    /// Starts a minimal SMTP server which accepts all emails. Returns its URL and the received
+    /// messages.
+    async fn smtp_sink() -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("smtp://{}", listener.local_addr().unwrap());
+        let messages = Arc::new(Mutex::new(Vec::new()));
+
+        tokio::spawn({
+            let messages = messages.clone();
+
+            async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    let messages = messages.clone();
+
+                    tokio::spawn(async move {
+                        let (reader, mut writer) = stream.into_split();
+                        let mut lines = BufReader::new(reader).lines();
+                        writer.write_all(b"220 localhost\r\n").await.unwrap();
+
+                        while let Ok(Some(line)) = lines.next_line().await {
+                            let reply: &[u8] = match line.to_ascii_uppercase().as_str() {
+                                "DATA" => {
+                                    writer.write_all(b"354 go ahead\r\n").await.unwrap();
+                                    let mut message = String::new();
+
+                                    while let Ok(Some(line)) = lines.next_line().await {
+                                        if line == "." {
+                                            break;
+                                        }
+                                        message.push_str(&line);
+                                        message.push('\n');
+                                    }
+
+                                    messages.lock().unwrap().push(message);
+                                    b"250 queued\r\n"
+                                }
+                                "QUIT" => {
+                                    writer.write_all(b"221 bye\r\n").await.unwrap();
+                                    break;
+                                }
+                                _ => b"250 ok\r\n",
+                            };
+                            writer.write_all(reply).await.unwrap();
+                        }
+                    });
+                }
+            }
+        });
+
+        (url, messages)
+    }
+
+    /// Undoes the quoted-printable encoding of links in a message.
+    fn decode(message: &str) -> String {
+        message.replace("=\n", "").replace("=3D", "=")
+    }
+
+    #[test]
+    fn address() {
+        assert_eq!(
+            parse_address(" Gast@Example.COM "),
+            Some("gast@example.com".into())
+        );
+        assert_eq!(parse_address("gast"), None);
+        assert_eq!(parse_address("gast@example.com, b@example.com"), None);
+    }
+
+    #[test]
+    fn schedule() {
+        let config = NewsletterConfig {
+            weekday: 2,
+            hour: 10,
+            ..Default::default()
+        };
+
+        let now: Zoned = "2025-03-07T12:00[Europe/Berlin]".parse().unwrap();
+        assert_eq!(week_key(&now), "2025-W10");
+        assert_eq!(
+            scheduled_time(&config, &now).unwrap(),
+            "2025-03-04T10:00[Europe/Berlin]".parse().unwrap()
+        );
+
+        // The ISO week of the last days of a year may belong to the next year.
+        let now: Zoned = "2025-12-29T08:00[Europe/Berlin]".parse().unwrap();
+        assert_eq!(week_key(&now), "2026-W01");
+        assert_eq!(
+            scheduled_time(&config, &now).unwrap(),
+            "2025-12-30T10:00[Europe/Berlin]".parse().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn subscribe_and_send_digest() {
+        let (smtp_url, messages) = smtp_sink().await;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+
+        let config = NewsletterConfig {
+            enabled: true,
+            database: dir.join("newsletter.sqlite3"),
+            smtp_url: Some(smtp_url),
+            ..Default::default()
+        };
+
+        let mut templates = Environment::new();
+        templates.set_loader(minijinja::path_loader("./templates"));
+        templates.add_global(
+            "config",
+            context! { site => context! { title => "Alhambra" } },
+        );
+
+        let newsletter = Newsletter::new(config, "https://example.com/", templates).unwrap();
+
+        let now = Timestamp::now();
+        let calendar = Calendar::new(StaticEventSource::new([
+            Event {
+                title: "Kneipenquiz".into(),
+                start_date: now + SignedDuration::from_hours(48),
+                ..Default::default()
+            },
+            Event {
+                title: "Silvesterparty".into(),
+                start_date: now + SignedDuration::from_hours(60 * 24),
+                ..Default::default()
+            },
+        ]))
+        .unwrap();
+        calendar.sync_once().await.unwrap();
+
+        newsletter
+            .subscribe("gast@example.com".into())
+            .await
+            .unwrap();
+        // Repeated signups don't send another confirmation email right away.
+        newsletter
+            .subscribe("gast@example.com".into())
+            .await
+            .unwrap();
+
+        let confirmation = decode(&messages.lock().unwrap().pop().unwrap());
+        assert!(messages.lock().unwrap().is_empty());
+        assert!(confirmation.contains("To: gast@example.com"));
+
+        let token = confirmation
+            .split("https://example.com/newsletter/confirm?token=")
+            .nth(1)
+            .unwrap()[..64]
+            .to_string();
+
+        // Pending subscribers don't receive the digest.
+        assert_eq!(
+            newsletter.send_digest(&calendar, now, None).await.unwrap(),
+            0
+        );
+
+        assert!(newsletter.confirm(token.clone()).await.unwrap());
+        assert!(!newsletter.confirm("unknown".into()).await.unwrap());
+
+        assert_eq!(
+            newsletter.send_digest(&calendar, now, None).await.unwrap(),
+            1
+        );
+
+        let digest = decode(&messages.lock().unwrap().pop().unwrap());
+        assert!(digest.contains("Subject: Diese Woche im Alhambra"));
+        assert!(digest.contains(&format!(
+            "List-Unsubscribe: <https://example.com/newsletter/unsubscribe?token={token}>"
+        )));
+        assert!(digest.contains("Content-Type: text/html"));
+        assert!(digest.contains("Kneipenquiz"));
+        assert!(!digest.contains("Silvesterparty"));
+
+        let week = week_key(&now.to_zoned(TimeZone::system()));
+        assert!(
+            newsletter
+                .with_store(move |store| store.digest_sent(&week))
+                .await
+                .unwrap()
+        );
+
+        assert!(newsletter.unsubscribe(token).await.unwrap());
+        assert_eq!(
+            newsletter.send_digest(&calendar, now, None).await.unwrap(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn resume_digest() {
+        let (smtp_url, messages) = smtp_sink().await;
+        let tmp = tempfile::tempdir().unwrap();
+
+        let config = NewsletterConfig {
+            enabled: true,
+            database: tmp.path().join("newsletter.sqlite3"),
+            smtp_url: Some(smtp_url),
+            ..Default::default()
+        };
+
+        let mut templates = Environment::new();
+        templates.set_loader(minijinja::path_loader("./templates"));
+        templates.add_global(
+            "config",
+            context! { site => context! { title => "Alhambra" } },
+        );
+
+        let newsletter = Newsletter::new(config, "https://example.com/", templates).unwrap();
+
+        let now = Timestamp::now();
+        let calendar = Calendar::new(StaticEventSource::new([Event {
+            title: "Kneipenquiz".into(),
+            start_date: now + SignedDuration::from_hours(48),
+            ..Default::default()
+        }]))
+        .unwrap();
+        calendar.sync_once().await.unwrap();
+
+        // The digest of the week was interrupted after it was sent to the first subscriber.
+        let week = week_key(&now.to_zoned(TimeZone::system()));
+        newsletter
+            .with_store(move |store| {
+                for (email, token) in [("a@example.com", "t1"), ("b@example.com", "t2")] {
+                    store.subscribe(email, token, now)?;
+                    store.confirm(token, now)?;
+                }
+                store.record_delivery(&week, "a@example.com", now)
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(
+            newsletter.send_digest(&calendar, now, None).await.unwrap(),
+            2
+        );
+
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 1);
+        assert!(decode(&messages[0]).contains("To: b@example.com"));
+    }
+}
@@ -0,0 +1,365 @@
+//! SQLite storage of newsletter subscribers and sent digests.
+
+use crate::Result;
+use jiff::{SignedDuration, Timestamp};
+use rusqlite::{Connection, OptionalExtension, params};
+use std::path::Path;
+
+/// Minimum time between two confirmation emails to the same address, so that the signup form
+/// can't be abused to flood someone's inbox.
+const RESEND_CONFIRMATION_AFTER: SignedDuration = SignedDuration::from_hours(1);
+
+/// Schema migrations, applied in order. The number of applied migrations is stored as
+/// `user_version`. Timestamps are stored as UNIX seconds.
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE subscribers (
+        email TEXT PRIMARY KEY,
+        token TEXT NOT NULL UNIQUE,
+        created_at INTEGER NOT NULL,
+        confirmation_sent_at INTEGER NOT NULL,
+        confirmed_at INTEGER
+    );
+
+    CREATE TABLE digests (
+        week TEXT PRIMARY KEY,
+        sent_at INTEGER NOT NULL,
+        recipients INTEGER NOT NULL
+    );
+",
+    "
+    CREATE TABLE digest_deliveries (
+        week TEXT NOT NULL,
+        email TEXT NOT NULL,
+        sent_at INTEGER NOT NULL,
+        PRIMARY KEY (week, email)
+    );
+",
+];
+
+/// A newsletter subscriber.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subscriber {
+    /// The email address.
+    pub email: String,
+    /// Secret for confirming the subscription and unsubscribing.
+    pub token: String,
+    /// The time of the confirmation, `None` while the subscription is pending.
+    pub confirmed_at: Option<Timestamp>,
+}
+
+/// Subscribers and sent digests, stored in a SQLite database.
+pub struct SubscriberStore {
+    conn: Connection,
+}
+
+impl SubscriberStore {
+    /// Opens the database at `path`, creating it if necessary.
+    pub fn open(path: &Path) -> Result<SubscriberStore> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        SubscriberStore::migrate(Connection::open(path)?)
+    }
+
+    /// Opens a temporary in-memory database.
+    pub fn open_in_memory() -> Result<SubscriberStore> {
+        SubscriberStore::migrate(Connection::open_in_memory()?)
+    }
+
+    fn migrate(conn: Connection) -> Result<SubscriberStore> {
+        let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            log::info!("migrating newsletter database to version {}", i + 1);
+            conn.execute_batch(&format!(
+                "BEGIN; {migration} PRAGMA user_version = {}; COMMIT;",
+                i + 1
+            ))?;
+        }
+
+        Ok(SubscriberStore { conn })
+    }
+
+    /// Adds a pending subscription for `email` with a new `token`. Returns the token to send a
+    /// confirmation email for, which is `None` if the subscription is already confirmed or a
+    /// confirmation email was sent recently.
+    pub fn subscribe(&self, email: &str, token: &str, now: Timestamp) -> Result<Option<String>> {
+        let existing = self
+            .conn
+            .query_row(
+                "SELECT token, confirmation_sent_at, confirmed_at FROM subscribers WHERE email = ?1",
+                params![email],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, i64>(1)?,
+                        row.get::<_, Option<i64>>(2)?,
+                    ))
+                },
+            )
+            .optional()?;
+
+        match existing {
+            None => {
+                self.conn.execute(
+                    "INSERT INTO subscribers (email, token, created_at, confirmation_sent_at)
+                     VALUES (?1, ?2, ?3, ?3)",
+                    params![email, token, now.as_second()],
+                )?;
+                Ok(Some(token.into()))
+            }
+            Some((_, _, Some(_))) => Ok(None),
+            Some((token, sent_at, None)) => {
+                if now.duration_since(timestamp(sent_at)) < RESEND_CONFIRMATION_AFTER {
+                    return Ok(None);
+                }
+
+                self.conn.execute(
+                    "UPDATE subscribers SET confirmation_sent_at = ?1 WHERE email = ?2",
+                    params![now.as_second(), email],
+                )?;
+                Ok(Some(token))
+            }
+        }
+    }
+
+    /// Confirms the subscription with `token`. Returns the subscriber, or `None` if the token is
+    /// unknown.
+    pub fn confirm(&self, token: &str, now: Timestamp) -> Result<Option<Subscriber>> {
+        self.conn.execute(
+            "UPDATE subscribers SET confirmed_at = ?1 WHERE token = ?2 AND confirmed_at IS NULL",
+            params![now.as_second(), token],
+        )?;
+
+        self.subscriber(token)
+    }
+
+    /// Removes the subscriber with `token`. Returns `false` if the token is unknown.
+    pub fn unsubscribe(&self, token: &str) -> Result<bool> {
+        let deleted = self
+            .conn
+            .execute("DELETE FROM subscribers WHERE token = ?1", params![token])?;
+
+        Ok(deleted > 0)
+    }
+
+    /// Returns the subscriber with `token`, if any.
+    pub fn subscriber(&self, token: &str) -> Result<Option<Subscriber>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT email, token, confirmed_at FROM subscribers WHERE token = ?1",
+                params![token],
+                subscriber_from_row,
+            )
+            .optional()?)
+    }
+
+    /// Returns all confirmed subscribers.
+    pub fn confirmed(&self) -> Result<Vec<Subscriber>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT email, token, confirmed_at FROM subscribers
+             WHERE confirmed_at IS NOT NULL ORDER BY email",
+        )?;
+
+        let subscribers = stmt
+            .query_map([], subscriber_from_row)?
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(subscribers)
+    }
+
+    /// Returns the confirmed subscribers which didn't receive the digest of `week` yet.
+    pub fn undelivered(&self, week: &str) -> Result<Vec<Subscriber>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT email, token, confirmed_at FROM subscribers
+             WHERE confirmed_at IS NOT NULL
+             AND email NOT IN (SELECT email FROM digest_deliveries WHERE week = ?1)
+             ORDER BY email",
+        )?;
+
+        let subscribers = stmt
+            .query_map(params![week], subscriber_from_row)?
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(subscribers)
+    }
+
+    /// Records that the digest of `week` was sent to `email`, so that it isn't sent again if
+    /// sending the digest is interrupted.
+    pub fn record_delivery(&self, week: &str, email: &str, now: Timestamp) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO digest_deliveries (week, email, sent_at) VALUES (?1, ?2, ?3)",
+            params![week, email, now.as_second()],
+        )?;
+
+        Ok(())
+    }
+
+    /// Removes pending subscriptions created before `before`. Returns the number of removed
+    /// subscriptions.
+    pub fn purge_pending(&self, before: Timestamp) -> Result<usize> {
+        Ok(self.conn.execute(
+            "DELETE FROM subscribers WHERE confirmed_at IS NULL AND created_at < ?1",
+            params![before.as_second()],
+        )?)
+    }
+
+    /// Returns `true` if the digest of `week` was already sent.
+    pub fn digest_sent(&self, week: &str) -> Result<bool> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT 1 FROM digests WHERE week = ?1",
+                params![week],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some())
+    }
+
+    /// Records that the digest of `week` was sent to all subscribers. The recorded deliveries of
+    /// the week are replaced by their number. Returns the number of recipients.
+    pub fn record_digest(&self, week: &str, now: Timestamp) -> Result<usize> {
+        let tx = self.conn.unchecked_transaction()?;
+
+        let recipients = tx.query_row(
+            "SELECT COUNT(*) FROM digest_deliveries WHERE week = ?1",
+            params![week],
+            |row| row.get::<_, i64>(0),
+        )?;
+        tx.execute(
+            "INSERT OR REPLACE INTO digests (week, sent_at, recipients) VALUES (?1, ?2, ?3)",
+            params![week, now.as_second(), recipients],
+        )?;
+        tx.execute(
+            "DELETE FROM digest_deliveries WHERE week = ?1",
+            params![week],
+        )?;
+        tx.commit()?;
+
+        Ok(recipients as usize)
+    }
+}
+
+fn subscriber_from_row(row: &rusqlite::Row) -> rusqlite::Result<Subscriber> {
+    Ok(Subscriber {
+        email: row.get(0)?,
+        token: row.get(1)?,
+        confirmed_at: row.get::<_, Option<i64>>(2)?.map(timestamp),
+    })
+}
+
+fn timestamp(seconds: i64) -> Timestamp {
+    Timestamp::from_second(seconds).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn double_opt_in() {
+        let store = SubscriberStore::open_in_memory().unwrap();
+        let now: Timestamp = "2025-03-01T12:00:00Z".parse().unwrap();
+
+        assert_eq!(
+            store.subscribe("a@example.com", "t1", now).unwrap(),
+            Some("t1".into())
+        );
+        // No new confirmation email right away, and the original token is kept.
+        assert_eq!(store.subscribe("a@example.com", "t2", now).unwrap(), None);
+        assert_eq!(
+            store
+                .subscribe("a@example.com", "t3", now + SignedDuration::from_hours(2))
+                .unwrap(),
+            Some("t1".into())
+        );
+        assert!(store.confirmed().unwrap().is_empty());
+
+        assert_eq!(store.confirm("unknown", now).unwrap(), None);
+        let subscriber = store.confirm("t1", now).unwrap().unwrap();
+        assert_eq!(subscriber.email, "a@example.com");
+        assert_eq!(subscriber.confirmed_at, Some(now));
+
+        // Confirmed subscribers don't get another confirmation email.
+        assert_eq!(
+            store
+                .subscribe("a@example.com", "t4", now + SignedDuration::from_hours(3))
+                .unwrap(),
+            None
+        );
+        assert_eq!(store.confirmed().unwrap(), [subscriber]);
+
+        assert!(store.unsubscribe("t1").unwrap());
+        assert!(!store.unsubscribe("t1").unwrap());
+        assert!(store.confirmed().unwrap().is_empty());
+    }
+
+    #[test]
+    fn purge_pending() {
+        let store = SubscriberStore::open_in_memory().unwrap();
+        let now: Timestamp = "2025-03-01T12:00:00Z".parse().unwrap();
+
+        store.subscribe("old@example.com", "t1", now).unwrap();
+        store.subscribe("confirmed@example.com", "t2", now).unwrap();
+        store.confirm("t2", now).unwrap();
+        store
+            .subscribe(
+                "new@example.com",
+                "t3",
+                now + SignedDuration::from_hours(48),
+            )
+            .unwrap();
+
+        assert_eq!(
+            store
+                .purge_pending(now + SignedDuration::from_hours(24))
+                .unwrap(),
+            1
+        );
+        assert_eq!(store.subscriber("t1").unwrap(), None);
+        assert!(store.subscriber("t2").unwrap().is_some());
+        assert!(store.subscriber("t3").unwrap().is_some());
+    }
+
+    #[test]
+    fn digests() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let path = dir.join("newsletter.sqlite3");
+        let now = Timestamp::now();
+
+        let store = SubscriberStore::open(&path).unwrap();
+        for (email, token) in [("a@example.com", "t1"), ("b@example.com", "t2")] {
+            store.subscribe(email, token, now).unwrap();
+            store.confirm(token, now).unwrap();
+        }
+
+        // Subscribers who already received the digest of the week are skipped.
+        store
+            .record_delivery("2025-W10", "a@example.com", now)
+            .unwrap();
+        let emails = |week| {
+            store
+                .undelivered(week)
+                .unwrap()
+                .into_iter()
+                .map(|subscriber| subscriber.email)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(emails("2025-W10"), ["b@example.com"]);
+        assert_eq!(emails("2025-W11"), ["a@example.com", "b@example.com"]);
+
+        assert!(!store.digest_sent("2025-W10").unwrap());
+        assert_eq!(store.record_digest("2025-W10", now).unwrap(), 1);
+        drop(store);
+
+        // Migrations are only applied once.
+        let store = SubscriberStore::open(&path).unwrap();
+        assert!(store.digest_sent("2025-W10").unwrap());
+        assert!(!store.digest_sent("2025-W11").unwrap());
+    }
+}
@@ -0,0 +1,403 @@
+//! Generated social preview images for events.
+//!
+//! Every event gets a 1200×630 PNG card showing the site logo, the event title and date in the
+//! site's colors. Rendered cards are cached on disk by a hash of everything that is displayed on
+//! them, so a changed event automatically gets a new card.
+
+use crate::calendar::Event;
+use crate::calendar::templating::{format_date, format_time};
+use crate::{Error, Result};
+use ab_glyph::{Font, FontVec, PxScale, ScaleFont};
+use image::{ImageFormat, Rgba, RgbaImage, imageops};
+use jiff::tz::TimeZone;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Width of preview images in pixels.
+pub const WIDTH: u32 = 1200;
+/// Height of preview images in pixels.
+pub const HEIGHT: u32 = 630;
+
+/// Bump this whenever the layout changes to invalidate cached images.
+const LAYOUT_VERSION: u32 = 1;
+
+const LOGO_PATH: &str = "./static/images/alhambra.png";
+const TITLE_FONT_PATH: &str = "./static/fonts/lato/lato-bold.ttf";
+const DATE_FONT_PATH: &str = "./static/fonts/lato/lato-regular.ttf";
+
+const BACKGROUND: Rgba<u8> = Rgba([0xc2, 0x1e, 0x1d, 0xff]);
+const FOREGROUND: Rgba<u8> = Rgba([0xff, 0xff, 0xff, 0xff]);
+
+const MARGIN: u32 = 60;
+const TITLE_TOP: f32 = 210.0;
+const TITLE_MAX_LINES: usize = 3;
+const TITLE_SIZES: [f32; 3] = [88.0, 72.0, 60.0];
+const DATE_SIZE: f32 = 48.0;
+const DATE_BASELINE: f32 = HEIGHT as f32 - 70.0;
+
+/// Cached images which were not requested for this long are deleted.
+const PRUNE_AFTER: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+/// Minimum interval between two prunings of the cache.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Renders and caches preview images.
+pub struct PreviewRenderer {
+    cache_dir: PathBuf,
+    logo: RgbaImage,
+    title_font: FontVec,
+    date_font: FontVec,
+    last_pruned: Mutex<Option<Instant>>,
+}
+
+impl PreviewRenderer {
+    /// Creates a new `PreviewRenderer` which stores rendered images in `cache_dir`.
+    pub fn new(cache_dir: impl Into<PathBuf>) -> Result<PreviewRenderer> {
+        let cache_dir = cache_dir.into();
+        std::fs::create_dir_all(&cache_dir)?;
+
+        let renderer = PreviewRenderer {
+            cache_dir,
+            logo: image::open(LOGO_PATH)?.to_rgba8(),
+            title_font: load_font(TITLE_FONT_PATH)?,
+            date_font: load_font(DATE_FONT_PATH)?,
+            last_pruned: Mutex::new(None),
+        };
+        renderer.prune_if_due();
+
+        Ok(renderer)
+    }
+
+    /// Returns the path of the preview image of `event`, rendering it first if it is not cached
+    /// yet.
+    pub fn render(&self, event: &Event) -> Result<PathBuf> {
+        let date = date_line(event);
+        let path = self
+            .cache_dir
+            .join(format!("{}.png", cache_key(event, &date)));
+
+        if path.exists() {
+            // The modification time tracks the last use, see `prune`.
+            if let Err(err) = touch(&path) {
+                log::warn!("failed to update modification time of {path:?}: {err}");
+            }
+            return Ok(path);
+        }
+
+        self.prune_if_due();
+
+        log::info!("rendering preview image for event {}", event.id);
+
+        let image = self.draw(&event.title, &date);
+
+        // Write to a temporary file first to never serve partially written images.
+        let tmp_path = path.with_extension("png.tmp");
+        image.save_with_format(&tmp_path, ImageFormat::Png)?;
+        std::fs::rename(tmp_path, &path)?;
+
+        Ok(path)
+    }
+
+    fn draw(&self, title: &str, date: &str) -> RgbaImage {
+        let mut image = RgbaImage::from_pixel(WIDTH, HEIGHT, BACKGROUND);
+
+        let logo_x = (WIDTH - self.logo.width().min(WIDTH)) / 2;
+        imageops::overlay(&mut image, &self.logo, logo_x.into(), MARGIN.into());
+
+        let max_width = (WIDTH - 2 * MARGIN) as f32;
+
+        // Use the largest font size at which the title fits, truncating it at the smallest size.
+        let (scale, lines) = TITLE_SIZES
+            .iter()
+            .map(|&size| {
+                let scale = PxScale::from(size);
+                (scale, wrap(&self.title_font, scale, title, max_width))
+            })
+            .find(|(_, lines)| lines.len() <= TITLE_MAX_LINES)
+            .unwrap_or_else(|| {
+                let scale = PxScale::from(TITLE_SIZES[TITLE_SIZES.len() - 1]);
+                let mut lines = wrap(&self.title_font, scale, title, max_width);
+                lines.truncate(TITLE_MAX_LINES);
+                let last = lines.last_mut().unwrap();
+                *last = ellipsize(&self.title_font, scale, last, max_width);
+                (scale, lines)
+            });
+
+        let scaled = self.title_font.as_scaled(scale);
+        let line_height = scaled.height() + scaled.line_gap();
+        let mut baseline = TITLE_TOP + scaled.ascent();
+
+        for line in &lines {
+            draw_centered(&mut image, &self.title_font, scale, line, baseline);
+            baseline += line_height;
+        }
+
+        draw_centered(
+            &mut image,
+            &self.date_font,
+            PxScale::from(DATE_SIZE),
+            date,
+            DATE_BASELINE,
+        );
+
+        image
+    }
+
+    /// Prunes the cache, unless it was pruned within `PRUNE_INTERVAL`. Failures are logged.
+    fn prune_if_due(&self) {
+        let mut last_pruned = self
+            .last_pruned
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+
+        if last_pruned.is_some_and(|last_pruned| last_pruned.elapsed() < PRUNE_INTERVAL) {
+            return;
+        }
+
+        *last_pruned = Some(Instant::now());
+
+        if let Err(err) = self.prune(SystemTime::now()) {
+            log::warn!("failed to prune preview image cache: {err}");
+        }
+    }
+
+    /// Deletes cached images which were not requested since `PRUNE_AFTER` before `now`, e.g.
+    /// those of past or changed events.
+    fn prune(&self, now: SystemTime) -> Result<()> {
+        for entry in std::fs::read_dir(&self.cache_dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+
+            let unused = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| now.duration_since(modified).ok())
+                .is_some_and(|age| age > PRUNE_AFTER);
+
+            if metadata.is_file() && unused {
+                log::debug!("removing unused preview image {:?}", entry.file_name());
+                std::fs::remove_file(entry.path())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Sets the modification time of a file to now.
+fn touch(path: &Path) -> std::io::Result<()> {
+    std::fs::File::options()
+        .write(true)
+        .open(path)?
+        .set_modified(SystemTime::now())
+}
+
+fn load_font(path: impl AsRef<Path>) -> Result<FontVec> {
+    let path = path.as_ref();
+
+    FontVec::try_from_vec(std::fs::read(path)?)
+        .map_err(|err| Error::Font(format!("{path:?}: {err}")))
+}
+
+/// Returns the date line displayed on the preview image.
+fn date_line(event: &Event) -> String {
+    let start_date = event.start_date.to_zoned(TimeZone::system());
+
+    format!(
+        "{} · {} Uhr",
+        format_date(&start_date),
+        format_time(&start_date)
+    )
+}
+
+/// Derives the cache key from everything that is displayed on the preview image.
+fn cache_key(event: &Event, date: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(LAYOUT_VERSION.to_be_bytes());
+    hasher.update(event.id.as_bytes());
+    hasher.update([0]);
+    hasher.update(event.title.as_bytes());
+    hasher.update([0]);
+    hasher.update(date.as_bytes());
+    hex::encode(&hasher.finalize()[..8])
+}
+
+/// Returns the width of `text` in pixels.
+fn text_width(font: &FontVec, scale: PxScale, text: &str) -> f32 {
+    let scaled = font.as_scaled(scale);
+    let mut width = 0.0;
+    let mut prev = None;
+
+    for c in text.chars() {
+        let id = scaled.glyph_id(c);
+
+        if let Some(prev) = prev {
+            width += scaled.kern(prev, id);
+        }
+
+        width += scaled.h_advance(id);
+        prev = Some(id);
+    }
+
+    width
+}
+
+/// Breaks `text` into lines which fit into `max_width`. Words which are too long on their own are
+/// kept on a line of their own.
+fn wrap(font: &FontVec, scale: PxScale, text: &str, max_width: f32) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut line = String::new();
+
+    for word in text.split_whitespace() {
+        let candidate = if line.is_empty() {
+            word.to_string()
+        } else {
+            format!("{line} {word}")
+        };
+
+        if line.is_empty() || text_width(font, scale, &candidate) <= max_width {
+            line = candidate;
+        } else {
+            lines.push(std::mem::replace(&mut line, word.to_string()));
+        }
+    }
+
+    if !line.is_empty() {
+        lines.push(line);
+    }
+
+    lines
+}
+
+/// Shortens `text` until it fits into `max_width` including a trailing ellipsis.
+fn ellipsize(font: &FontVec, scale: PxScale, text: &str, max_width: f32) -> String {
+    let mut text = text.to_string();
+
+    loop {
+        let candidate = format!("{}…", text.trim_end());
+
+        if text.is_empty() || text_width(font, scale, &candidate) <= max_width {
+            return candidate;
+        }
+
+        text.pop();
+    }
+}
+
+/// Draws a horizontally centered line of text onto `image`.
+fn draw_centered(image: &mut RgbaImage, font: &FontVec, scale: PxScale, text: &str, baseline: f32) {
+    let scaled = font.as_scaled(scale);
+    let mut x = (WIDTH as f32 - text_width(font, scale, text)) / 2.0;
+    let mut prev = None;
+
+    for c in text.chars() {
+        let id = scaled.glyph_id(c);
+
+        if let Some(prev) = prev {
+            x += scaled.kern(prev, id);
+        }
+
+        let glyph = id.with_scale_and_position(scale, ab_glyph::point(x, baseline));
+        x += scaled.h_advance(id);
+        prev = Some(id);
+
+        let Some(outline) = font.outline_glyph(glyph) else {
+            continue;
+        };
+
+        let bounds = outline.px_bounds();
+
+        outline.draw(|gx, gy, coverage| {
+            let px = bounds.min.x as i32 + gx as i32;
+            let py = bounds.min.y as i32 + gy as i32;
+
+            if px < 0 || py < 0 || px >= WIDTH as i32 || py >= HEIGHT as i32 {
+                return;
+            }
+
+            let pixel = image.get_pixel_mut(px as u32, py as u32);
+
+            for channel in 0..3 {
+                let bg = f32::from(pixel[channel]);
+                let fg = f32::from(FOREGROUND[channel]);
+                pixel[channel] = (bg + (fg - bg) * coverage.min(1.0)).round() as u8;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::GenericImageView;
+
+    #[test]
+    fn wrap_lines() {
+        let font = load_font(TITLE_FONT_PATH).unwrap();
+        let scale = PxScale::from(88.0);
+
+        assert_eq!(wrap(&font, scale, "Konzert", 1080.0), vec!["Konzert"]);
+        assert_eq!(wrap(&font, scale, "  ", 1080.0), Vec::<String>::new());
+
+        let lines = wrap(
+            &font,
+            scale,
+            "Vorverkaufsparty Weihnachtskonzert mit zwei Bands und DJ",
+            1080.0,
+        );
+        assert!(lines.len() > 1);
+        assert!(
+            lines
+                .iter()
+                .all(|line| text_width(&font, scale, line) <= 1080.0)
+        );
+
+        let ellipsized = ellipsize(&font, scale, &lines[0], 300.0);
+        assert!(ellipsized.ends_with('…'));
+        assert!(text_width(&font, scale, &ellipsized) <= 300.0);
+    }
+
+    #[test]
+    fn render_and_cache() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cache_dir = tmp.path();
+        let renderer = PreviewRenderer::new(cache_dir).unwrap();
+
+        let mut event = Event {
+            id: "2030-01-10-konzert".into(),
+            title: "Konzert".into(),
+            start_date: "2030-01-10T18:00:00Z".parse().unwrap(),
+            ..Default::default()
+        };
+
+        let path = renderer.render(&event).unwrap();
+        let image = image::open(&path).unwrap();
+        assert_eq!(image.dimensions(), (WIDTH, HEIGHT));
+        assert_eq!(image.get_pixel(5, 5), BACKGROUND);
+
+        assert_eq!(renderer.render(&event).unwrap(), path);
+
+        // Changing the event invalidates the cached image.
+        event.title = "Konzert (verlegt)".into();
+        let changed = renderer.render(&event).unwrap();
+        assert_ne!(changed, path);
+
+        // Images which were not requested for a while are pruned.
+        renderer
+            .prune(SystemTime::now() + PRUNE_AFTER - Duration::from_secs(60))
+            .unwrap();
+        assert!(path.exists());
+        assert!(changed.exists());
+
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() - PRUNE_AFTER * 2)
+            .unwrap();
+        renderer.prune(SystemTime::now()).unwrap();
+        assert!(!path.exists());
+        assert!(changed.exists());
+    }
+}
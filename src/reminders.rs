@@ -0,0 +1,449 @@
+//! Reminders of upcoming events sent as Web Push notifications.
+//!
+//! Visitors subscribe their browser to reminders for a single event or for all events with a
+//! tag. The push subscriptions are stored server-side, and a scheduler sends a notification to
+//! every interested subscription shortly before an event starts. Messages are authenticated with
+//! the VAPID key pair of the site and encrypted for the browser, see [`webpush`].
+
+pub mod store;
+pub mod webpush;
+
+use crate::calendar::templating::{format_date, format_time};
+use crate::calendar::{Calendar, Event};
+use crate::{Error, RemindersConfig, Result};
+use jiff::{SignedDuration, Timestamp, tz::TimeZone};
+use serde::Deserialize;
+use std::io;
+use std::sync::{Arc, Mutex};
+use store::SubscriptionStore;
+use tokio::sync::oneshot::{self, Receiver, Sender};
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
+use webpush::{PushOutcome, PushSubscription, VapidKey, WebPushClient};
+
+/// Path of the subscription endpoint.
+pub const PATH: &str = "/reminders";
+
+/// Interval in which the scheduler checks for events starting soon.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Maximum length of a tag in a subscription.
+const MAX_TAG_LEN: usize = 64;
+
+/// What a subscription wants to be reminded of.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Topic {
+    /// The event with the given ID.
+    Event(String),
+    /// All events with the given tag.
+    Tag(String),
+}
+
+impl Topic {
+    /// Returns the kind and value of the topic as stored in the database.
+    fn parts(&self) -> (&str, &str) {
+        match self {
+            Topic::Event(id) => ("event", id),
+            Topic::Tag(tag) => ("tag", tag),
+        }
+    }
+}
+
+/// Manages push subscriptions and sends reminders.
+pub struct Reminders {
+    config: RemindersConfig,
+    base_url: String,
+    store: Arc<Mutex<SubscriptionStore>>,
+    client: WebPushClient,
+}
+
+impl Reminders {
+    /// Creates the reminders from their configuration. Notifications link to event pages below
+    /// `base_url`.
+    pub fn new(config: RemindersConfig, base_url: &str) -> Result<Reminders> {
+        let (Some(private_key), Some(public_key), Some(subject)) = (
+            &config.vapid_private_key,
+            &config.vapid_public_key,
+            &config.subject,
+        ) else {
+            return Err(Error::Reminder(
+                "reminders.vapid_private_key, reminders.vapid_public_key and reminders.subject must be set"
+                    .into(),
+            ));
+        };
+
+        let client = WebPushClient::new(
+            VapidKey::new(private_key, public_key)?,
+            subject,
+            Duration::from_secs(config.timeout_seconds),
+        )?;
+
+        let store = SubscriptionStore::open(&config.database)?;
+
+        Ok(Reminders {
+            config,
+            base_url: base_url.trim_end_matches('/').into(),
+            store: Arc::new(Mutex::new(store)),
+            client,
+        })
+    }
+
+    /// Runs a function with the subscription store on the blocking thread pool.
+    async fn with_store<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&SubscriptionStore) -> Result<T> + Send + 'static,
+    {
+        let store = self.store.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let store = store.lock().unwrap_or_else(|err| err.into_inner());
+            f(&store)
+        })
+        .await
+        .map_err(|err| Error::Reminder(format!("database task failed: {err}")))?
+    }
+
+    /// Checks whether the endpoint of a subscription belongs to an allowed push service. This
+    /// keeps the server from sending requests to arbitrary URLs.
+    pub fn is_allowed_endpoint(&self, endpoint: &str) -> bool {
+        let Ok(url) = url::Url::parse(endpoint) else {
+            return false;
+        };
+
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+
+        url.scheme() == "https"
+            && self
+                .config
+                .allowed_push_hosts
+                .iter()
+                .any(|allowed| host == allowed || host.ends_with(&format!(".{allowed}")))
+    }
+
+    /// Checks whether a tag can be subscribed to.
+    pub fn is_valid_tag(tag: &str) -> bool {
+        !tag.trim().is_empty() && tag.len() <= MAX_TAG_LEN
+    }
+
+    /// Adds a topic to a subscription. Event topics expire at `expires_at`. Returns `false` if
+    /// the subscription has too many topics.
+    pub async fn subscribe(
+        &self,
+        subscription: PushSubscription,
+        topic: Topic,
+        expires_at: Option<Timestamp>,
+    ) -> Result<bool> {
+        self.with_store(move |store| {
+            store.subscribe(&subscription, &topic, expires_at, Timestamp::now())
+        })
+        .await
+    }
+
+    /// Removes a topic from a subscription.
+    pub async fn unsubscribe(&self, endpoint: String, topic: Topic) -> Result<()> {
+        self.with_store(move |store| store.unsubscribe(&endpoint, &topic))
+            .await
+    }
+
+    /// Returns the JSON payload of the notification for an event, which is shown by the service
+    /// worker.
+    fn payload(&self, event: &Event) -> Result<Vec<u8>> {
+        let start_date = event.start_date.to_zoned(TimeZone::system());
+
+        let payload = serde_json::to_vec(&serde_json::json!({
+            "title": event.title,
+            "body": format!("{}, {} Uhr", format_date(&start_date), format_time(&start_date)),
+            "url": format!("{}/events/{}", self.base_url, event.id),
+            "tag": event.id,
+        }))
+        .map_err(|err| Error::Reminder(format!("failed to serialize payload: {err}")))?;
+
+        Ok(payload)
+    }
+
+    /// Sends reminders for the events starting within the lead time to all interested
+    /// subscriptions which haven't been reminded yet. Returns the number of sent reminders.
+    pub async fn send_due(&self, calendar: &Calendar, now: Timestamp) -> Result<usize> {
+        let lead_time = SignedDuration::from_mins(self.config.lead_time_minutes);
+        let events = calendar.get_events(now..now + lead_time).await?;
+        let mut sent = 0;
+
+        for event in events {
+            let recipients = {
+                let (id, tags) = (event.id.clone(), event.tags.clone());
+                self.with_store(move |store| store.recipients(&id, &tags))
+                    .await?
+            };
+
+            if recipients.is_empty() {
+                continue;
+            }
+
+            let payload = self.payload(&event)?;
+            // Reminders are pointless once the event started.
+            let ttl = Duration::try_from(now.duration_until(event.start_date)).unwrap_or_default();
+
+            for subscription in recipients {
+                let host = url::Url::parse(&subscription.endpoint)
+                    .ok()
+                    .and_then(|url| url.host_str().map(String::from))
+                    .unwrap_or_default();
+
+                match self.client.send(&subscription, &payload, ttl).await {
+                    Ok(PushOutcome::Delivered) => {
+                        let id = event.id.clone();
+                        let start_date = event.start_date;
+                        self.with_store(move |store| {
+                            store.record_reminder(&subscription.endpoint, &id, start_date)
+                        })
+                        .await?;
+                        sent += 1;
+                    }
+                    Ok(PushOutcome::Gone) => {
+                        log::info!("removing expired push subscription at {host}");
+                        self.with_store(move |store| store.remove(&subscription.endpoint))
+                            .await?;
+                    }
+                    // The reminder is retried with the next check.
+                    Err(err) => {
+                        log::warn!("failed to send reminder for \"{event}\" to {host}: {err}")
+                    }
+                }
+            }
+        }
+
+        self.with_store(move |store| store.purge(now)).await?;
+
+        if sent > 0 {
+            log::info!("sent {sent} event reminders");
+        }
+
+        Ok(sent)
+    }
+
+    /// Spawns a task which sends due reminders periodically.
+    pub fn spawn_scheduler(self: Arc<Self>, calendar: Calendar) -> ReminderTaskHandle {
+        let (stop_tx, stop_rx) = oneshot::channel();
+
+        let join_handle = tokio::spawn(async move {
+            self.run_scheduler(calendar, stop_rx).await;
+        });
+
+        ReminderTaskHandle {
+            join_handle,
+            stop_tx,
+        }
+    }
+
+    async fn run_scheduler(&self, calendar: Calendar, mut stop: Receiver<()>) {
+        loop {
+            if let Err(err) = self.send_due(&calendar, Timestamp::now()).await {
+                log::error!("failed to send reminders: {err}");
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(CHECK_INTERVAL) => {}
+                _ = &mut stop => break,
+            }
+        }
+    }
+}
+
+/// Handle to the reminder scheduler task.
+pub struct ReminderTaskHandle {
+    join_handle: JoinHandle<()>,
+    stop_tx: Sender<()>,
+}
+
+impl ReminderTaskHandle {
+    /// Stops the scheduler task. Blocks until the background task is finished.
+    pub async fn stop(self) -> io::Result<()> {
+        if self.stop_tx.send(()).is_ok() {
+            self.join_handle.await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calendar::StaticEventSource;
+    use actix_web::{App, HttpRequest, HttpResponse, HttpServer, web};
+    use webpush::tests::UserAgent;
+
+    /// A message received by the push endpoint stand-in.
+    struct Received {
+        path: String,
+        authorization: String,
+        ttl: String,
+        body: Vec<u8>,
+    }
+
+    /// Starts a local stand-in for a push service. Subscriptions below `/gone` are expired.
+    /// Returns its URL and the received messages.
+    async fn push_service() -> (String, Arc<Mutex<Vec<Received>>>) {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let data = web::Data::new(received.clone());
+
+        let server = HttpServer::new(move || {
+            App::new().app_data(data.clone()).default_service(web::to(
+                |req: HttpRequest,
+                 body: web::Bytes,
+                 received: web::Data<Arc<Mutex<Vec<Received>>>>| async move {
+                    let header = |name| {
+                        req.headers()
+                            .get(name)
+                            .and_then(|value| value.to_str().ok())
+                            .unwrap_or_default()
+                            .to_string()
+                    };
+
+                    if req.path().starts_with("/gone") {
+                        return HttpResponse::Gone().finish();
+                    }
+
+                    received.lock().unwrap().push(Received {
+                        path: req.path().into(),
+                        authorization: header("authorization"),
+                        ttl: header("ttl"),
+                        body: body.to_vec(),
+                    });
+
+                    HttpResponse::Created().finish()
+                },
+            ))
+        })
+        .workers(1)
+        .bind("127.0.0.1:0")
+        .unwrap();
+
+        let url = format!("http://{}", server.addrs()[0]);
+        tokio::spawn(server.run());
+
+        (url, received)
+    }
+
+    fn reminders(push_url: &str, dir: &std::path::Path) -> Reminders {
+        let (private_key, public_key) = VapidKey::generate().unwrap();
+
+        Reminders::new(
+            RemindersConfig {
+                enabled: true,
+                database: dir.join("reminders.sqlite3"),
+                vapid_public_key: Some(public_key),
+                vapid_private_key: Some(private_key),
+                subject: Some("mailto:info@example.com".into()),
+                allowed_push_hosts: vec!["push.example.com".into()],
+                ..Default::default()
+            },
+            push_url,
+        )
+        .unwrap()
+    }
+
+    #[actix_web::test]
+    async fn allowed_endpoints() {
+        let dir = tempfile::tempdir().unwrap();
+        let reminders = reminders("https://example.com", dir.path());
+
+        assert!(reminders.is_allowed_endpoint("https://push.example.com/abc"));
+        assert!(reminders.is_allowed_endpoint("https://eu.push.example.com/abc"));
+        assert!(!reminders.is_allowed_endpoint("http://push.example.com/abc"));
+        assert!(!reminders.is_allowed_endpoint("https://evilpush.example.com/abc"));
+        assert!(!reminders.is_allowed_endpoint("https://push.example.com.evil.com/abc"));
+        assert!(!reminders.is_allowed_endpoint("https://localhost/abc"));
+        assert!(!reminders.is_allowed_endpoint("not a url"));
+    }
+
+    #[actix_web::test]
+    async fn send_due() {
+        let (push_url, received) = push_service().await;
+        let dir = tempfile::tempdir().unwrap();
+        let reminders = reminders(&push_url, dir.path());
+
+        let now = Timestamp::now();
+        let event = |id: &str, start_date, tags: &[&str]| Event {
+            id: id.into(),
+            start_date,
+            title: format!("Event {id}"),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            ..Default::default()
+        };
+        let calendar = Calendar::new(StaticEventSource::new([
+            event("soon", now + SignedDuration::from_mins(30), &["Jazz"]),
+            event("later", now + SignedDuration::from_hours(5), &["Jazz"]),
+        ]))
+        .unwrap();
+        calendar.sync_once().await.unwrap();
+
+        let jazz_fan = UserAgent::new();
+        let subscription = |path: &str, user_agent: &UserAgent| PushSubscription {
+            endpoint: format!("{push_url}/{path}"),
+            keys: user_agent.keys.clone(),
+        };
+
+        let jazz = subscription("jazz", &jazz_fan);
+        assert!(
+            reminders
+                .subscribe(jazz.clone(), Topic::Tag("Jazz".into()), None)
+                .await
+                .unwrap()
+        );
+        let later = UserAgent::new();
+        reminders
+            .subscribe(
+                subscription("later", &later),
+                Topic::Event("later".into()),
+                None,
+            )
+            .await
+            .unwrap();
+        reminders
+            .subscribe(
+                subscription("gone", &UserAgent::new()),
+                Topic::Event("soon".into()),
+                None,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(reminders.send_due(&calendar, now).await.unwrap(), 1);
+        // Reminders are only sent once.
+        assert_eq!(reminders.send_due(&calendar, now).await.unwrap(), 0);
+        // The expired subscription was removed.
+        assert_eq!(
+            reminders.with_store(|store| store.count()).await.unwrap(),
+            2
+        );
+
+        let message = received.lock().unwrap().remove(0);
+        assert_eq!(message.path, "/jazz");
+        assert!(message.authorization.starts_with("vapid t="));
+        assert!((1790..=1800).contains(&message.ttl.parse::<u64>().unwrap()));
+
+        let payload: serde_json::Value =
+            serde_json::from_slice(&jazz_fan.decrypt(&message.body)).unwrap();
+        assert_eq!(payload["title"], "Event soon");
+        assert_eq!(payload["url"], format!("{push_url}/events/soon"));
+        assert_eq!(payload["tag"], "soon");
+
+        reminders
+            .unsubscribe(jazz.endpoint, Topic::Tag("Jazz".into()))
+            .await
+            .unwrap();
+        assert_eq!(
+            reminders
+                .send_due(&calendar, now + SignedDuration::from_hours(4))
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(received.lock().unwrap()[0].path, "/later");
+    }
+}
@@ -0,0 +1,320 @@
+//! SQLite storage of push subscriptions, their reminder topics and sent reminders.
+
+use super::Topic;
+use super::webpush::{PushSubscription, SubscriptionKeys};
+use crate::{Error, Result};
+use jiff::Timestamp;
+use rusqlite::{Connection, params};
+use std::path::Path;
+
+/// Maximum number of topics per subscription, so that a single browser can't fill the database.
+const MAX_TOPICS: i64 = 100;
+
+/// Schema migrations, applied in order. The number of applied migrations is stored as
+/// `user_version`. Timestamps are stored as UNIX seconds.
+const MIGRATIONS: &[&str] = &["
+    CREATE TABLE subscriptions (
+        endpoint TEXT PRIMARY KEY,
+        p256dh TEXT NOT NULL,
+        auth TEXT NOT NULL,
+        created_at INTEGER NOT NULL
+    );
+
+    CREATE TABLE topics (
+        endpoint TEXT NOT NULL REFERENCES subscriptions (endpoint) ON DELETE CASCADE,
+        kind TEXT NOT NULL,
+        value TEXT NOT NULL,
+        expires_at INTEGER,
+        PRIMARY KEY (endpoint, kind, value)
+    );
+
+    CREATE TABLE reminders (
+        endpoint TEXT NOT NULL REFERENCES subscriptions (endpoint) ON DELETE CASCADE,
+        event_id TEXT NOT NULL,
+        start_date INTEGER NOT NULL,
+        PRIMARY KEY (endpoint, event_id)
+    );
+"];
+
+/// Push subscriptions and sent reminders, stored in a SQLite database.
+pub struct SubscriptionStore {
+    conn: Connection,
+}
+
+impl SubscriptionStore {
+    /// Opens the database at `path`, creating it if necessary.
+    pub fn open(path: &Path) -> Result<SubscriptionStore> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        SubscriptionStore::migrate(Connection::open(path)?)
+    }
+
+    /// Opens a temporary in-memory database.
+    pub fn open_in_memory() -> Result<SubscriptionStore> {
+        SubscriptionStore::migrate(Connection::open_in_memory()?)
+    }
+
+    fn migrate(conn: Connection) -> Result<SubscriptionStore> {
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            log::info!("migrating reminders database to version {}", i + 1);
+            conn.execute_batch(&format!(
+                "BEGIN; {migration} PRAGMA user_version = {}; COMMIT;",
+                i + 1
+            ))?;
+        }
+
+        Ok(SubscriptionStore { conn })
+    }
+
+    /// Adds a topic to a subscription, creating or updating the subscription. Topics expire at
+    /// `expires_at`, if any. Returns `false` if the subscription already has too many topics.
+    pub fn subscribe(
+        &self,
+        subscription: &PushSubscription,
+        topic: &Topic,
+        expires_at: Option<Timestamp>,
+        now: Timestamp,
+    ) -> Result<bool> {
+        self.conn.execute(
+            "INSERT INTO subscriptions (endpoint, p256dh, auth, created_at) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (endpoint) DO UPDATE SET p256dh = excluded.p256dh, auth = excluded.auth",
+            params![
+                subscription.endpoint,
+                subscription.keys.p256dh,
+                subscription.keys.auth,
+                now.as_second()
+            ],
+        )?;
+
+        let topics: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM topics WHERE endpoint = ?1",
+            params![subscription.endpoint],
+            |row| row.get(0),
+        )?;
+
+        let (kind, value) = topic.parts();
+        if topics >= MAX_TOPICS {
+            let exists: bool = self.conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM topics WHERE endpoint = ?1 AND kind = ?2 AND value = ?3)",
+                params![subscription.endpoint, kind, value],
+                |row| row.get(0),
+            )?;
+            return Ok(exists);
+        }
+
+        self.conn.execute(
+            "INSERT OR IGNORE INTO topics (endpoint, kind, value, expires_at)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![
+                subscription.endpoint,
+                kind,
+                value,
+                expires_at.map(|date| date.as_second())
+            ],
+        )?;
+
+        Ok(true)
+    }
+
+    /// Removes a topic from a subscription. The subscription is removed with its last topic.
+    pub fn unsubscribe(&self, endpoint: &str, topic: &Topic) -> Result<()> {
+        let (kind, value) = topic.parts();
+        self.conn.execute(
+            "DELETE FROM topics WHERE endpoint = ?1 AND kind = ?2 AND value = ?3",
+            params![endpoint, kind, value],
+        )?;
+        self.remove_unused()
+    }
+
+    /// Removes a subscription with all of its topics.
+    pub fn remove(&self, endpoint: &str) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM subscriptions WHERE endpoint = ?1",
+            params![endpoint],
+        )?;
+        Ok(())
+    }
+
+    /// Returns the subscriptions interested in an event which haven't been reminded of it yet.
+    pub fn recipients(&self, event_id: &str, tags: &[String]) -> Result<Vec<PushSubscription>> {
+        let tags = serde_json::to_string(tags)
+            .map_err(|err| Error::Reminder(format!("failed to serialize tags: {err}")))?;
+        let mut stmt = self.conn.prepare(
+            "SELECT endpoint, p256dh, auth FROM subscriptions s
+             WHERE EXISTS (
+                 SELECT 1 FROM topics t
+                 WHERE t.endpoint = s.endpoint
+                 AND (
+                     (t.kind = 'event' AND t.value = ?1)
+                     OR (t.kind = 'tag' AND t.value IN (SELECT value FROM json_each(?2)))
+                 )
+             )
+             AND NOT EXISTS (
+                 SELECT 1 FROM reminders r WHERE r.endpoint = s.endpoint AND r.event_id = ?1
+             )
+             ORDER BY endpoint",
+        )?;
+
+        let subscriptions = stmt
+            .query_map(params![event_id, tags], |row| {
+                Ok(PushSubscription {
+                    endpoint: row.get(0)?,
+                    keys: SubscriptionKeys {
+                        p256dh: row.get(1)?,
+                        auth: row.get(2)?,
+                    },
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(subscriptions)
+    }
+
+    /// Records that a subscription was reminded of an event, so that it isn't reminded again.
+    pub fn record_reminder(
+        &self,
+        endpoint: &str,
+        event_id: &str,
+        start_date: Timestamp,
+    ) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR IGNORE INTO reminders (endpoint, event_id, start_date) VALUES (?1, ?2, ?3)",
+            params![endpoint, event_id, start_date.as_second()],
+        )?;
+        Ok(())
+    }
+
+    /// Removes expired topics, reminders of past events and subscriptions without topics.
+    pub fn purge(&self, now: Timestamp) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM topics WHERE expires_at < ?1",
+            params![now.as_second()],
+        )?;
+        self.conn.execute(
+            "DELETE FROM reminders WHERE start_date < ?1",
+            params![now.as_second()],
+        )?;
+        self.remove_unused()
+    }
+
+    fn remove_unused(&self) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM subscriptions
+             WHERE NOT EXISTS (SELECT 1 FROM topics t WHERE t.endpoint = subscriptions.endpoint)",
+            [],
+        )?;
+        Ok(())
+    }
+
+    /// Returns the number of subscriptions.
+    pub fn count(&self) -> Result<usize> {
+        let count: i64 = self
+            .conn
+            .query_row("SELECT COUNT(*) FROM subscriptions", [], |row| row.get(0))?;
+        Ok(count as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jiff::SignedDuration;
+
+    fn subscription(endpoint: &str) -> PushSubscription {
+        PushSubscription {
+            endpoint: endpoint.into(),
+            keys: SubscriptionKeys {
+                p256dh: "key".into(),
+                auth: "auth".into(),
+            },
+        }
+    }
+
+    fn endpoints(subscriptions: Vec<PushSubscription>) -> Vec<String> {
+        subscriptions.into_iter().map(|s| s.endpoint).collect()
+    }
+
+    #[test]
+    fn recipients() {
+        let store = SubscriptionStore::open_in_memory().unwrap();
+        let now: Timestamp = "2025-03-01T12:00:00Z".parse().unwrap();
+        let start = now + SignedDuration::from_hours(2);
+        let tags = vec!["Jazz".to_string(), "Konzert".to_string()];
+
+        let event = Topic::Event("e1".into());
+        let jazz = Topic::Tag("Jazz".into());
+        assert!(
+            store
+                .subscribe(&subscription("a"), &event, Some(start), now)
+                .unwrap()
+        );
+        assert!(
+            store
+                .subscribe(&subscription("b"), &jazz, None, now)
+                .unwrap()
+        );
+        assert!(
+            store
+                .subscribe(&subscription("b"), &event, Some(start), now)
+                .unwrap()
+        );
+        assert!(
+            store
+                .subscribe(&subscription("c"), &Topic::Tag("Quiz".into()), None, now)
+                .unwrap()
+        );
+
+        assert_eq!(
+            endpoints(store.recipients("e1", &tags).unwrap()),
+            ["a", "b"]
+        );
+        assert_eq!(endpoints(store.recipients("e2", &tags).unwrap()), ["b"]);
+        assert!(store.recipients("e2", &[]).unwrap().is_empty());
+
+        store.record_reminder("a", "e1", start).unwrap();
+        assert_eq!(endpoints(store.recipients("e1", &tags).unwrap()), ["b"]);
+
+        store.unsubscribe("b", &jazz).unwrap();
+        assert!(store.recipients("e2", &tags).unwrap().is_empty());
+        assert_eq!(store.count().unwrap(), 3);
+
+        store.remove("b").unwrap();
+        assert!(store.recipients("e1", &tags).unwrap().is_empty());
+
+        // Event topics expire with the event, subscriptions with their last topic.
+        store.purge(start + SignedDuration::from_secs(1)).unwrap();
+        assert_eq!(store.count().unwrap(), 1);
+        assert_eq!(
+            endpoints(store.recipients("e3", &["Quiz".into()]).unwrap()),
+            ["c"]
+        );
+    }
+
+    #[test]
+    fn topic_limit() {
+        let store = SubscriptionStore::open_in_memory().unwrap();
+        let now = Timestamp::now();
+        let subscription = subscription("a");
+
+        for i in 0..MAX_TOPICS {
+            let topic = Topic::Tag(format!("tag{i}"));
+            assert!(store.subscribe(&subscription, &topic, None, now).unwrap());
+        }
+
+        assert!(
+            !store
+                .subscribe(&subscription, &Topic::Tag("more".into()), None, now)
+                .unwrap()
+        );
+        assert!(
+            store
+                .subscribe(&subscription, &Topic::Tag("tag0".into()), None, now)
+                .unwrap()
+        );
+    }
+}
@@ -0,0 +1,490 @@
+//! Web Push messages with VAPID authentication (RFC 8292) and encrypted payloads (RFC 8291).
+
+use crate::{Error, Result};
+use base64::prelude::*;
+use jiff::{SignedDuration, Timestamp};
+use reqwest::StatusCode;
+use reqwest::header::{AUTHORIZATION, CONTENT_ENCODING, CONTENT_TYPE};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::{self, EcdsaKeyPair, KeyPair};
+use ring::{aead, agreement, hkdf};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Record size announced in the header of encrypted payloads. Payloads always fit into a single
+/// record.
+const RECORD_SIZE: u32 = 4096;
+
+/// Maximum size of a payload before encryption, leaving room for the padding delimiter and the
+/// authentication tag in a single record.
+pub const MAX_PAYLOAD_SIZE: usize = RECORD_SIZE as usize - 17;
+
+/// Validity of VAPID tokens. Push services reject tokens valid for more than 24 hours.
+const TOKEN_VALIDITY: SignedDuration = SignedDuration::from_hours(12);
+
+/// A push subscription as returned by `PushSubscription.toJSON()` in the browser.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PushSubscription {
+    /// The URL of the push service to send messages to.
+    pub endpoint: String,
+    /// The keys for encrypting messages.
+    pub keys: SubscriptionKeys,
+}
+
+/// The keys of a push subscription, base64url encoded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubscriptionKeys {
+    /// The public key of the browser.
+    pub p256dh: String,
+    /// The authentication secret.
+    pub auth: String,
+}
+
+/// Result of sending a push message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PushOutcome {
+    /// The push service accepted the message.
+    Delivered,
+    /// The subscription expired or was cancelled and should be removed.
+    Gone,
+}
+
+fn crypto_error(_: ring::error::Unspecified) -> Error {
+    Error::Reminder("cryptographic operation failed".into())
+}
+
+/// Extracts the private key from the PKCS#8 document of an EC key pair. The document contains an
+/// `ECPrivateKey` structure (RFC 5915), whose second field is the private key.
+fn ec_private_key(pkcs8: &[u8]) -> Result<Vec<u8>> {
+    use pkcs8::der::asn1::{AnyRef, OctetStringRef};
+    use pkcs8::der::{Decode, Reader, SliceReader};
+
+    let invalid = |err: pkcs8::der::Error| Error::Reminder(format!("invalid private key: {err}"));
+
+    let info = pkcs8::PrivateKeyInfo::from_der(pkcs8)
+        .map_err(|err| Error::Reminder(format!("invalid PKCS#8 document: {err}")))?;
+
+    let mut reader = SliceReader::new(info.private_key).map_err(invalid)?;
+    let (version, private_key) = reader
+        .sequence(|seq| {
+            let version = u8::decode(seq)?;
+            let private_key = OctetStringRef::decode(seq)?.as_bytes().to_vec();
+
+            // Skip the optional parameters and public key.
+            while !seq.is_finished() {
+                AnyRef::decode(seq)?;
+            }
+
+            Ok((version, private_key))
+        })
+        .and_then(|key| reader.finish(key))
+        .map_err(invalid)?;
+
+    if version != 1 {
+        return Err(Error::Reminder(format!(
+            "unsupported EC private key version {version}"
+        )));
+    }
+
+    Ok(private_key)
+}
+
+/// Decodes base64url, with or without padding.
+fn decode(value: &str) -> Result<Vec<u8>> {
+    BASE64_URL_SAFE_NO_PAD
+        .decode(value.trim_end_matches('='))
+        .map_err(|err| Error::Reminder(format!("invalid base64url value: {err}")))
+}
+
+/// The key pair identifying the application server to push services.
+pub struct VapidKey {
+    key_pair: EcdsaKeyPair,
+    public_key: String,
+}
+
+impl VapidKey {
+    /// Creates a key from the base64url encoded private key and uncompressed public key.
+    pub fn new(private_key: &str, public_key: &str) -> Result<VapidKey> {
+        let key_pair = EcdsaKeyPair::from_private_key_and_public_key(
+            &signature::ECDSA_P256_SHA256_FIXED_SIGNING,
+            &decode(private_key)?,
+            &decode(public_key)?,
+            &SystemRandom::new(),
+        )
+        .map_err(|err| Error::Reminder(format!("invalid VAPID key pair: {err}")))?;
+
+        Ok(VapidKey {
+            key_pair,
+            public_key: public_key.trim_end_matches('=').into(),
+        })
+    }
+
+    /// Generates a new key pair. Returns the base64url encoded private and public key.
+    pub fn generate() -> Result<(String, String)> {
+        let rng = SystemRandom::new();
+        let alg = &signature::ECDSA_P256_SHA256_FIXED_SIGNING;
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(alg, &rng).map_err(crypto_error)?;
+        let key_pair = EcdsaKeyPair::from_pkcs8(alg, pkcs8.as_ref(), &rng)
+            .map_err(|err| Error::Reminder(format!("invalid generated key: {err}")))?;
+
+        let private_key = ec_private_key(pkcs8.as_ref())?;
+
+        Ok((
+            BASE64_URL_SAFE_NO_PAD.encode(private_key),
+            BASE64_URL_SAFE_NO_PAD.encode(key_pair.public_key()),
+        ))
+    }
+
+    /// Returns the value of the `Authorization` header for a push message to `endpoint`.
+    fn authorization(&self, endpoint: &str, subject: &str, now: Timestamp) -> Result<String> {
+        let endpoint = url::Url::parse(endpoint)
+            .map_err(|err| Error::Reminder(format!("invalid endpoint: {err}")))?;
+
+        let header = BASE64_URL_SAFE_NO_PAD.encode(r#"{"typ":"JWT","alg":"ES256"}"#);
+        let claims = BASE64_URL_SAFE_NO_PAD.encode(
+            serde_json::json!({
+                "aud": endpoint.origin().ascii_serialization(),
+                "exp": (now + TOKEN_VALIDITY).as_second(),
+                "sub": subject,
+            })
+            .to_string(),
+        );
+
+        let message = format!("{header}.{claims}");
+        let signature = self
+            .key_pair
+            .sign(&SystemRandom::new(), message.as_bytes())
+            .map_err(crypto_error)?;
+
+        Ok(format!(
+            "vapid t={message}.{}, k={}",
+            BASE64_URL_SAFE_NO_PAD.encode(signature),
+            self.public_key
+        ))
+    }
+}
+
+/// Length of an HKDF output.
+struct Len(usize);
+
+impl hkdf::KeyType for Len {
+    fn len(&self) -> usize {
+        self.0
+    }
+}
+
+/// Derives `out.len()` bytes with HKDF-SHA256.
+fn hkdf(salt: &[u8], ikm: &[u8], info: &[&[u8]], out: &mut [u8]) -> Result<()> {
+    hkdf::Salt::new(hkdf::HKDF_SHA256, salt)
+        .extract(ikm)
+        .expand(info, Len(out.len()))
+        .and_then(|okm| okm.fill(out))
+        .map_err(crypto_error)
+}
+
+/// Encrypts a payload for the browser with the public key `p256dh` and the authentication secret
+/// `auth`, using the `aes128gcm` content encoding.
+pub fn encrypt(payload: &[u8], keys: &SubscriptionKeys) -> Result<Vec<u8>> {
+    if payload.len() > MAX_PAYLOAD_SIZE {
+        return Err(Error::Reminder(format!(
+            "payload of {} bytes is too large",
+            payload.len()
+        )));
+    }
+
+    let ua_public = decode(&keys.p256dh)?;
+    let auth = decode(&keys.auth)?;
+    let rng = SystemRandom::new();
+
+    let private_key = agreement::EphemeralPrivateKey::generate(&agreement::ECDH_P256, &rng)
+        .map_err(crypto_error)?;
+    let as_public = private_key.compute_public_key().map_err(crypto_error)?;
+
+    let mut salt = [0; 16];
+    rng.fill(&mut salt).map_err(crypto_error)?;
+
+    agreement::agree_ephemeral(
+        private_key,
+        &agreement::UnparsedPublicKey::new(&agreement::ECDH_P256, &ua_public),
+        |shared_secret| {
+            encrypt_record(
+                payload,
+                shared_secret,
+                &auth,
+                &ua_public,
+                as_public.as_ref(),
+                &salt,
+            )
+        },
+    )
+    .map_err(|_| Error::Reminder("invalid p256dh key".into()))?
+}
+
+/// Encrypts `payload` as a single record, given the ECDH `shared_secret` of the browser key
+/// `ua_public` and the application server key `as_public` (RFC 8291, section 3.4).
+fn encrypt_record(
+    payload: &[u8],
+    shared_secret: &[u8],
+    auth: &[u8],
+    ua_public: &[u8],
+    as_public: &[u8],
+    salt: &[u8; 16],
+) -> Result<Vec<u8>> {
+    let mut ikm = [0; 32];
+    hkdf(
+        auth,
+        shared_secret,
+        &[b"WebPush: info\0", ua_public, as_public],
+        &mut ikm,
+    )?;
+
+    let mut cek = [0; 16];
+    hkdf(salt, &ikm, &[b"Content-Encoding: aes128gcm\0"], &mut cek)?;
+    let mut nonce = [0; 12];
+    hkdf(salt, &ikm, &[b"Content-Encoding: nonce\0"], &mut nonce)?;
+
+    // A single record without padding, terminated by the delimiter of the last record.
+    let mut record = payload.to_vec();
+    record.push(2);
+
+    let key = aead::LessSafeKey::new(
+        aead::UnboundKey::new(&aead::AES_128_GCM, &cek).map_err(crypto_error)?,
+    );
+    key.seal_in_place_append_tag(
+        aead::Nonce::assume_unique_for_key(nonce),
+        aead::Aad::empty(),
+        &mut record,
+    )
+    .map_err(crypto_error)?;
+
+    let mut body = Vec::with_capacity(21 + as_public.len() + record.len());
+    body.extend_from_slice(salt);
+    body.extend_from_slice(&RECORD_SIZE.to_be_bytes());
+    body.push(as_public.len() as u8);
+    body.extend_from_slice(as_public);
+    body.extend_from_slice(&record);
+
+    Ok(body)
+}
+
+/// Sends push messages to push services.
+pub struct WebPushClient {
+    vapid: VapidKey,
+    subject: String,
+    client: reqwest::Client,
+}
+
+impl WebPushClient {
+    /// Creates a client which identifies itself with the `vapid` key and the contact `subject`.
+    pub fn new(vapid: VapidKey, subject: &str, timeout: Duration) -> Result<WebPushClient> {
+        Ok(WebPushClient {
+            vapid,
+            subject: subject.into(),
+            client: reqwest::Client::builder().timeout(timeout).build()?,
+        })
+    }
+
+    /// Sends an encrypted payload to a subscription. Push services discard the message if it
+    /// can't be delivered within `ttl`.
+    pub async fn send(
+        &self,
+        subscription: &PushSubscription,
+        payload: &[u8],
+        ttl: Duration,
+    ) -> Result<PushOutcome> {
+        let body = encrypt(payload, &subscription.keys)?;
+        let authorization =
+            self.vapid
+                .authorization(&subscription.endpoint, &self.subject, Timestamp::now())?;
+
+        let resp = self
+            .client
+            .post(&subscription.endpoint)
+            .header(AUTHORIZATION, authorization)
+            .header(CONTENT_ENCODING, "aes128gcm")
+            .header(CONTENT_TYPE, "application/octet-stream")
+            .header("TTL", ttl.as_secs())
+            .body(body)
+            .send()
+            .await?;
+
+        if matches!(resp.status(), StatusCode::NOT_FOUND | StatusCode::GONE) {
+            return Ok(PushOutcome::Gone);
+        }
+
+        resp.error_for_status()?;
+        Ok(PushOutcome::Delivered)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// The key pair and authentication secret of a browser.
+    pub(crate) struct UserAgent {
+        private_key: agreement::EphemeralPrivateKey,
+        pub(crate) keys: SubscriptionKeys,
+        auth: Vec<u8>,
+    }
+
+    impl UserAgent {
+        pub(crate) fn new() -> UserAgent {
+            let rng = SystemRandom::new();
+            let private_key =
+                agreement::EphemeralPrivateKey::generate(&agreement::ECDH_P256, &rng).unwrap();
+            let public_key = private_key.compute_public_key().unwrap();
+            let mut auth = vec![0; 16];
+            rng.fill(&mut auth).unwrap();
+
+            UserAgent {
+                private_key,
+                keys: SubscriptionKeys {
+                    p256dh: BASE64_URL_SAFE_NO_PAD.encode(public_key),
+                    auth: BASE64_URL_SAFE_NO_PAD.encode(&auth),
+                },
+                auth,
+            }
+        }
+
+        /// Decrypts a message like a browser would. Consumes the user agent, since ring's
+        /// ephemeral keys can only be used once.
+        pub(crate) fn decrypt(self, body: &[u8]) -> Vec<u8> {
+            let (salt, rest) = body.split_at(16);
+            assert_eq!(
+                u32::from_be_bytes(rest[..4].try_into().unwrap()),
+                RECORD_SIZE
+            );
+            let key_len = rest[4] as usize;
+            let (as_public, record) = rest[5..].split_at(key_len);
+            let ua_public = decode(&self.keys.p256dh).unwrap();
+
+            let mut ikm = [0; 32];
+            agreement::agree_ephemeral(
+                self.private_key,
+                &agreement::UnparsedPublicKey::new(&agreement::ECDH_P256, as_public),
+                |shared_secret| {
+                    hkdf(
+                        &self.auth,
+                        shared_secret,
+                        &[b"WebPush: info\0", &ua_public, as_public],
+                        &mut ikm,
+                    )
+                },
+            )
+            .unwrap()
+            .unwrap();
+
+            let mut cek = [0; 16];
+            hkdf(salt, &ikm, &[b"Content-Encoding: aes128gcm\0"], &mut cek).unwrap();
+            let mut nonce = [0; 12];
+            hkdf(salt, &ikm, &[b"Content-Encoding: nonce\0"], &mut nonce).unwrap();
+
+            let key =
+                aead::LessSafeKey::new(aead::UnboundKey::new(&aead::AES_128_GCM, &cek).unwrap());
+            let mut record = record.to_vec();
+            let plaintext = key
+                .open_in_place(
+                    aead::Nonce::assume_unique_for_key(nonce),
+                    aead::Aad::empty(),
+                    &mut record,
+                )
+                .unwrap();
+
+            assert_eq!(plaintext.last(), Some(&2));
+            plaintext[..plaintext.len() - 1].to_vec()
+        }
+    }
+
+    #[test]
+    fn vapid() {
+        let (private_key, public_key) = VapidKey::generate().unwrap();
+        let key = VapidKey::new(&private_key, &public_key).unwrap();
+        assert!(VapidKey::new(&public_key, &private_key).is_err());
+
+        let now: Timestamp = "2025-03-01T12:00:00Z".parse().unwrap();
+        let authorization = key
+            .authorization(
+                "https://fcm.googleapis.com/fcm/send/abc",
+                "mailto:info@example.com",
+                now,
+            )
+            .unwrap();
+
+        let (token, k) = authorization
+            .strip_prefix("vapid t=")
+            .unwrap()
+            .split_once(", k=")
+            .unwrap();
+        assert_eq!(k, public_key);
+
+        let (message, signature) = token.rsplit_once('.').unwrap();
+        signature::UnparsedPublicKey::new(
+            &signature::ECDSA_P256_SHA256_FIXED,
+            decode(&public_key).unwrap(),
+        )
+        .verify(message.as_bytes(), &decode(signature).unwrap())
+        .unwrap();
+
+        let claims: serde_json::Value =
+            serde_json::from_slice(&decode(message.split_once('.').unwrap().1).unwrap()).unwrap();
+        assert_eq!(
+            claims,
+            serde_json::json!({
+                "aud": "https://fcm.googleapis.com",
+                "exp": 1740873600,
+                "sub": "mailto:info@example.com",
+            })
+        );
+    }
+
+    #[test]
+    fn encryption() {
+        let user_agent = UserAgent::new();
+        let body = encrypt(b"Kneipenquiz", &user_agent.keys).unwrap();
+        assert_eq!(user_agent.decrypt(&body), b"Kneipenquiz");
+
+        let user_agent = UserAgent::new();
+        assert!(encrypt(&[0; MAX_PAYLOAD_SIZE + 1], &user_agent.keys).is_err());
+    }
+
+    #[test]
+    fn rfc8291_example() {
+        // The example of RFC 8291, Appendix A. ring can't import the private keys of the example,
+        // so encryption starts from the ECDH shared secret given there.
+        let body = encrypt_record(
+            b"When I grow up, I want to be a watermelon",
+            &decode("kyrL1jIIOHEzg3sM2ZWRHDRB62YACZhhSlknJ672kSs").unwrap(),
+            &decode("BTBZMqHH6r4Tts7J_aSIgg").unwrap(),
+            &decode("BCVxsr7N_eNgVRqvHtD0zTZsEc6-VV-JvLexhqUzORcxaOzi6-AYWXvTBHm4bjyPjs7Vd8pZGH6SRpkNtoIAiw4").unwrap(),
+            &decode("BP4z9KsN6nGRTbVYI_c7VJSPQTBtkgcy27mlmlMoZIIgDll6e3vCYLocInmYWAmS6TlzAC8wEqKK6PBru3jl7A8").unwrap(),
+            &decode("DGv6ra1nlYgDCS1FRnbzlw").unwrap().try_into().unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            BASE64_URL_SAFE_NO_PAD.encode(body),
+            "DGv6ra1nlYgDCS1FRnbzlwAAEABBBP4z9KsN6nGRTbVYI_c7VJSPQTBtkgcy27mlmlMoZIIgDll6e3vCYLocInmYWAmS6TlzAC8wEqKK6PBru3jl7A_yl95bQpu6cVPTpK4Mqgkf1CXztLVBSt2Ks3oZwbuwXPXLWyouBWLVWGNWQexSgSxsj_Qulcy4a-fN"
+        );
+    }
+
+    #[test]
+    fn private_key_from_pkcs8() {
+        let rng = SystemRandom::new();
+        let alg = &signature::ECDSA_P256_SHA256_FIXED_SIGNING;
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(alg, &rng).unwrap();
+        let key_pair = EcdsaKeyPair::from_pkcs8(alg, pkcs8.as_ref(), &rng).unwrap();
+
+        let private_key = ec_private_key(pkcs8.as_ref()).unwrap();
+        assert_eq!(private_key.len(), 32);
+        EcdsaKeyPair::from_private_key_and_public_key(
+            alg,
+            &private_key,
+            key_pair.public_key().as_ref(),
+            &rng,
+        )
+        .unwrap();
+
+        assert!(ec_private_key(&pkcs8.as_ref()[..40]).is_err());
+    }
+}
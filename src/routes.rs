@@ -0,0 +1,7 @@
+//! Request handlers of the optional features. Each module registers its routes with
+//! `configure`, and its handlers respond with `404 Not Found` while the feature is disabled.
+
+pub mod admin;
+pub mod contact;
+pub mod newsletter;
+pub mod reminders;
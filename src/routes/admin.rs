@@ -0,0 +1,587 @@
+//! The admin UI for editing local events, and the admin endpoints authenticated with a bearer
+//! token.
+
+use actix_utils::future::{Ready, ready};
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::dev::{self, ServiceRequest};
+use actix_web::error::{
+    ErrorBadRequest, ErrorForbidden, ErrorInternalServerError, ErrorNotFound, ErrorUnauthorized,
+    InternalError,
+};
+use actix_web::http::StatusCode;
+use actix_web::http::header::{self, CacheControl, CacheDirective, ContentType};
+use actix_web::web::{self, Data};
+use actix_web::{FromRequest, HttpRequest, HttpResponse, Responder, Result};
+use actix_web_httpauth::extractors::bearer::BearerAuth;
+use actix_web_httpauth::middleware::HttpAuthentication;
+use jiff::{Timestamp, tz::TimeZone};
+use minijinja::value::Value;
+use serde::Deserialize;
+use wohnzimmer::admin::{self, Admin, EventForm, LoginForm, Session, ValidationErrors};
+use wohnzimmer::calendar::local::{LocalEventStore, preview_description};
+use wohnzimmer::calendar::{Calendar, Event, PublishState};
+use wohnzimmer::{AdminConfig, CalendarConfig, SanitizeConfig, ServerConfig};
+
+use crate::{MiniJinjaRenderer, client_ip};
+
+/// Triggers an immediate calendar sync, e.g. after a typo was fixed in the calendar. Concurrent
+/// requests share a single sync.
+async fn admin_sync(calendar: Data<Calendar>) -> impl Responder {
+    match calendar.sync_coalesced().await {
+        Ok(report) => {
+            log::info!(
+                "manual calendar sync: {} events, {} added, {} removed, {} changed",
+                report.events,
+                report.added,
+                report.removed,
+                report.changed
+            );
+            HttpResponse::Ok().json(report)
+        }
+        Err(err) => {
+            log::error!("manual calendar sync failed: {err}");
+            HttpResponse::BadGateway().json(serde_json::json!({ "error": err.to_string() }))
+        }
+    }
+}
+
+/// Reports the full health of the calendar sync including the last error, which the public
+/// readiness probe leaves out.
+async fn admin_health(calendar: Data<Calendar>) -> impl Responder {
+    HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(calendar.health().await)
+}
+
+/// A logged in user of the admin UI. Without a valid session cookie, the request is redirected
+/// to the login page.
+struct AdminSession {
+    token: String,
+    session: Session,
+}
+
+impl AdminSession {
+    /// Checks the CSRF token submitted with a form.
+    fn check_csrf(&self, token: &str) -> Result<()> {
+        if wohnzimmer::secret_eq(&self.session.csrf_token, token) {
+            Ok(())
+        } else {
+            Err(ErrorForbidden("invalid CSRF token"))
+        }
+    }
+}
+
+impl FromRequest for AdminSession {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _pl: &mut dev::Payload) -> Self::Future {
+        let Ok(admin) = <Data<Admin>>::extract(req).into_inner() else {
+            return ready(Err(ErrorNotFound("not found")));
+        };
+
+        let session = req.cookie(admin::SESSION_COOKIE).and_then(|cookie| {
+            let token = cookie.value().to_owned();
+            admin
+                .session(&token, Timestamp::now())
+                .map(|session| AdminSession { token, session })
+        });
+
+        ready(session.ok_or_else(|| {
+            InternalError::from_response("login required", admin_redirect("/login")).into()
+        }))
+    }
+}
+
+/// Returns the admin UI, or a 404 error if it is disabled.
+fn admin_enabled(admin: Option<Data<Admin>>) -> Result<Data<Admin>> {
+    admin.ok_or_else(|| ErrorNotFound("not found"))
+}
+
+/// Logs an admin error and hides the details from the user.
+fn admin_error(err: wohnzimmer::Error) -> actix_web::Error {
+    log::error!("{err}");
+    ErrorInternalServerError("admin error")
+}
+
+/// Redirects to a page of the admin UI, e.g. after a form submission.
+fn admin_redirect(path: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header((header::LOCATION, format!("{}{path}", admin::PATH)))
+        .finish()
+}
+
+/// Renders a page of the admin UI. The pages contain CSRF tokens, so they are neither cached nor
+/// indexed.
+fn render_admin(
+    tmpl_env: &MiniJinjaRenderer,
+    session: Option<&AdminSession>,
+    template: &str,
+    ctx: Value,
+) -> Result<HttpResponse> {
+    let html = tmpl_env.render_to_string(
+        template,
+        minijinja::context! {
+            user => session.map(|session| &session.session.user),
+            csrf_token => session.map(|session| &session.session.csrf_token),
+            ..ctx
+        },
+    )?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .insert_header(("x-robots-tag", "noindex"))
+        .content_type(ContentType::html())
+        .body(html))
+}
+
+/// Syncs the calendar after an event was changed in the admin UI, so that the change is visible
+/// right away.
+async fn sync_admin_change(calendar: &Calendar) {
+    if let Err(err) = calendar.sync_coalesced().await {
+        log::error!("failed to sync calendar events after admin change: {err}");
+    }
+}
+
+async fn admin_index(admin: Option<Data<Admin>>) -> Result<HttpResponse> {
+    admin_enabled(admin)?;
+    Ok(admin_redirect("/events"))
+}
+
+async fn admin_login_form(
+    tmpl_env: MiniJinjaRenderer,
+    admin: Option<Data<Admin>>,
+) -> Result<HttpResponse> {
+    admin_enabled(admin)?;
+    render_admin(&tmpl_env, None, "admin/login.html", Value::UNDEFINED)
+}
+
+/// Checks the password of a user and starts a session.
+async fn admin_login(
+    req: HttpRequest,
+    tmpl_env: MiniJinjaRenderer,
+    admin: Option<Data<Admin>>,
+    server_config: Data<ServerConfig>,
+    form: web::Form<LoginForm>,
+) -> Result<HttpResponse> {
+    let admin = admin_enabled(admin)?;
+    let user = form.user.trim();
+    let now = Timestamp::now();
+
+    if !admin.allow_login(&client_ip(&req, &server_config), user, now) {
+        log::warn!("too many admin login attempts");
+        let error = "Zu viele Anmeldeversuche. Bitte versuche es später noch einmal.";
+        let mut res = render_admin(
+            &tmpl_env,
+            None,
+            "admin/login.html",
+            minijinja::context! { error, login_user => user },
+        )?;
+        *res.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+        return Ok(res);
+    }
+
+    let Some(token) = admin
+        .login(user, &form.password, now)
+        .await
+        .map_err(admin_error)?
+    else {
+        log::warn!("failed admin login");
+        let error = "Benutzername oder Passwort ist falsch.";
+        let mut res = render_admin(
+            &tmpl_env,
+            None,
+            "admin/login.html",
+            minijinja::context! { error, login_user => user },
+        )?;
+        *res.status_mut() = StatusCode::UNAUTHORIZED;
+        return Ok(res);
+    };
+
+    log::info!("admin login of user {user}");
+
+    let cookie = Cookie::build(admin::SESSION_COOKIE, token)
+        .path(admin::PATH)
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        .secure(req.connection_info().scheme() == "https")
+        .finish();
+
+    let mut res = admin_redirect("/events");
+    res.add_cookie(&cookie)?;
+    Ok(res)
+}
+
+/// Form which only carries the CSRF token, e.g. for logging out.
+#[derive(Deserialize)]
+struct CsrfForm {
+    csrf_token: String,
+}
+
+async fn admin_logout(
+    admin: Data<Admin>,
+    session: AdminSession,
+    form: web::Form<CsrfForm>,
+) -> Result<HttpResponse> {
+    session.check_csrf(&form.csrf_token)?;
+    admin.logout(&session.token);
+
+    let mut res = admin_redirect("/login");
+    res.add_removal_cookie(
+        &Cookie::build(admin::SESSION_COOKIE, "")
+            .path(admin::PATH)
+            .finish(),
+    )?;
+    Ok(res)
+}
+
+/// Lists all events of the local event source.
+async fn admin_events(
+    tmpl_env: MiniJinjaRenderer,
+    session: AdminSession,
+    store: Data<LocalEventStore>,
+) -> Result<HttpResponse> {
+    let now = Timestamp::now();
+    let rows: Vec<Value> = store
+        .list()
+        .await
+        .map_err(admin_error)?
+        .into_iter()
+        .map(|event| {
+            let updated_at = event
+                .updated_at
+                .to_zoned(TimeZone::system())
+                .strftime("%d.%m.%Y %H:%M")
+                .to_string();
+            let updated_by = event.updated_by.clone();
+            let event = Event::from(event);
+            let published = event.is_published(now);
+            let status = match (event.publish_state, event.publish_at) {
+                (PublishState::Draft, _) => Some("Entwurf".to_string()),
+                (PublishState::Published, Some(publish_at)) if !published => Some(format!(
+                    "Veröffentlichung am {}",
+                    publish_at
+                        .to_zoned(TimeZone::system())
+                        .strftime("%d.%m.%Y %H:%M")
+                )),
+                (PublishState::Published, _) => None,
+            };
+
+            minijinja::context! {
+                event => Value::from_object(event),
+                published,
+                status,
+                updated_at,
+                updated_by,
+            }
+        })
+        .collect();
+
+    render_admin(
+        &tmpl_env,
+        Some(&session),
+        "admin/events.html",
+        minijinja::context! { events => rows },
+    )
+}
+
+/// Renders the form of a new event or of the existing event `id`.
+fn render_event_form(
+    tmpl_env: &MiniJinjaRenderer,
+    session: &AdminSession,
+    id: Option<&str>,
+    form: &EventForm,
+    errors: &ValidationErrors,
+    preview: Option<String>,
+) -> Result<HttpResponse> {
+    render_admin(
+        tmpl_env,
+        Some(session),
+        "admin/event.html",
+        minijinja::context! { id, form, errors, preview },
+    )
+}
+
+async fn admin_event_new(
+    tmpl_env: MiniJinjaRenderer,
+    session: AdminSession,
+) -> Result<HttpResponse> {
+    render_event_form(
+        &tmpl_env,
+        &session,
+        None,
+        &EventForm::default(),
+        &ValidationErrors::new(),
+        None,
+    )
+}
+
+async fn admin_event_edit(
+    tmpl_env: MiniJinjaRenderer,
+    session: AdminSession,
+    store: Data<LocalEventStore>,
+    id: web::Path<String>,
+) -> Result<HttpResponse> {
+    let event = store
+        .get(&id)
+        .await
+        .map_err(admin_error)?
+        .ok_or_else(|| ErrorNotFound("not found"))?;
+
+    render_event_form(
+        &tmpl_env,
+        &session,
+        Some(&id),
+        &EventForm::from_event(&event),
+        &ValidationErrors::new(),
+        None,
+    )
+}
+
+/// Saves a submitted event form as a new event or as the existing event `id`. With the preview
+/// button, the form is only shown again with a preview of the description.
+async fn save_event(
+    tmpl_env: &MiniJinjaRenderer,
+    session: &AdminSession,
+    store: &LocalEventStore,
+    calendar: &Calendar,
+    sanitize: &SanitizeConfig,
+    id: Option<String>,
+    form: EventForm,
+) -> Result<HttpResponse> {
+    session.check_csrf(&form.csrf_token)?;
+
+    if form.is_preview() {
+        let preview = preview_description(&form.description, sanitize);
+        return render_event_form(
+            tmpl_env,
+            session,
+            id.as_deref(),
+            &form,
+            &ValidationErrors::new(),
+            Some(preview),
+        );
+    }
+
+    let event_id = match &id {
+        Some(id) => id.clone(),
+        None => admin::new_event_id().map_err(admin_error)?,
+    };
+
+    let event = match form.validate(&event_id, &session.session.user, Timestamp::now()) {
+        Ok(event) => event,
+        Err(errors) => {
+            let mut res =
+                render_event_form(tmpl_env, session, id.as_deref(), &form, &errors, None)?;
+            *res.status_mut() = StatusCode::BAD_REQUEST;
+            return Ok(res);
+        }
+    };
+
+    store.save(event).await.map_err(admin_error)?;
+    log::info!("user {} saved event {event_id}", session.session.user);
+    sync_admin_change(calendar).await;
+
+    Ok(admin_redirect("/events"))
+}
+
+async fn admin_event_create(
+    tmpl_env: MiniJinjaRenderer,
+    session: AdminSession,
+    store: Data<LocalEventStore>,
+    calendar: Data<Calendar>,
+    calendar_config: Data<CalendarConfig>,
+    form: web::Form<EventForm>,
+) -> Result<HttpResponse> {
+    save_event(
+        &tmpl_env,
+        &session,
+        &store,
+        &calendar,
+        &calendar_config.sanitize,
+        None,
+        form.into_inner(),
+    )
+    .await
+}
+
+async fn admin_event_update(
+    tmpl_env: MiniJinjaRenderer,
+    session: AdminSession,
+    store: Data<LocalEventStore>,
+    calendar: Data<Calendar>,
+    calendar_config: Data<CalendarConfig>,
+    id: web::Path<String>,
+    form: web::Form<EventForm>,
+) -> Result<HttpResponse> {
+    // Don't recreate an event which was deleted in the meantime.
+    if store.get(&id).await.map_err(admin_error)?.is_none() {
+        return Err(ErrorNotFound("not found"));
+    }
+
+    save_event(
+        &tmpl_env,
+        &session,
+        &store,
+        &calendar,
+        &calendar_config.sanitize,
+        Some(id.into_inner()),
+        form.into_inner(),
+    )
+    .await
+}
+
+/// Asks for confirmation before an event is deleted.
+async fn admin_event_delete_form(
+    tmpl_env: MiniJinjaRenderer,
+    session: AdminSession,
+    store: Data<LocalEventStore>,
+    id: web::Path<String>,
+) -> Result<HttpResponse> {
+    let event = store
+        .get(&id)
+        .await
+        .map_err(admin_error)?
+        .ok_or_else(|| ErrorNotFound("not found"))?;
+
+    render_admin(
+        &tmpl_env,
+        Some(&session),
+        "admin/delete.html",
+        minijinja::context! { event => Value::from_object(Event::from(event)) },
+    )
+}
+
+async fn admin_event_delete(
+    session: AdminSession,
+    store: Data<LocalEventStore>,
+    calendar: Data<Calendar>,
+    id: web::Path<String>,
+    form: web::Form<CsrfForm>,
+) -> Result<HttpResponse> {
+    session.check_csrf(&form.csrf_token)?;
+
+    if !store.delete(&id).await.map_err(admin_error)? {
+        return Err(ErrorNotFound("not found"));
+    }
+
+    log::info!("user {} deleted event {id}", session.session.user);
+    sync_admin_change(&calendar).await;
+
+    Ok(admin_redirect("/events"))
+}
+
+async fn admin_auth(
+    req: ServiceRequest,
+    credentials: Option<BearerAuth>,
+) -> Result<ServiceRequest, (actix_web::Error, ServiceRequest)> {
+    let config = <Data<AdminConfig>>::extract(req.request())
+        .into_inner()
+        .unwrap();
+
+    match (&config.token, credentials) {
+        // Without a token the admin endpoints are disabled, so pretend they don't exist.
+        (None, _) => Err((ErrorNotFound("not found"), req)),
+        // Valid token.
+        (Some(token), Some(creds)) if wohnzimmer::secret_eq(token, creds.token()) => Ok(req),
+        // Invalid token.
+        (Some(_), Some(_)) => Err((ErrorUnauthorized("unauthorized"), req)),
+        // Missing token.
+        (Some(_), None) => Err((ErrorBadRequest("missing bearer token"), req)),
+    }
+}
+
+/// Registers the admin endpoints and the admin UI.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/admin/sync")
+            .wrap(HttpAuthentication::with_fn(admin_auth))
+            .post(admin_sync),
+    )
+    .service(
+        web::resource("/admin/health")
+            .wrap(HttpAuthentication::with_fn(admin_auth))
+            .get(admin_health),
+    )
+    .service(
+        web::scope(admin::PATH)
+            .service(web::resource("").get(admin_index))
+            .service(
+                web::resource("/login")
+                    .get(admin_login_form)
+                    .post(admin_login),
+            )
+            .service(web::resource("/logout").post(admin_logout))
+            .service(
+                web::resource("/events")
+                    .get(admin_events)
+                    .post(admin_event_create),
+            )
+            .service(web::resource("/events/new").get(admin_event_new))
+            .service(
+                web::resource("/events/{id}")
+                    .get(admin_event_edit)
+                    .post(admin_event_update),
+            )
+            .service(
+                web::resource("/events/{id}/delete")
+                    .get(admin_event_delete_form)
+                    .post(admin_event_delete),
+            ),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::App;
+    use actix_web::test::{self, TestRequest};
+    use wohnzimmer::calendar::EventSource;
+
+    #[actix_web::test]
+    async fn admin_health() {
+        // A fake `EventSource` which is unavailable.
+        struct Source;
+
+        #[async_trait::async_trait]
+        impl EventSource for Source {
+            async fn fetch_events(&self) -> wohnzimmer::Result<Vec<Event>> {
+                Err(wohnzimmer::Error::Sync("calendar unavailable".into()))
+            }
+        }
+
+        let calendar = Data::new(Calendar::new(Source).unwrap());
+        calendar.sync_once().await.unwrap_err();
+
+        let config = AdminConfig {
+            token: Some("secret".into()),
+            ..Default::default()
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(calendar)
+                .app_data(Data::new(config))
+                .service(
+                    web::resource("/admin/health")
+                        .wrap(HttpAuthentication::with_fn(admin_auth))
+                        .get(super::admin_health),
+                ),
+        )
+        .await;
+
+        let req = TestRequest::get().uri("/admin/health").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        let req = TestRequest::get()
+            .uri("/admin/health")
+            .insert_header((header::AUTHORIZATION, "Bearer secret"))
+            .to_request();
+        let health: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(health["last_success"], serde_json::Value::Null);
+        assert_eq!(
+            health["last_error"],
+            "calendar sync failed: calendar unavailable"
+        );
+    }
+}
@@ -0,0 +1,127 @@
+//! The contact form.
+
+use actix_web::error::ErrorNotFound;
+use actix_web::http::StatusCode;
+use actix_web::http::header::{CacheControl, CacheDirective};
+use actix_web::web::{Data, Html};
+use actix_web::{HttpRequest, Responder, Result, web};
+use jiff::{Timestamp, Zoned};
+use minijinja::value::Value;
+use wohnzimmer::contact::{self, Contact, ContactForm, Verdict};
+use wohnzimmer::meta::PageMeta;
+use wohnzimmer::{ServerConfig, SiteConfig};
+
+use crate::{MiniJinjaRenderer, base_url, client_ip};
+
+/// Returns the contact form handling, or a 404 error if it is disabled.
+fn contact_enabled(contact: Option<Data<Contact>>) -> Result<Data<Contact>> {
+    contact.ok_or_else(|| ErrorNotFound("not found"))
+}
+
+/// Renders the contact page in one of its states, `form` or `sent`.
+fn render_contact(
+    req: &HttpRequest,
+    tmpl_env: &MiniJinjaRenderer,
+    site: &SiteConfig,
+    state: &str,
+    ctx: Value,
+) -> Result<Html> {
+    let meta = PageMeta::new(site, &base_url(req, site), contact::PATH, Some("Kontakt"));
+
+    tmpl_env.render(
+        "kontakt.html",
+        minijinja::context! { request_path => req.uri().path(), meta, state, ..ctx },
+    )
+}
+
+async fn contact_form(
+    req: HttpRequest,
+    tmpl_env: MiniJinjaRenderer,
+    site: Data<SiteConfig>,
+    contact: Option<Data<Contact>>,
+) -> Result<impl Responder> {
+    let contact = contact_enabled(contact)?;
+    let ctx = minijinja::context! {
+        form => ContactForm::default(),
+        token => contact.form_token(Timestamp::now()),
+    };
+
+    Ok(render_contact(&req, &tmpl_env, &site, "form", ctx)?
+        .customize()
+        // The form contains a token with the time it was rendered.
+        .insert_header(CacheControl(vec![CacheDirective::NoStore])))
+}
+
+/// Checks a submitted contact form and delivers it. Spam is dropped without telling the sender.
+async fn contact_submit(
+    req: HttpRequest,
+    tmpl_env: MiniJinjaRenderer,
+    site: Data<SiteConfig>,
+    contact: Option<Data<Contact>>,
+    server_config: Data<ServerConfig>,
+    form: web::Form<ContactForm>,
+) -> Result<impl Responder> {
+    let contact = contact_enabled(contact)?;
+    let form = form.into_inner();
+    let now = Timestamp::now();
+
+    // Shows the form again with the entered values.
+    let retry = |status: StatusCode, ctx: Value| -> Result<_> {
+        let ctx = minijinja::context! { form, token => contact.form_token(now), ..ctx };
+        Ok(render_contact(&req, &tmpl_env, &site, "form", ctx)?
+            .customize()
+            .with_status(status))
+    };
+
+    match contact.check(&form, now) {
+        Verdict::Accepted => {}
+        Verdict::Spam => {
+            log::info!("dropping contact request which looks like spam");
+            return Ok(
+                render_contact(&req, &tmpl_env, &site, "sent", Value::UNDEFINED)?.customize(),
+            );
+        }
+        Verdict::Expired => {
+            let error = "Das Formular ist abgelaufen. Bitte sende es noch einmal ab.";
+            return retry(StatusCode::BAD_REQUEST, minijinja::context! { error });
+        }
+    }
+
+    let request = match form.validate(Zoned::now().date()) {
+        Ok(request) => request,
+        Err(errors) => {
+            let error = "Bitte überprüfe deine Angaben.";
+            return retry(
+                StatusCode::BAD_REQUEST,
+                minijinja::context! { error, errors },
+            );
+        }
+    };
+
+    if !contact.allow(&client_ip(&req, &server_config), now) {
+        let error = "Du hast uns gerade schon mehrere Nachrichten geschickt. Bitte versuche es später noch einmal.";
+        return retry(StatusCode::TOO_MANY_REQUESTS, minijinja::context! { error });
+    }
+
+    if let Err(err) = contact.send(&request).await {
+        log::error!("{err}");
+        let error = "Deine Nachricht konnte leider nicht gesendet werden. Bitte versuche es später noch einmal.";
+        return retry(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            minijinja::context! { error },
+        );
+    }
+
+    log::info!("received contact request: {}", request.kind.label());
+
+    Ok(render_contact(&req, &tmpl_env, &site, "sent", Value::UNDEFINED)?.customize())
+}
+
+/// Registers the contact form.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource(contact::PATH)
+            .get(contact_form)
+            .post(contact_submit),
+    );
+}
@@ -0,0 +1,266 @@
+//! Signup, confirmation and unsubscribe pages of the newsletter.
+
+use actix_web::error::{ErrorInternalServerError, ErrorNotFound};
+use actix_web::http::StatusCode;
+use actix_web::http::header::{CacheControl, CacheDirective};
+use actix_web::web::{self, Data, Html};
+use actix_web::{HttpRequest, Responder, Result};
+use jiff::Timestamp;
+use minijinja::value::Value;
+use serde::Deserialize;
+use wohnzimmer::contact::Verdict;
+use wohnzimmer::meta::PageMeta;
+use wohnzimmer::newsletter::{self, Newsletter};
+use wohnzimmer::{ServerConfig, SiteConfig};
+
+use crate::{MiniJinjaRenderer, base_url, client_ip};
+
+/// Query of the newsletter confirmation and unsubscribe links.
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: String,
+}
+
+/// Newsletter signup form.
+#[derive(Deserialize)]
+struct SignupForm {
+    email: String,
+    /// Signed time at which the form was rendered.
+    #[serde(default)]
+    token: String,
+    /// Honeypot field, which has to stay empty.
+    #[serde(default)]
+    website: String,
+}
+
+/// Returns the newsletter, or a 404 error if it is disabled.
+fn newsletter_enabled(newsletter: Option<Data<Newsletter>>) -> Result<Data<Newsletter>> {
+    newsletter.ok_or_else(|| ErrorNotFound("not found"))
+}
+
+/// Logs a newsletter error and hides the details from the visitor.
+fn newsletter_error(err: wohnzimmer::Error) -> actix_web::Error {
+    log::error!("{err}");
+    ErrorInternalServerError("newsletter error")
+}
+
+/// Renders the newsletter page in one of its states, e.g. `form` or `confirmed`.
+fn render_newsletter(
+    req: &HttpRequest,
+    tmpl_env: &MiniJinjaRenderer,
+    site: &SiteConfig,
+    state: &str,
+    ctx: Value,
+) -> Result<Html> {
+    let meta = PageMeta::new(
+        site,
+        &base_url(req, site),
+        newsletter::PATH,
+        Some("Newsletter"),
+    );
+
+    tmpl_env.render(
+        "newsletter.html",
+        minijinja::context! { request_path => req.uri().path(), meta, state, ..ctx },
+    )
+}
+
+async fn newsletter_form(
+    req: HttpRequest,
+    tmpl_env: MiniJinjaRenderer,
+    site: Data<SiteConfig>,
+    newsletter: Option<Data<Newsletter>>,
+) -> Result<impl Responder> {
+    let newsletter = newsletter_enabled(newsletter)?;
+    let ctx = minijinja::context! { token => newsletter.spam_guard().form_token(Timestamp::now()) };
+
+    Ok(render_newsletter(&req, &tmpl_env, &site, "form", ctx)?
+        .customize()
+        // The form contains a token with the time it was rendered.
+        .insert_header(CacheControl(vec![CacheDirective::NoStore])))
+}
+
+/// Starts a subscription. The response doesn't reveal whether the address is already subscribed.
+/// Spam is dropped without telling the sender.
+async fn newsletter_subscribe(
+    req: HttpRequest,
+    tmpl_env: MiniJinjaRenderer,
+    site: Data<SiteConfig>,
+    newsletter: Option<Data<Newsletter>>,
+    server_config: Data<ServerConfig>,
+    form: web::Form<SignupForm>,
+) -> Result<impl Responder> {
+    let newsletter = newsletter_enabled(newsletter)?;
+    let guard = newsletter.spam_guard();
+    let now = Timestamp::now();
+
+    // Shows the form again with the entered address.
+    let retry = |error: &str, status: StatusCode| -> Result<_> {
+        let ctx = minijinja::context! {
+            email => form.email,
+            token => guard.form_token(now),
+            error,
+        };
+        Ok(render_newsletter(&req, &tmpl_env, &site, "form", ctx)?
+            .customize()
+            .with_status(status))
+    };
+
+    match guard.check(&form.website, &form.token, now) {
+        Verdict::Accepted => {}
+        Verdict::Spam => {
+            log::info!("dropping newsletter signup which looks like spam");
+            let ctx = minijinja::context! { email => form.email };
+            return Ok(render_newsletter(&req, &tmpl_env, &site, "pending", ctx)?.customize());
+        }
+        Verdict::Expired => {
+            return retry(
+                "Das Formular ist abgelaufen. Bitte sende es noch einmal ab.",
+                StatusCode::BAD_REQUEST,
+            );
+        }
+    }
+
+    let Some(email) = newsletter::parse_address(&form.email) else {
+        return retry(
+            "Bitte gib eine gültige E-Mail-Adresse ein.",
+            StatusCode::BAD_REQUEST,
+        );
+    };
+
+    if !guard.allow(&client_ip(&req, &server_config), now) {
+        return retry(
+            "Du hast dich gerade schon mehrmals angemeldet. Bitte versuche es später noch einmal.",
+            StatusCode::TOO_MANY_REQUESTS,
+        );
+    }
+
+    newsletter
+        .subscribe(email.clone())
+        .await
+        .map_err(newsletter_error)?;
+
+    let body = render_newsletter(
+        &req,
+        &tmpl_env,
+        &site,
+        "pending",
+        minijinja::context! { email },
+    )?;
+
+    Ok(body.customize())
+}
+
+/// Asks to confirm a subscription. Confirming needs a POST request, so that link scanners of mail
+/// providers don't confirm subscriptions by following the link.
+async fn newsletter_confirm_page(
+    req: HttpRequest,
+    tmpl_env: MiniJinjaRenderer,
+    site: Data<SiteConfig>,
+    newsletter: Option<Data<Newsletter>>,
+    query: web::Query<TokenQuery>,
+) -> Result<impl Responder> {
+    let newsletter = newsletter_enabled(newsletter)?;
+    let token = query.into_inner().token;
+
+    let state = match newsletter
+        .subscriber(token.clone())
+        .await
+        .map_err(newsletter_error)?
+    {
+        Some(subscriber) if subscriber.confirmed_at.is_some() => "confirmed",
+        Some(_) => "confirm",
+        None => "invalid",
+    };
+
+    render_newsletter(&req, &tmpl_env, &site, state, minijinja::context! { token })
+}
+
+async fn newsletter_confirm(
+    req: HttpRequest,
+    tmpl_env: MiniJinjaRenderer,
+    site: Data<SiteConfig>,
+    newsletter: Option<Data<Newsletter>>,
+    query: web::Query<TokenQuery>,
+) -> Result<impl Responder> {
+    let newsletter = newsletter_enabled(newsletter)?;
+
+    let state = match newsletter
+        .confirm(query.into_inner().token)
+        .await
+        .map_err(newsletter_error)?
+    {
+        true => "confirmed",
+        false => "invalid",
+    };
+
+    render_newsletter(&req, &tmpl_env, &site, state, Value::UNDEFINED)
+}
+
+/// Asks to unsubscribe. Like confirming, unsubscribing needs a POST request.
+async fn newsletter_unsubscribe_page(
+    req: HttpRequest,
+    tmpl_env: MiniJinjaRenderer,
+    site: Data<SiteConfig>,
+    newsletter: Option<Data<Newsletter>>,
+    query: web::Query<TokenQuery>,
+) -> Result<impl Responder> {
+    let newsletter = newsletter_enabled(newsletter)?;
+    let token = query.into_inner().token;
+
+    let state = match newsletter
+        .subscriber(token.clone())
+        .await
+        .map_err(newsletter_error)?
+    {
+        Some(_) => "unsubscribe",
+        None => "invalid",
+    };
+
+    render_newsletter(&req, &tmpl_env, &site, state, minijinja::context! { token })
+}
+
+/// Removes a subscription. This also handles one-click unsubscribe requests of mail clients
+/// (RFC 8058), which post to the URL of the `List-Unsubscribe` header.
+async fn newsletter_unsubscribe(
+    req: HttpRequest,
+    tmpl_env: MiniJinjaRenderer,
+    site: Data<SiteConfig>,
+    newsletter: Option<Data<Newsletter>>,
+    query: web::Query<TokenQuery>,
+) -> Result<impl Responder> {
+    let newsletter = newsletter_enabled(newsletter)?;
+
+    let state = match newsletter
+        .unsubscribe(query.into_inner().token)
+        .await
+        .map_err(newsletter_error)?
+    {
+        true => "unsubscribed",
+        false => "invalid",
+    };
+
+    render_newsletter(&req, &tmpl_env, &site, state, Value::UNDEFINED)
+}
+
+/// Registers the newsletter pages.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope(newsletter::PATH)
+            .service(
+                web::resource("")
+                    .get(newsletter_form)
+                    .post(newsletter_subscribe),
+            )
+            .service(
+                web::resource("/confirm")
+                    .get(newsletter_confirm_page)
+                    .post(newsletter_confirm),
+            )
+            .service(
+                web::resource("/unsubscribe")
+                    .get(newsletter_unsubscribe_page)
+                    .post(newsletter_unsubscribe),
+            ),
+    );
+}
@@ -0,0 +1,119 @@
+//! Push subscriptions for event reminders and the service worker showing them.
+
+use actix_files::NamedFile;
+use actix_web::error::{
+    ErrorBadRequest, ErrorInternalServerError, ErrorNotFound, ErrorTooManyRequests,
+};
+use actix_web::http::header::{CacheControl, CacheDirective};
+use actix_web::web::{self, Data};
+use actix_web::{HttpRequest, HttpResponse, Responder, Result, route};
+use jiff::Timestamp;
+use serde::Deserialize;
+use wohnzimmer::calendar::Calendar;
+use wohnzimmer::reminders::webpush::PushSubscription;
+use wohnzimmer::reminders::{self, Reminders, Topic};
+
+use crate::STATIC_DIR;
+
+/// Request to add or remove a reminder of a push subscription.
+#[derive(Deserialize)]
+struct ReminderRequest {
+    subscription: PushSubscription,
+    topic: Topic,
+}
+
+/// Returns the reminders, or a 404 error if they are disabled.
+fn reminders_enabled(reminders: Option<Data<Reminders>>) -> Result<Data<Reminders>> {
+    reminders.ok_or_else(|| ErrorNotFound("not found"))
+}
+
+/// Logs a reminder error and hides the details from the visitor.
+fn reminder_error(err: wohnzimmer::Error) -> actix_web::Error {
+    log::error!("{err}");
+    ErrorInternalServerError("reminder error")
+}
+
+/// Subscribes a browser to reminders of an upcoming event or of all events with a tag.
+async fn reminders_subscribe(
+    calendar: Data<Calendar>,
+    reminders: Option<Data<Reminders>>,
+    body: web::Json<ReminderRequest>,
+) -> Result<impl Responder> {
+    let reminders = reminders_enabled(reminders)?;
+    let ReminderRequest {
+        subscription,
+        topic,
+    } = body.into_inner();
+
+    if !reminders.is_allowed_endpoint(&subscription.endpoint) {
+        return Err(ErrorBadRequest("unsupported push service"));
+    }
+
+    // Reminders of a single event are removed once it started.
+    let expires_at = match &topic {
+        Topic::Event(id) => {
+            let event = calendar
+                .get_event(id)
+                .await
+                .filter(|event| event.start_date > Timestamp::now())
+                .ok_or_else(|| ErrorNotFound("not found"))?;
+            Some(event.start_date)
+        }
+        Topic::Tag(tag) if !Reminders::is_valid_tag(tag) => {
+            return Err(ErrorBadRequest("invalid tag"));
+        }
+        Topic::Tag(_) => None,
+    };
+
+    if !reminders
+        .subscribe(subscription, topic, expires_at)
+        .await
+        .map_err(reminder_error)?
+    {
+        return Err(ErrorTooManyRequests("too many reminders"));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Removes a reminder of a browser.
+async fn reminders_unsubscribe(
+    reminders: Option<Data<Reminders>>,
+    body: web::Json<ReminderRequest>,
+) -> Result<impl Responder> {
+    let reminders = reminders_enabled(reminders)?;
+    let ReminderRequest {
+        subscription,
+        topic,
+    } = body.into_inner();
+
+    reminders
+        .unsubscribe(subscription.endpoint, topic)
+        .await
+        .map_err(reminder_error)?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Serves the service worker showing reminders. It is served from the root instead of the static
+/// files, because its scope is limited to the path it is served from.
+#[route("/sw.js", method = "GET", method = "HEAD")]
+async fn service_worker(req: HttpRequest) -> Result<HttpResponse> {
+    let file = NamedFile::open_async(format!("{STATIC_DIR}/js/sw.js")).await?;
+
+    // Browsers check for updates of service workers anyway, but shouldn't use a stale copy.
+    Ok(file
+        .customize()
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        .respond_to(&req)
+        .map_into_boxed_body())
+}
+
+/// Registers the reminder subscriptions and the service worker.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(service_worker).service(
+        web::resource(reminders::PATH)
+            .post(reminders_subscribe)
+            .delete(reminders_unsubscribe),
+    );
+}
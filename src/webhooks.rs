@@ -0,0 +1,596 @@
+//! Outgoing webhooks which are notified about event changes.
+//!
+//! After every sync which changed events, a JSON payload is queued for each configured endpoint.
+//! Deliveries are stored as files in the queue directory until the endpoint accepted them, so
+//! they survive restarts. Failed deliveries are retried with exponential backoff.
+//!
+//! Payloads are signed like GitHub webhooks: the `X-Wohnzimmer-Signature-256` header contains
+//! `sha256=` followed by the hex encoded HMAC-SHA256 of the request body.
+
+use crate::calendar::Event;
+use crate::calendar::diff::{EventDiff, EventUpdate};
+use crate::metrics::{DeliveryStatus, WebhookMetrics};
+use crate::{Error, Result, WebhooksConfig};
+use hmac::{Hmac, Mac};
+use jiff::Timestamp;
+use prometheus::Registry;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::oneshot::{self, Receiver, Sender};
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
+
+/// Header containing the signature of the request body.
+pub const SIGNATURE_HEADER: &str = "X-Wohnzimmer-Signature-256";
+/// Header containing the ID of the payload.
+pub const DELIVERY_HEADER: &str = "X-Wohnzimmer-Delivery";
+
+/// Delay between checks of the queue while no delivery is pending.
+const IDLE_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// The JSON payload sent to endpoints.
+#[derive(Serialize)]
+struct Payload<'a> {
+    /// Identifies the changes. Instances running side by side during a deploy send the same ID
+    /// for the same changes, so receivers can ignore duplicates.
+    id: String,
+    /// The time when the changes were detected.
+    created_at: Timestamp,
+    added: &'a [Event],
+    removed: &'a [Event],
+    updated: &'a [EventUpdate],
+}
+
+impl Payload<'_> {
+    fn new(diff: &EventDiff, created_at: Timestamp) -> Result<Payload<'_>> {
+        let changes = serde_json::to_vec(&(&diff.added, &diff.removed, &diff.updated))
+            .map_err(|err| Error::Webhook(format!("failed to serialize payload: {err}")))?;
+
+        Ok(Payload {
+            id: hex::encode(&Sha256::digest(changes)[..16]),
+            created_at,
+            added: &diff.added,
+            removed: &diff.removed,
+            updated: &diff.updated,
+        })
+    }
+}
+
+/// Returns the value of the signature header for a request `body`.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Returns the delay before the attempt following `attempts` failed ones.
+fn backoff(config: &WebhooksConfig, attempts: u32) -> Duration {
+    let factor = 2u64.saturating_pow(attempts.saturating_sub(1));
+
+    Duration::from_secs(
+        config
+            .initial_backoff_seconds
+            .saturating_mul(factor)
+            .min(config.max_backoff_seconds),
+    )
+}
+
+/// A pending delivery of a payload to an endpoint.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Delivery {
+    /// The ID of the payload.
+    id: String,
+    /// The URL of the endpoint.
+    url: String,
+    /// The serialized payload.
+    body: String,
+    /// The number of failed attempts.
+    attempts: u32,
+    /// The earliest time of the next attempt.
+    next_attempt: Timestamp,
+}
+
+/// Pending deliveries, stored as one JSON file per delivery.
+struct Queue {
+    dir: PathBuf,
+    deliveries: HashMap<PathBuf, Delivery>,
+}
+
+impl Queue {
+    /// Loads the deliveries stored in `dir`. Files which can't be parsed are ignored.
+    fn open(dir: &Path) -> Result<Queue> {
+        std::fs::create_dir_all(dir)?;
+
+        let mut deliveries = HashMap::new();
+
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+
+            // Skips leftovers of interrupted writes.
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+
+            match serde_json::from_slice(&std::fs::read(&path)?) {
+                Ok(delivery) => {
+                    deliveries.insert(path, delivery);
+                }
+                Err(err) => log::warn!("ignoring invalid webhook delivery {path:?}: {err}"),
+            }
+        }
+
+        Ok(Queue {
+            dir: dir.to_path_buf(),
+            deliveries,
+        })
+    }
+
+    fn len(&self) -> usize {
+        self.deliveries.len()
+    }
+
+    /// Adds a new delivery.
+    fn push(&mut self, delivery: Delivery) -> Result<()> {
+        let mut name = [0; 8];
+        getrandom::fill(&mut name).expect("failed to generate delivery file name");
+
+        let path = self.dir.join(format!("{}.json", hex::encode(name)));
+        self.save(path, delivery)
+    }
+
+    /// Writes a delivery to `path`, replacing the file atomically.
+    fn save(&mut self, path: PathBuf, delivery: Delivery) -> Result<()> {
+        let data = serde_json::to_vec(&delivery)
+            .map_err(|err| Error::Webhook(format!("failed to serialize delivery: {err}")))?;
+
+        let tmp_path = path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, data)?;
+        std::fs::rename(&tmp_path, &path)?;
+
+        self.deliveries.insert(path, delivery);
+        Ok(())
+    }
+
+    fn remove(&mut self, path: &Path) -> Result<()> {
+        self.deliveries.remove(path);
+
+        match std::fs::remove_file(path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the time of the earliest pending attempt.
+    fn next_attempt(&self) -> Option<Timestamp> {
+        self.deliveries
+            .values()
+            .map(|delivery| delivery.next_attempt)
+            .min()
+    }
+
+    /// Returns the deliveries which are due at `now`, oldest first.
+    fn due(&self, now: Timestamp) -> Vec<(PathBuf, Delivery)> {
+        let mut due: Vec<_> = self
+            .deliveries
+            .iter()
+            .filter(|(_, delivery)| delivery.next_attempt <= now)
+            .map(|(path, delivery)| (path.clone(), delivery.clone()))
+            .collect();
+
+        due.sort_by_key(|(_, delivery)| delivery.next_attempt);
+        due
+    }
+}
+
+/// Delivers event changes to the configured webhook endpoints.
+pub struct WebhookDispatcher {
+    config: WebhooksConfig,
+    client: reqwest::Client,
+    queue: Queue,
+    metrics: WebhookMetrics,
+}
+
+impl WebhookDispatcher {
+    /// Creates a new `WebhookDispatcher`, loading pending deliveries from the queue directory.
+    pub fn new(config: WebhooksConfig) -> Result<WebhookDispatcher> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_seconds))
+            .build()?;
+        let queue = Queue::open(&config.queue_dir)?;
+        let metrics = WebhookMetrics::new()?;
+
+        if queue.len() > 0 {
+            log::info!("loaded {} pending webhook deliveries", queue.len());
+        }
+
+        metrics.queued_deliveries().set(queue.len() as i64);
+
+        Ok(WebhookDispatcher {
+            config,
+            client,
+            queue,
+            metrics,
+        })
+    }
+
+    /// Registers the webhook metrics in a prometheus registry.
+    pub fn register_metrics(&self, registry: &Registry) -> Result<()> {
+        self.metrics.register(registry)
+    }
+
+    /// Queues a delivery of `diff` to every endpoint.
+    fn enqueue(&mut self, diff: &EventDiff) -> Result<()> {
+        let now = Timestamp::now();
+        let payload = Payload::new(diff, now)?;
+        let body = serde_json::to_string(&payload)
+            .map_err(|err| Error::Webhook(format!("failed to serialize payload: {err}")))?;
+
+        for endpoint in &self.config.endpoints {
+            self.queue.push(Delivery {
+                id: payload.id.clone(),
+                url: endpoint.url.clone(),
+                body: body.clone(),
+                attempts: 0,
+                next_attempt: now,
+            })?;
+        }
+
+        self.metrics
+            .queued_deliveries()
+            .set(self.queue.len() as i64);
+        Ok(())
+    }
+
+    /// Attempts all deliveries which are due.
+    async fn deliver_due(&mut self) {
+        for (path, delivery) in self.queue.due(Timestamp::now()) {
+            match self.attempt(path, delivery).await {
+                Ok(status) => self.metrics.deliveries_total(status).inc(),
+                Err(err) => log::error!("failed to update webhook queue: {err}"),
+            }
+        }
+
+        self.metrics
+            .queued_deliveries()
+            .set(self.queue.len() as i64);
+    }
+
+    /// Attempts a single delivery and updates the queue accordingly.
+    async fn attempt(&mut self, path: PathBuf, delivery: Delivery) -> Result<DeliveryStatus> {
+        let Some(secret) = self.config.secret(&delivery.url) else {
+            log::warn!(
+                "dropping webhook delivery {} to {}, which is not configured anymore",
+                delivery.id,
+                delivery.url
+            );
+            self.queue.remove(&path)?;
+            return Ok(DeliveryStatus::Dropped);
+        };
+
+        let start = Timestamp::now();
+        let result = self.send(&delivery, secret).await;
+        let end = Timestamp::now();
+
+        self.metrics
+            .delivery_duration_seconds()
+            .observe(end.duration_since(start).as_secs_f64());
+
+        let Err(err) = result else {
+            log::info!("delivered webhook {} to {}", delivery.id, delivery.url);
+            self.queue.remove(&path)?;
+            return Ok(DeliveryStatus::Success);
+        };
+
+        let attempts = delivery.attempts + 1;
+
+        if attempts >= self.config.max_attempts {
+            log::error!(
+                "giving up webhook delivery {} to {} after {attempts} attempts: {err}",
+                delivery.id,
+                delivery.url
+            );
+            self.queue.remove(&path)?;
+            return Ok(DeliveryStatus::Dropped);
+        }
+
+        let delay = backoff(&self.config, attempts);
+        log::warn!(
+            "failed to deliver webhook {} to {}, retrying in {delay:?}: {err}",
+            delivery.id,
+            delivery.url
+        );
+
+        self.queue.save(
+            path,
+            Delivery {
+                attempts,
+                next_attempt: end + delay,
+                ..delivery
+            },
+        )?;
+
+        Ok(DeliveryStatus::Failure)
+    }
+
+    async fn send(&self, delivery: &Delivery, secret: &str) -> Result<()> {
+        self.client
+            .post(&delivery.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(DELIVERY_HEADER, &delivery.id)
+            .header(SIGNATURE_HEADER, sign(secret, delivery.body.as_bytes()))
+            .body(delivery.body.clone())
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+
+    /// Starts a background task which queues the changes received via `changes` and delivers
+    /// them. The changes of the initial sync are not delivered, since they just reflect a
+    /// restart. Returns a `WebhookTaskHandle` to stop the task.
+    pub fn spawn(self, changes: broadcast::Receiver<Arc<EventDiff>>) -> WebhookTaskHandle {
+        let (stop_tx, stop_rx) = oneshot::channel();
+
+        let join_handle = tokio::spawn(async move {
+            self.run(changes, stop_rx).await;
+        });
+
+        WebhookTaskHandle {
+            join_handle,
+            stop_tx,
+        }
+    }
+
+    async fn run(
+        mut self,
+        mut changes: broadcast::Receiver<Arc<EventDiff>>,
+        mut stop: Receiver<()>,
+    ) {
+        log::info!(
+            "delivering event changes to {} webhooks",
+            self.config.endpoints.len()
+        );
+
+        loop {
+            let delay = match self.queue.next_attempt() {
+                Some(next_attempt) => {
+                    Duration::try_from(next_attempt.duration_since(Timestamp::now()))
+                        .unwrap_or_default()
+                }
+                None => IDLE_DELAY,
+            };
+
+            let deliver = tokio::select! {
+                result = changes.recv() => {
+                    match result {
+                        Ok(diff) if diff.initial => {}
+                        Ok(diff) => {
+                            if let Err(err) = self.enqueue(&diff) {
+                                log::error!("failed to queue webhook deliveries: {err}");
+                            }
+                        }
+                        Err(RecvError::Lagged(skipped)) => {
+                            log::warn!("webhooks missed {skipped} calendar changes");
+                        }
+                        Err(RecvError::Closed) => break,
+                    }
+
+                    false
+                }
+                _ = tokio::time::sleep(delay) => true,
+                _ = &mut stop => break,
+            };
+
+            // An attempt which is interrupted by the stop signal is repeated after a restart.
+            if deliver {
+                tokio::select! {
+                    _ = self.deliver_due() => {}
+                    _ = &mut stop => break,
+                }
+            }
+        }
+
+        log::info!("stopping webhook deliveries, {} pending", self.queue.len());
+    }
+}
+
+/// A handle for stopping a `WebhookDispatcher` task.
+pub struct WebhookTaskHandle {
+    join_handle: JoinHandle<()>,
+    stop_tx: Sender<()>,
+}
+
+impl WebhookTaskHandle {
+    /// Stops the dispatcher task. Blocks until the background task is finished.
+    pub async fn stop(self) -> io::Result<()> {
+        if self.stop_tx.send(()).is_ok() {
+            self.join_handle.await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WebhookEndpoint;
+    use actix_web::{App, HttpRequest, HttpResponse, HttpServer, web};
+    use std::sync::Mutex;
+
+    fn diff() -> EventDiff {
+        EventDiff {
+            added: vec![Event {
+                id: "abc".into(),
+                title: "Konzert".into(),
+                start_date: "2025-03-05T18:00:00Z".parse().unwrap(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn signature() {
+        // Test case 2 from RFC 4231.
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn payload_id() {
+        let diff = diff();
+        let first = Payload::new(&diff, "2025-03-01T12:00:00Z".parse().unwrap()).unwrap();
+        let second = Payload::new(&diff, "2025-03-01T12:05:00Z".parse().unwrap()).unwrap();
+        assert_eq!(first.id, second.id);
+
+        let other = EventDiff {
+            removed: diff.added.clone(),
+            ..Default::default()
+        };
+        assert_ne!(
+            Payload::new(&other, second.created_at).unwrap().id,
+            first.id
+        );
+    }
+
+    #[test]
+    fn backoff_delays() {
+        let config = WebhooksConfig::default();
+
+        assert_eq!(backoff(&config, 1), Duration::from_secs(30));
+        assert_eq!(backoff(&config, 2), Duration::from_secs(60));
+        assert_eq!(backoff(&config, 8), Duration::from_secs(3600));
+        assert_eq!(backoff(&config, u32::MAX), Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn queue() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let now: Timestamp = "2025-03-01T12:00:00Z".parse().unwrap();
+        let delivery = |id: &str, next_attempt: Timestamp| Delivery {
+            id: id.into(),
+            url: "https://example.com/hook".into(),
+            body: "{}".into(),
+            attempts: 0,
+            next_attempt,
+        };
+
+        let mut queue = Queue::open(dir).unwrap();
+        queue.push(delivery("b", now)).unwrap();
+        queue
+            .push(delivery("a", now - jiff::SignedDuration::from_mins(1)))
+            .unwrap();
+        queue
+            .push(delivery("c", now + jiff::SignedDuration::from_mins(1)))
+            .unwrap();
+        std::fs::write(dir.join("broken.json"), "{").unwrap();
+
+        // Deliveries survive a restart.
+        let mut queue = Queue::open(dir).unwrap();
+        assert_eq!(queue.len(), 3);
+        assert_eq!(
+            queue.next_attempt(),
+            Some(now - jiff::SignedDuration::from_mins(1))
+        );
+
+        let due = queue.due(now);
+        assert_eq!(
+            due.iter()
+                .map(|(_, delivery)| delivery.id.as_str())
+                .collect::<Vec<_>>(),
+            ["a", "b"]
+        );
+
+        queue.remove(&due[0].0).unwrap();
+        assert_eq!(Queue::open(dir).unwrap().len(), 2);
+    }
+
+    #[actix_rt::test]
+    async fn deliveries() {
+        // Records received requests and fails the first one.
+        let received: Arc<Mutex<Vec<(String, String)>>> = Default::default();
+        let server_received = received.clone();
+
+        let server = HttpServer::new(move || {
+            let received = server_received.clone();
+
+            App::new().route(
+                "/hook",
+                web::post().to(move |req: HttpRequest, body: String| {
+                    let received = received.clone();
+
+                    async move {
+                        let signature = req
+                            .headers()
+                            .get(SIGNATURE_HEADER)
+                            .unwrap()
+                            .to_str()
+                            .unwrap();
+                        let mut received = received.lock().unwrap();
+                        received.push((signature.to_string(), body));
+
+                        if received.len() == 1 {
+                            HttpResponse::InternalServerError().finish()
+                        } else {
+                            HttpResponse::Ok().finish()
+                        }
+                    }
+                }),
+            )
+        })
+        .workers(1)
+        .bind("127.0.0.1:0")
+        .unwrap();
+        let url = format!("http://{}/hook", server.addrs()[0]);
+        let server = server.run();
+        let server_handle = server.handle();
+        actix_rt::spawn(server);
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let config = WebhooksConfig {
+            endpoints: vec![WebhookEndpoint {
+                url,
+                secret: Some("secret".into()),
+            }],
+            queue_dir: dir.to_path_buf(),
+            initial_backoff_seconds: 0,
+            ..Default::default()
+        };
+
+        let mut dispatcher = WebhookDispatcher::new(config).unwrap();
+        dispatcher.enqueue(&diff()).unwrap();
+
+        dispatcher.deliver_due().await;
+        assert_eq!(dispatcher.queue.len(), 1);
+        let (_, delivery) = &dispatcher.queue.due(Timestamp::now())[0];
+        assert_eq!(delivery.attempts, 1);
+
+        dispatcher.deliver_due().await;
+        assert_eq!(dispatcher.queue.len(), 0);
+
+        server_handle.stop(false).await;
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+
+        let (signature, body) = &received[1];
+        assert_eq!(*signature, sign("secret", body.as_bytes()));
+
+        let payload: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(payload["added"][0]["id"], "abc");
+        assert_eq!(payload["removed"], serde_json::json!([]));
+    }
+}
@@ -0,0 +1,52 @@
+// Click-to-load for media embeds in event descriptions.
+//
+// Embed placeholders are rendered server-side without any third party resources. Only when a
+// visitor clicks on one, the placeholder is replaced by the provider's iframe.
+(function () {
+  "use strict";
+
+  var providers = {
+    youtube: {
+      pattern: /^[A-Za-z0-9_-]+$/,
+      src: function (id) {
+        return "https://www.youtube-nocookie.com/embed/" + id + "?autoplay=1";
+      },
+      height: 270,
+    },
+    spotify: {
+      pattern: /^(track|album|playlist|episode|show|artist)\/[A-Za-z0-9]+$/,
+      src: function (id) {
+        return "https://open.spotify.com/embed/" + id;
+      },
+      height: 352,
+    },
+  };
+
+  document.addEventListener("click", function (event) {
+    var placeholder = event.target.closest(".embed[data-embed-provider]");
+    if (!placeholder) {
+      return;
+    }
+
+    var provider = providers[placeholder.dataset.embedProvider];
+    var id = placeholder.dataset.embedId;
+    if (!provider || !provider.pattern.test(id)) {
+      // Fall back to following the link.
+      return;
+    }
+
+    event.preventDefault();
+
+    var iframe = document.createElement("iframe");
+    iframe.src = provider.src(id);
+    iframe.width = "100%";
+    iframe.height = provider.height;
+    iframe.allow = "autoplay; encrypted-media; picture-in-picture";
+    iframe.setAttribute("allowfullscreen", "");
+    iframe.setAttribute("frameborder", "0");
+    iframe.setAttribute("loading", "lazy");
+
+    placeholder.replaceChildren(iframe);
+    placeholder.classList.add("embed-loaded");
+  });
+})();
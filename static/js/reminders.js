@@ -0,0 +1,137 @@
+// Opt-in reminders of events via Web Push.
+//
+// Buttons inside the `.reminders` block subscribe the browser to push notifications for an event
+// (`data-reminder-event`) or for all events with a tag (`data-reminder-tag`). The block stays
+// hidden in browsers without push support. Which reminders are active is remembered in
+// localStorage, so that the buttons can show it and remove them again.
+(function () {
+  "use strict";
+
+  var STORAGE_KEY = "reminders";
+
+  var container = document.querySelector(".reminders[data-vapid-key]");
+  if (
+    !container ||
+    !("serviceWorker" in navigator) ||
+    !("PushManager" in window) ||
+    !("Notification" in window)
+  ) {
+    return;
+  }
+
+  function load() {
+    try {
+      return JSON.parse(localStorage.getItem(STORAGE_KEY)) || {};
+    } catch (e) {
+      return {};
+    }
+  }
+
+  function save(active) {
+    localStorage.setItem(STORAGE_KEY, JSON.stringify(active));
+  }
+
+  function topicOf(button) {
+    if (button.dataset.reminderEvent) {
+      return { event: button.dataset.reminderEvent };
+    }
+    return { tag: button.dataset.reminderTag };
+  }
+
+  function keyOf(topic) {
+    return topic.event ? "event:" + topic.event : "tag:" + topic.tag;
+  }
+
+  // Converts the base64url encoded VAPID key for `PushManager.subscribe()`.
+  function decodeKey(value) {
+    var base64 = (value + "===".slice((value.length + 3) % 4))
+      .replace(/-/g, "+")
+      .replace(/_/g, "/");
+    return Uint8Array.from(atob(base64), function (c) {
+      return c.charCodeAt(0);
+    });
+  }
+
+  // Returns the push subscription of the browser, creating it if `create` is set.
+  function subscription(create) {
+    return navigator.serviceWorker
+      .register("/sw.js")
+      .then(function () {
+        return navigator.serviceWorker.ready;
+      })
+      .then(function (registration) {
+        return registration.pushManager.getSubscription().then(function (existing) {
+          if (existing || !create) {
+            return existing;
+          }
+          return registration.pushManager.subscribe({
+            userVisibleOnly: true,
+            applicationServerKey: decodeKey(container.dataset.vapidKey),
+          });
+        });
+      });
+  }
+
+  function update(button) {
+    var active = load()[keyOf(topicOf(button))];
+    button.textContent = active ? button.dataset.labelActive : button.dataset.label;
+    button.setAttribute("aria-pressed", active ? "true" : "false");
+  }
+
+  var buttons = container.querySelectorAll("button[data-label]");
+  buttons.forEach(update);
+  container.hidden = false;
+
+  container.addEventListener("click", function (event) {
+    var button = event.target.closest("button[data-label]");
+    if (!button) {
+      return;
+    }
+
+    var topic = topicOf(button);
+    var key = keyOf(topic);
+    var enable = !load()[key];
+    button.disabled = true;
+
+    // Some browsers only ask for permission directly in response to a click.
+    var permission = enable ? Notification.requestPermission() : Promise.resolve("granted");
+
+    permission
+      .then(function (result) {
+        if (result !== "granted") {
+          throw new Error("notifications are not permitted");
+        }
+        return subscription(enable);
+      })
+      .then(function (sub) {
+        if (!sub) {
+          return;
+        }
+        return fetch("/reminders", {
+          method: enable ? "POST" : "DELETE",
+          headers: { "Content-Type": "application/json" },
+          body: JSON.stringify({ subscription: sub.toJSON(), topic: topic }),
+        }).then(function (response) {
+          if (!response.ok) {
+            throw new Error("server responded with " + response.status);
+          }
+        });
+      })
+      .then(function () {
+        var active = load();
+        if (enable) {
+          active[key] = true;
+        } else {
+          delete active[key];
+        }
+        save(active);
+      })
+      .catch(function (err) {
+        console.error("failed to update reminder", err);
+      })
+      .finally(function () {
+        button.disabled = false;
+        update(button);
+      });
+  });
+})();
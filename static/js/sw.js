@@ -0,0 +1,27 @@
+// Service worker showing event reminders sent via Web Push.
+//
+// The server sends a JSON payload with the `title`, `body` and `url` of the notification, see
+// `src/reminders.rs`. Clicking on the notification opens the event page.
+"use strict";
+
+self.addEventListener("push", function (event) {
+  var data = event.data ? event.data.json() : {};
+
+  event.waitUntil(
+    self.registration.showNotification(data.title || "Erinnerung", {
+      body: data.body,
+      tag: data.tag,
+      icon: "/static/images/android-chrome-192x192.png",
+      data: { url: data.url },
+    }),
+  );
+});
+
+self.addEventListener("notificationclick", function (event) {
+  event.notification.close();
+
+  var url = event.notification.data && event.notification.data.url;
+  if (url) {
+    event.waitUntil(self.clients.openWindow(url));
+  }
+});